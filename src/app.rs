@@ -1,135 +1,350 @@
 use std::{
-    io::{self, Stdout},
+    collections::HashMap,
+    future::Future,
+    io::{self, Stdout, Write},
+    panic,
+    sync::Once,
     time::Duration,
 };
 
 use crate::{
     chat_room::ChatRoom,
-    models::network::NetworkMessage,
+    config::{Config, Rule},
+    console::Console,
+    keymap::{Action, Keymap},
+    logger::LogBuffer,
+    models::{
+        message::{now_millis, ChatMessage, DeliveryStatus, Edit, Reaction},
+        network::NetworkEvent,
+        presence::{Presence, PresenceState},
+        search::{SearchHit, SearchQuery},
+        status::NodeStatus,
+    },
+    node::{ChatNode, EventStream, NodeError},
+    notify,
+    search::Search,
+    status_bar::StatusBar,
+    supervisor::{Supervisor, TaskResult},
+    theme::THEMES,
     topic_list::*,
-    ui::{draw_chat_room, draw_topic_list},
+    ui,
 };
 use crossterm::{
-    event::{DisableMouseCapture, EnableMouseCapture},
+    cursor::Show,
+    event::{
+        DisableMouseCapture, EnableMouseCapture, Event, EventStream as TerminalEvents,
+        MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
+use futures::StreamExt;
+use log::{error, warn};
 use tokio::{
+    select,
     sync::mpsc::{Receiver, Sender},
-    time::Instant,
-};
-use tui::{
-    backend::CrosstermBackend,
-    style::{Color, Style},
-    Terminal,
+    task::JoinSet,
+    time::{interval, Instant},
 };
+use tui::{backend::CrosstermBackend, Terminal};
+
+pub use crate::theme::AppStyle;
 
 /// the current active page
 #[derive(Debug, Clone)]
-pub enum Page<'a> {
+pub enum Page {
     /// the main page where you can see all rooms
-    TopicList(TopicList<'a>),
+    TopicList(TopicList),
     /// the page where you can send messages
-    ChatRoom(ChatRoom),
-}
-
-pub struct AppStyle {
-    pub base_style: Style,
-    pub selected_style: Style,
-    pub normal_style: Style,
-}
-
-impl AppStyle {
-    pub fn new() -> Self {
-        AppStyle {
-            base_style: Style::default().fg(Color::Gray),
-            selected_style: Style::default().bg(Color::LightBlue).fg(Color::DarkGray),
-            normal_style: Style::default().fg(Color::LightBlue),
-        }
-    }
+    ChatRoom(Box<ChatRoom>),
+    /// the page where you search the messages of every room
+    Search(Box<Search>),
 }
 
 #[derive(Debug, Clone)]
-pub enum AppMessage<'a> {
+pub enum AppMessage {
     Quit,
-    ChangePage { page: Page<'a> },
-    MessageReceived { message: String },
+    ChangePage {
+        page: Page,
+    },
+    /// a message from a peer, sent from `Network`
+    MessageReceived {
+        room: String,
+        message: ChatMessage,
+        /// sent just now, see `NetworkEvent::MessageReceived`
+        live: bool,
+    },
+    ReactionReceived {
+        room: String,
+        reaction: Reaction,
+    },
+    EditReceived {
+        room: String,
+        edit: Edit,
+    },
+    /// what happened to a message we sent
+    Delivery {
+        room: String,
+        message_id: String,
+        status: DeliveryStatus,
+    },
+    /// `peer_id` has seen `message_ids`
+    Receipt {
+        room: String,
+        peer_id: String,
+        message_ids: Vec<String>,
+    },
+    /// a peer came online, or changed its presence
+    PresenceReceived {
+        presence: Presence,
+    },
+    /// we haven't heard from a peer for a while
+    PresenceExpired {
+        peer_id: String,
+    },
+    /// change our presence
+    SetPresence {
+        state: PresenceState,
+        status: String,
+    },
+    /// `author` is typing in `room`
+    Typing {
+        room: String,
+        author: String,
+    },
+    /// switch to the theme `name`, or list the themes if it is empty
+    SetTheme {
+        name: String,
+    },
+    /// search the stored messages
+    Search {
+        query: SearchQuery,
+    },
+    SearchResults {
+        hits: Vec<SearchHit>,
+    },
+    /// join `room` and open it, with its stored messages
+    OpenRoom {
+        room: String,
+    },
+    /// open `room` at the message `message_id`
+    OpenMessage {
+        room: String,
+        message_id: String,
+    },
+    /// the status of the node, when the app starts
+    NodeStatus {
+        status: NodeStatus,
+    },
+    /// our addresses, peers, meshes or NAT status changed
+    StatusChanged {
+        event: NetworkEvent,
+    },
+    /// something went wrong, show it to the user
+    Error {
+        error: String,
+    },
 }
 
+/// How long a notification stays on the screen.
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(5);
+
+/// How long without a key press until we are away.
+const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// How often the screen is redrawn when nothing happens,
+/// so notifications expire.
+const TICK_RATE: Duration = Duration::from_millis(250);
+
 // actor
-pub struct App<'a> {
+pub struct App {
     pub style: AppStyle,
-    pub page: Page<'a>,
+    pub keymap: Keymap,
+    /// the keys are shown over the page
+    pub help: bool,
+    pub status_bar: StatusBar,
+    /// the log, under the page
+    pub console: Console,
+    pub page: Page,
     pub should_close: bool,
     pub terminal: Terminal<CrosstermBackend<Stdout>>,
-    pub rx: Receiver<AppMessage<'a>>,
-    pub tx: Sender<AppMessage<'a>>,
-    pub tx_network: Sender<NetworkMessage>,
+    pub rx: Receiver<AppMessage>,
+    pub tx: Sender<AppMessage>,
+    pub node: ChatNode,
+    /// the last error, and when it happened
+    pub notification: Option<(String, Instant)>,
+    /// the presence of the peers, by peer id
+    pub presence: HashMap<String, Presence>,
+    /// our presence, and the status text
+    pub presence_state: PresenceState,
+    pub status: String,
+    /// we were set away for being idle, not by the user
+    pub auto_away: bool,
+    /// when a key was last pressed
+    pub last_input: Instant,
+    pub config: Config,
+    /// when the app started, in milliseconds since the unix epoch,
+    /// older messages come from the history and don't notify
+    pub started: u64,
+    /// the requests to the node and the commands that run in the background
+    pub tasks: JoinSet<(&'static str, TaskResult)>,
 }
 
 // handle
-pub struct AppHandle<'a> {
-    pub tx: Sender<AppMessage<'a>>,
+pub struct AppHandle {
+    pub tx: Sender<AppMessage>,
 }
 
-impl<'a> App<'a> {
+impl App {
     pub fn new(
-        rx: Receiver<AppMessage<'a>>,
-        tx: Sender<AppMessage<'a>>,
-        tx_network: Sender<NetworkMessage>,
-    ) -> Result<App<'a>, std::io::Error> {
-        let style = AppStyle::new();
+        rx: Receiver<AppMessage>,
+        tx: Sender<AppMessage>,
+        node: ChatNode,
+        config: Config,
+        log: LogBuffer,
+    ) -> Result<App, std::io::Error> {
+        // the config is checked when it is loaded
+        let style = config
+            .theme
+            .as_deref()
+            .and_then(|name| config.style(name))
+            .unwrap_or_default();
         let topic_list = TopicList::new();
         let page = Page::TopicList(topic_list);
 
         // setup terminal
+        install_panic_hook();
         enable_raw_mode()?;
         let mut stdout = io::stdout();
-        execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
+        execute!(stdout, EnterAlternateScreen)?;
+        // without it, the terminal selects text with the mouse
+        if config.mouse {
+            execute!(stdout, EnableMouseCapture)?;
+        }
 
         let backend = CrosstermBackend::new(stdout);
         let terminal = Terminal::new(backend)?;
 
         Ok(App {
             style,
+            keymap: Keymap::new(&config.keys),
+            help: false,
+            status_bar: StatusBar::new(node.peer_id().to_string(), config.nickname.clone()),
+            console: Console::new(log),
             page,
             should_close: false,
             terminal,
             rx,
             tx,
-            tx_network,
+            node,
+            notification: None,
+            presence: HashMap::new(),
+            presence_state: PresenceState::Online,
+            status: String::new(),
+            auto_away: false,
+            last_input: Instant::now(),
+            config,
+            started: now_millis(),
+            tasks: JoinSet::new(),
         })
     }
 
-    pub async fn run(
-        &mut self,
-        // tx: Sender<AppMessage<'a>>,
-        // tx_network: Sender<GlobalEvent>,
-    ) -> Result<(), std::io::Error> {
-        let tick_rate = Duration::from_millis(250);
-        let mut last_tick = Instant::now();
+    pub async fn run(&mut self) -> Result<(), std::io::Error> {
+        let mut input = TerminalEvents::new();
+        let mut tick = interval(TICK_RATE);
 
-        loop {
-            let timeout = tick_rate
-                .checked_sub(last_tick.elapsed())
-                .unwrap_or_else(|| Duration::from_secs(0));
+        // the events only tell what changes from now on
+        let (node, tx) = (self.node.clone(), self.tx.clone());
+        self.spawn("status", async move {
+            let status = node.status().await?;
+            let _ = tx.send(AppMessage::NodeStatus { status }).await;
+            Ok(())
+        });
 
-            match &mut self.page {
-                Page::TopicList(page) => {
-                    draw_topic_list(page, &self.tx, &self.tx_network, tick_rate).await;
-                }
-                Page::ChatRoom(page) => {
-                    draw_chat_room(page, &self.tx, &self.tx_network, timeout).await
+        loop {
+            if let Some((_, at)) = &self.notification {
+                if at.elapsed() >= NOTIFICATION_DURATION {
+                    self.notification = None;
                 }
+            }
+            let panes = ui::Panes {
+                status_bar: &self.status_bar,
+                console: &mut self.console,
+                notification: self.notification.as_ref().map(|(n, _)| n.as_str()),
+                help: self.help,
             };
 
-            // try_recv is non-blocking
-            if let Ok(msg) = self.rx.try_recv() {
-                self.handle_message(msg).await;
-            }
+            self.terminal
+                .draw(|f| ui::draw(f, &mut self.page, &self.style, &self.keymap, panes))?;
 
-            if last_tick.elapsed() >= tick_rate {
-                last_tick = Instant::now();
+            select! {
+                _ = tick.tick() => {
+                    let idle = self.last_input.elapsed() >= AWAY_AFTER;
+                    if idle && self.presence_state == PresenceState::Online {
+                        self.auto_away = true;
+                        self.set_presence(PresenceState::Away, self.status.clone());
+                    }
+                }
+                event = input.next() => match event {
+                    Some(Ok(Event::Key(k))) => {
+                        self.last_input = Instant::now();
+                        if self.auto_away {
+                            self.set_presence(PresenceState::Online, self.status.clone());
+                        }
+                        let typing = if self.console.open {
+                            self.console.editing
+                        } else {
+                            ui::is_typing(&self.page)
+                        };
+                        let action = self.keymap.action(k);
+                        if self.help {
+                            // any key closes the help
+                            self.help = false;
+                        } else if action == Some(Action::Help) && !typing {
+                            self.help = true;
+                        } else if action == Some(Action::Console) && !typing && !self.console.open {
+                            self.console.open = true;
+                        } else {
+                            ui::handle_key(
+                                k,
+                                &mut self.page,
+                                &mut self.console,
+                                &self.keymap,
+                                &self.tx,
+                                &self.node,
+                            )
+                            .await;
+                        }
+                    }
+                    Some(Ok(Event::Mouse(m))) if m.kind != MouseEventKind::Moved => {
+                        self.last_input = Instant::now();
+                        if self.auto_away {
+                            self.set_presence(PresenceState::Online, self.status.clone());
+                        }
+                        if self.help {
+                            // a click closes the help too
+                            self.help = !matches!(m.kind, MouseEventKind::Down(_));
+                        } else {
+                            ui::handle_mouse(m, &mut self.page, &mut self.console, &self.tx, &self.node)
+                                .await;
+                        }
+                    }
+                    // drawn again at the new size, from a clean screen
+                    Some(Ok(Event::Resize(..))) => self.terminal.autoresize()?,
+                    Some(Ok(_)) => {}
+                    Some(Err(e)) => return Err(e),
+                    // the terminal is gone
+                    None => return Ok(()),
+                },
+                msg = self.rx.recv() => match msg {
+                    Some(msg) => self.handle_message(msg),
+                    None => return Ok(()),
+                },
+                Some(joined) = self.tasks.join_next() => match joined {
+                    Ok((_, Ok(()))) => {}
+                    Ok((name, Err(e))) => warn!("the {name} task failed: {e}"),
+                    Err(e) => error!("a background task failed: {e}"),
+                },
             }
 
             if self.should_close {
@@ -138,47 +353,375 @@ impl<'a> App<'a> {
         }
     }
 
-    async fn handle_message(&mut self, msg: AppMessage<'a>) {
+    /// Run `future` in the background, its failure is logged.
+    fn spawn<F>(&mut self, name: &'static str, future: F)
+    where
+        F: Future<Output = TaskResult> + Send + 'static,
+    {
+        self.tasks.spawn(async move { (name, future.await) });
+    }
+
+    /// Change our presence, and tell the peers.
+    fn set_presence(&mut self, state: PresenceState, status: String) {
+        if state != PresenceState::Away {
+            self.auto_away = false;
+        }
+        self.presence_state = state;
+        self.status = status.clone();
+        let node = self.node.clone();
+        self.spawn("presence", async move {
+            Ok(node.set_presence(state, &status).await?)
+        });
+    }
+
+    /// Tell the user about `message`, if the rule of `room` says so.
+    fn notify(&mut self, room: &str, message: &ChatMessage) {
+        let me = self.node.peer_id().to_string();
+        if self.presence_state == PresenceState::DoNotDisturb
+            || message.author == me
+            || message.timestamp < self.started
+        {
+            return;
+        }
+        let notify = match self.config.rule(room) {
+            Rule::All => true,
+            Rule::Mentions => notify::is_mention(&message.body, &self.mention_names()),
+            Rule::None => false,
+        };
+        if !notify {
+            return;
+        }
+
+        let author = ui::short_name(&message.author);
+        let title = format!("{author} in {room}");
+        let escape = notify::escape(self.config.notifications.alert, &title, &message.body);
+        let backend = self.terminal.backend_mut();
+        let _ = backend
+            .write_all(escape.as_bytes())
+            .and_then(|_| backend.flush());
+        if let Some(command) = &self.config.notifications.command {
+            let run = notify::run_command(command, room, &message.author, &message.body);
+            self.spawn("notify command", run);
+        }
+    }
+
+    /// The words that mention us, the end of our
+    /// `PeerId`, our nickname and the keywords.
+    fn mention_names(&self) -> Vec<String> {
+        let me = self.node.peer_id().to_string();
+        let mut names = vec![ui::short_name(&me).to_string()];
+        names.extend(self.config.nickname.clone());
+        names.extend(self.config.keywords.iter().cloned());
+        names
+    }
+
+    /// Switch to the theme `name`, from the config or built in.
+    fn set_theme(&mut self, name: &str) {
+        let mut themes: Vec<&str> = THEMES.to_vec();
+        themes.extend(self.config.themes.keys().map(String::as_str));
+        themes.sort_unstable();
+        themes.dedup();
+        let error = match self.config.style(name) {
+            Some(style) => {
+                self.style = style;
+                return;
+            }
+            None if name.is_empty() => format!("themes: {}", themes.join(", ")),
+            None => format!("unknown theme {name}, themes: {}", themes.join(", ")),
+        };
+        self.handle_message(AppMessage::Error { error });
+    }
+
+    fn handle_message(&mut self, msg: AppMessage) {
         match msg {
-            AppMessage::Quit => {
-                disable_raw_mode().unwrap();
-                execute!(
-                    self.terminal.backend_mut(),
-                    LeaveAlternateScreen,
-                    DisableMouseCapture
-                )
-                .unwrap();
-                self.terminal.show_cursor().unwrap();
-                self.should_close = true;
-                // send message to `Network`
-                let _ = self.tx_network.send(NetworkMessage::Quit).await;
-            }
-            AppMessage::ChangePage { page } => {
+            // the terminal is restored and the
+            // `Network` stopped once `run` returns
+            AppMessage::Quit => self.should_close = true,
+            AppMessage::ChangePage { mut page } => {
+                // the new page knows nothing of the peers yet
+                match &mut page {
+                    Page::TopicList(page) => page.presence = self.presence.clone(),
+                    Page::ChatRoom(page) => {
+                        page.presence = self.presence.clone();
+                        page.mentions = self.mention_names();
+                        page.time = self.config.time.clone();
+                    }
+                    Page::Search(_) => {}
+                }
                 self.page = page;
             }
-            // This message is sent from `Network`
-            AppMessage::MessageReceived { message } => {
+            AppMessage::PresenceReceived { presence } => {
+                match &mut self.page {
+                    Page::TopicList(page) => page.push_presence(presence.clone()),
+                    Page::ChatRoom(page) => page.push_presence(presence.clone()),
+                    Page::Search(_) => {}
+                }
+                self.presence.insert(presence.author.clone(), presence);
+            }
+            AppMessage::PresenceExpired { peer_id } => {
+                match &mut self.page {
+                    Page::TopicList(page) => page.remove_presence(&peer_id),
+                    Page::ChatRoom(page) => page.remove_presence(&peer_id),
+                    Page::Search(_) => {}
+                }
+                self.presence.remove(&peer_id);
+            }
+            AppMessage::SetPresence { state, status } => self.set_presence(state, status),
+            AppMessage::SetTheme { name } => self.set_theme(&name),
+            AppMessage::MessageReceived {
+                room,
+                message,
+                live,
+            } => {
+                self.notify(&room, &message);
+                if let Page::ChatRoom(page) = &mut self.page {
+                    if page.name == room {
+                        let id = message.id.clone();
+                        if live {
+                            page.push_live_message(message, now_millis());
+                        } else {
+                            page.push_message(message);
+                        }
+                        // it is on the screen, so we have seen it
+                        let node = self.node.clone();
+                        self.spawn("read receipt", async move {
+                            Ok(node.mark_seen(&room, vec![id]).await?)
+                        });
+                    }
+                }
+            }
+            AppMessage::Delivery {
+                room,
+                message_id,
+                status,
+            } => {
+                if let Page::ChatRoom(page) = &mut self.page {
+                    if page.name == room {
+                        page.push_delivery(message_id, status);
+                    }
+                }
+            }
+            AppMessage::Receipt {
+                room,
+                peer_id,
+                message_ids,
+            } => {
+                if let Page::ChatRoom(page) = &mut self.page {
+                    if page.name == room {
+                        page.push_receipt(peer_id, message_ids);
+                    }
+                }
+            }
+            AppMessage::ReactionReceived { room, reaction } => {
                 if let Page::ChatRoom(page) = &mut self.page {
-                    page.items.push(message);
+                    if page.name == room {
+                        page.push_reaction(reaction);
+                    }
+                }
+            }
+            AppMessage::EditReceived { room, edit } => {
+                if let Page::ChatRoom(page) = &mut self.page {
+                    if page.name == room {
+                        page.push_edit(edit);
+                    }
+                }
+            }
+            AppMessage::Typing { room, author } => {
+                if let Page::ChatRoom(page) = &mut self.page {
+                    if page.name == room {
+                        page.push_typing(author, Instant::now());
+                    }
+                }
+            }
+            AppMessage::Search { query } => {
+                let (node, tx) = (self.node.clone(), self.tx.clone());
+                self.spawn("search", async move {
+                    let msg = match node.search(query).await {
+                        Ok(hits) => AppMessage::SearchResults { hits },
+                        Err(e) => AppMessage::Error {
+                            error: e.to_string(),
+                        },
+                    };
+                    let _ = tx.send(msg).await;
+                    Ok(())
+                });
+            }
+            AppMessage::SearchResults { hits } => {
+                if let Page::Search(page) = &mut self.page {
+                    page.push_hits(hits);
                 }
             }
+            AppMessage::OpenRoom { room } => {
+                let (node, tx) = (self.node.clone(), self.tx.clone());
+                self.spawn("open room", async move {
+                    let msg = match ChatRoom::open(&node, room).await {
+                        Ok(page) => AppMessage::ChangePage {
+                            page: Page::ChatRoom(Box::new(page)),
+                        },
+                        Err(e) => AppMessage::Error {
+                            error: e.to_string(),
+                        },
+                    };
+                    let _ = tx.send(msg).await;
+                    Ok(())
+                });
+            }
+            AppMessage::OpenMessage { room, message_id } => {
+                let (node, tx) = (self.node.clone(), self.tx.clone());
+                self.spawn("open message", async move {
+                    let msg = match open_message(&node, room, &message_id).await {
+                        Ok(page) => AppMessage::ChangePage { page },
+                        Err(e) => AppMessage::Error {
+                            error: e.to_string(),
+                        },
+                    };
+                    let _ = tx.send(msg).await;
+                    Ok(())
+                });
+            }
+            AppMessage::NodeStatus { status } => self.status_bar.set_status(status),
+            AppMessage::StatusChanged { event } => {
+                self.status_bar.apply(&event);
+            }
+            // shown even when we do not want to be disturbed,
+            // it is feedback, not a notification
+            AppMessage::Error { error } => self.notification = Some((error, Instant::now())),
         }
     }
 }
 
-impl<'a> AppHandle<'a>
-where
-    'a: 'static,
-{
+impl AppHandle {
+    /// Start the app under `supervisor`, as the `ui` task and
+    /// the `events` task that forwards it the events of `node`.
     pub fn new(
-        tx: Sender<AppMessage<'a>>,
-        rx: Receiver<AppMessage<'a>>,
-        tx_network: Sender<NetworkMessage>,
-    ) -> Self {
-        let actor = App::new(rx, tx.clone(), tx_network);
+        tx: Sender<AppMessage>,
+        rx: Receiver<AppMessage>,
+        node: ChatNode,
+        config: Config,
+        log: LogBuffer,
+        supervisor: &mut Supervisor,
+    ) -> Result<Self, std::io::Error> {
+        let mut actor = App::new(rx, tx.clone(), node.clone(), config, log)?;
+        let events = node.events();
 
-        tokio::spawn(async move { actor.unwrap().run().await });
+        supervisor.spawn("ui", async move {
+            let result = actor.run().await;
+            drop(actor);
+            restore_terminal()?;
+            // stop the `Network`
+            let _ = node.quit().await;
+            Ok(result?)
+        });
+        supervisor.spawn("events", forward_events(events, tx.clone()));
 
-        Self { tx }
+        Ok(Self { tx })
+    }
+}
+
+/// Join `room` and open it at the message `message_id`,
+/// with the history of the room loaded.
+async fn open_message(node: &ChatNode, room: String, message_id: &str) -> Result<Page, NodeError> {
+    let mut page = ChatRoom::open(node, room).await?;
+    page.select_message(message_id);
+    Ok(Page::ChatRoom(Box::new(page)))
+}
+
+/// Send the events of the node that the app
+/// cares about, until the app stops.
+async fn forward_events(mut events: EventStream, tx: Sender<AppMessage>) -> TaskResult {
+    loop {
+        let event = select! {
+            event = events.recv() => event,
+            _ = tx.closed() => return Ok(()),
+        };
+        let msg = match event {
+            Some(NetworkEvent::MessageReceived {
+                topic,
+                message,
+                live,
+            }) => AppMessage::MessageReceived {
+                room: topic,
+                message,
+                live,
+            },
+            Some(NetworkEvent::ReactionReceived { topic, reaction }) => {
+                AppMessage::ReactionReceived {
+                    room: topic,
+                    reaction,
+                }
+            }
+            Some(NetworkEvent::EditReceived { topic, edit }) => {
+                AppMessage::EditReceived { room: topic, edit }
+            }
+            Some(NetworkEvent::Delivery {
+                topic,
+                message_id,
+                status,
+            }) => AppMessage::Delivery {
+                room: topic,
+                message_id,
+                status,
+            },
+            Some(NetworkEvent::ReceiptReceived {
+                topic,
+                peer_id,
+                message_ids,
+            }) => AppMessage::Receipt {
+                room: topic,
+                peer_id,
+                message_ids,
+            },
+            Some(NetworkEvent::PresenceReceived { presence }) => {
+                AppMessage::PresenceReceived { presence }
+            }
+            Some(NetworkEvent::PresenceExpired { peer_id }) => {
+                AppMessage::PresenceExpired { peer_id }
+            }
+            Some(NetworkEvent::Typing { topic, peer_id }) => AppMessage::Typing {
+                room: topic,
+                author: peer_id,
+            },
+            Some(NetworkEvent::Error { error }) => AppMessage::Error {
+                error: error.to_string(),
+            },
+            Some(
+                event @ (NetworkEvent::Listening { .. }
+                | NetworkEvent::ListenAddressExpired { .. }
+                | NetworkEvent::PeerConnected { .. }
+                | NetworkEvent::PeerDisconnected { .. }
+                | NetworkEvent::MeshChanged { .. }
+                | NetworkEvent::NatChanged { .. }),
+            ) => AppMessage::StatusChanged { event },
+            Some(_) => continue,
+            None => return Ok(()),
+        };
+        if tx.send(msg).await.is_err() {
+            return Ok(());
+        }
     }
 }
+
+/// Leave raw mode and the alternate screen,
+/// it is safe to call more than once.
+pub fn restore_terminal() -> io::Result<()> {
+    disable_raw_mode()?;
+    execute!(
+        io::stdout(),
+        LeaveAlternateScreen,
+        DisableMouseCapture,
+        Show
+    )
+}
+
+/// Restore the terminal before the panic message is printed,
+/// otherwise it is lost in the alternate screen.
+fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            let _ = restore_terminal();
+            hook(info);
+        }));
+    });
+}
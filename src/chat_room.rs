@@ -1,10 +1,13 @@
-use crossterm::event::KeyCode;
-use libp2p::gossipsub::IdentTopic;
-use log::info;
-use tokio::sync::mpsc::Sender;
+use crate::node::{ChatNode, NodeError};
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use std::{
+    collections::{HashMap, HashSet},
+    time::Duration,
+};
+use tokio::{sync::mpsc::Sender, time::Instant};
 use tui::{
     backend::Backend,
-    layout::{Constraint, Direction, Layout},
+    layout::{Constraint, Direction, Layout, Rect},
     style::Modifier,
     text::{Span, Spans, Text},
     widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
@@ -13,136 +16,770 @@ use tui::{
 
 use crate::{
     app::{AppMessage, AppStyle, Page},
+    clock::TimeConfig,
+    keymap::{is_chord, Action, Keymap},
+    models::{
+        edit::{Edits, Version},
+        history::RoomHistory,
+        message::{now_millis, ChatMessage, DeliveryStatus, Edit, Reaction},
+        presence::{Presence, PresenceState},
+        reaction::Reactions,
+    },
+    notify::is_mention,
+    search::Search,
     topic_list::TopicList,
-    NetworkMessage,
+    ui::{contains, draw_input, input_height, presence_indicator, short_name, wrap},
 };
 
+/// How often we tell the room that we are typing, at most.
+const TYPING_INTERVAL: Duration = Duration::from_secs(2);
+
+/// How long someone is shown as typing after their last signal.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The emojis of the reaction picker.
+pub const REACTIONS: [&str; 6] = ["👍", "😂", "🎉", "😮", "😢", "🚀"];
+
 #[derive(Clone, Debug)]
 pub enum InputMode {
     Normal,
     Insert,
+    /// picking a reaction for the selected message,
+    /// the index of the highlighted emoji in `REACTIONS`
+    React(usize),
 }
 
 #[derive(Clone, Debug)]
 pub struct ChatRoom {
+    /// the selected message, or `None` to follow the latest
     pub state: ListState,
-    pub items: Vec<String>,
+    pub messages: Vec<ChatMessage>,
+    pub reactions: Reactions,
+    pub edits: Edits,
+    /// the message being edited in insert mode
+    pub editing: Option<String>,
+    /// the message being replied to in insert mode
+    pub replying: Option<String>,
+    /// only show this message and its replies
+    pub thread: Option<String>,
+    /// the peers that are typing, and when we last heard of it
+    pub typing: HashMap<String, Instant>,
+    /// when we last told the room that we are typing
+    pub typing_sent: Option<Instant>,
+    /// what happened to the messages we sent
+    pub delivery: HashMap<String, DeliveryStatus>,
+    /// the peers that have seen each message
+    pub seen_by: HashMap<String, HashSet<String>>,
+    /// the presence of the peers, by peer id
+    pub presence: HashMap<String, Presence>,
+    /// show the versions of the selected message
+    pub show_versions: bool,
     pub input_mode: InputMode,
     pub input: String,
     pub name: String,
-}
-
-impl Default for ChatRoom {
-    fn default() -> Self {
-        let mut state = ListState::default();
-        state.select(Some(0));
-
-        let items = vec!["Esta mensagem ja estava aqui antes".to_string()];
-
-        Self {
-            name: "0".to_string(),
-            state,
-            items,
-            input: String::new(),
-            input_mode: InputMode::Normal,
-        }
-    }
+    /// our own `PeerId`
+    pub me: String,
+    /// the words that mention us, their messages are highlighted
+    pub mentions: Vec<String>,
+    /// how the time of the messages is shown
+    pub time: TimeConfig,
+    /// when the live messages reached us, to spot the clocks that are off
+    pub received: HashMap<String, u64>,
+    /// where the help line, the messages and the input were last drawn
+    pub help_area: Rect,
+    pub messages_area: Rect,
+    pub input_area: Rect,
 }
 
 impl ChatRoom {
-    pub fn new(name: String) -> Self {
-        let mut state = ListState::default();
-        state.select(Some(0));
-
-        let items = vec![];
+    /// Join `room` and show it, with its stored messages.
+    pub async fn open(node: &ChatNode, room: String) -> Result<Self, NodeError> {
+        node.join(&room).await?;
+        let history = node.history(&room).await?;
+        let mut page = Self::new(room, node.peer_id().to_string());
+        page.load(history);
+        Ok(page)
+    }
 
+    pub fn new(name: String, me: String) -> Self {
         Self {
+            mentions: vec![short_name(&me).to_string()],
             name,
-            state,
-            items,
+            me,
+            state: ListState::default(),
+            messages: vec![],
+            reactions: Reactions::default(),
+            edits: Edits::default(),
+            editing: None,
+            replying: None,
+            thread: None,
+            typing: HashMap::new(),
+            typing_sent: None,
+            delivery: HashMap::new(),
+            seen_by: HashMap::new(),
+            presence: HashMap::new(),
+            show_versions: false,
             input: String::new(),
             input_mode: InputMode::Normal,
+            time: TimeConfig::default(),
+            received: HashMap::new(),
+            help_area: Rect::default(),
+            messages_area: Rect::default(),
+            input_area: Rect::default(),
         }
     }
 
-    pub async fn keybindings<'a>(
+    pub async fn keybindings(
         &mut self,
-        k: KeyCode,
-        tx: &Sender<AppMessage<'a>>,
-        tx_network: &Sender<NetworkMessage>,
+        k: KeyEvent,
+        keymap: &Keymap,
+        tx: &Sender<AppMessage>,
+        node: &ChatNode,
     ) {
         match &self.input_mode {
-            InputMode::Normal => match k {
-                KeyCode::Char('i') => self.input_mode = InputMode::Insert,
-                KeyCode::Char('q') | KeyCode::Esc => {
-                    tx.send(AppMessage::ChangePage {
-                        page: Page::TopicList(TopicList::new()),
-                    })
-                    .await
-                    .unwrap();
+            InputMode::Normal => match keymap.action(k) {
+                Some(Action::Insert) => self.input_mode = InputMode::Insert,
+                Some(Action::Down) => self.next(),
+                Some(Action::Up) => self.previous(),
+                // back to following the latest message
+                Some(Action::Latest) => self.state.select(None),
+                Some(Action::React) if self.selected().is_some() => {
+                    self.input_mode = InputMode::React(0);
+                }
+                Some(Action::Edit) => {
+                    let Some(message) = self.selected_own() else {
+                        return;
+                    };
+                    let (Version::Original(body) | Version::Edited(body)) =
+                        self.edits.current(message)
+                    else {
+                        return;
+                    };
+                    let (body, id) = (body.to_string(), message.id.clone());
+                    self.input = body;
+                    self.editing = Some(id);
+                    self.replying = None;
+                    self.input_mode = InputMode::Insert;
+                }
+                Some(Action::Delete) => {
+                    let Some(message) = self.selected_own() else {
+                        return;
+                    };
+                    let id = message.id.clone();
+                    let result = node.delete(&self.name, &id).await;
+                    self.edited(result, tx).await;
+                }
+                Some(Action::Reply) => {
+                    let Some(message) = self.selected() else {
+                        return;
+                    };
+                    self.replying = Some(message.id.clone());
+                    self.editing = None;
+                    self.input_mode = InputMode::Insert;
+                }
+                Some(Action::Thread) => {
+                    let Some(message) = self.selected() else {
+                        return;
+                    };
+                    self.thread = Some(message.id.clone());
+                    self.state.select(Some(0));
+                }
+                Some(Action::Parent) => self.select_parent(tx).await,
+                Some(Action::Resend) => {
+                    let Some(message) = self.selected_own() else {
+                        return;
+                    };
+                    if !matches!(
+                        self.delivery.get(&message.id),
+                        Some(DeliveryStatus::Failed { .. })
+                    ) {
+                        return;
+                    }
+                    let message = message.clone();
+                    let result = node.resend(&self.name, message).await;
+                    self.sent(result, tx).await;
+                }
+                Some(Action::Versions) => self.show_versions = !self.show_versions,
+                Some(Action::Search) => {
+                    let search = Search::new(self.me.clone(), format!("room:{} ", self.name));
+                    let page = Page::Search(Box::new(search));
+                    let _ = tx.send(AppMessage::ChangePage { page }).await;
+                }
+                // back to the whole room
+                Some(Action::Back) if self.thread.is_some() => {
+                    self.thread = None;
+                    self.state.select(None);
+                }
+                Some(Action::Back) => {
+                    let _ = tx
+                        .send(AppMessage::ChangePage {
+                            page: Page::TopicList(TopicList::new()),
+                        })
+                        .await;
                 }
                 _ => {}
             },
-            InputMode::Insert => match k {
-                KeyCode::Enter => {
-                    let topic = IdentTopic::new(self.name.clone());
-                    if tx_network
-                        .send(NetworkMessage::MessageReceived(topic, self.input.clone()))
-                        .await
-                        .is_ok()
-                    {
-                        info!("keycode:enter msg here");
-                        self.items.push(self.input.drain(..).collect());
+            InputMode::Insert => match k.code {
+                _ if keymap.while_typing(k, Action::Open, KeyCode::Enter)
+                    && self.editing.is_some() =>
+                {
+                    let id = self.editing.take().unwrap_or_default();
+                    let body: String = self.input.drain(..).collect();
+                    self.input_mode = InputMode::Normal;
+                    let result = node.edit(&self.name, &id, &body).await;
+                    self.edited(result, tx).await;
+                }
+                _ if keymap.while_typing(k, Action::Open, KeyCode::Enter) => {
+                    if let Some(query) = search_command(&self.input) {
+                        let mut search = Search::new(self.me.clone(), query.to_string());
+                        search.editing = false;
+                        let msg = match search.query() {
+                            Ok(query) => AppMessage::Search { query },
+                            Err(e) => AppMessage::Error {
+                                error: e.to_string(),
+                            },
+                        };
+                        self.input.clear();
+                        self.input_mode = InputMode::Normal;
+                        let page = Page::Search(Box::new(search));
+                        let _ = tx.send(AppMessage::ChangePage { page }).await;
+                        let _ = tx.send(msg).await;
+                        return;
+                    }
+                    if let Some(name) = theme_command(&self.input) {
+                        let name = name.to_string();
+                        self.input.clear();
+                        let _ = tx.send(AppMessage::SetTheme { name }).await;
+                        return;
+                    }
+                    if let Some((state, status)) = command(&self.input) {
+                        self.input.clear();
+                        let _ = tx.send(AppMessage::SetPresence { state, status }).await;
+                        return;
                     }
+                    let result = match &self.replying {
+                        Some(parent) => node.reply(&self.name, parent, &self.input).await,
+                        None => node.send(&self.name, &self.input).await,
+                    };
+                    self.sent(result, tx).await;
                 }
-                KeyCode::Char(c) => {
+                KeyCode::Char(c) if !is_chord(k) => {
                     self.input.push(c);
+                    self.send_typing(node).await;
                 }
                 KeyCode::Backspace => {
                     self.input.pop();
+                    self.send_typing(node).await;
                 }
-                KeyCode::Esc => {
-                    self.input_mode = InputMode::Normal;
+                _ if keymap.while_typing(k, Action::Back, KeyCode::Esc) => self.leave_insert_mode(),
+                _ => {}
+            },
+            InputMode::React(i) => {
+                let i = *i;
+                // the digits pick a reaction in every keymap
+                if let KeyCode::Char(c @ '1'..='9') = k.code {
+                    let i = c as usize - '1' as usize;
+                    if i < REACTIONS.len() {
+                        self.toggle_reaction(REACTIONS[i], tx, node).await;
+                    }
+                    return;
+                }
+                match keymap.action(k) {
+                    Some(Action::Right) => {
+                        self.input_mode = InputMode::React((i + 1) % REACTIONS.len());
+                    }
+                    Some(Action::Left) => {
+                        let i = i.checked_sub(1).unwrap_or(REACTIONS.len() - 1);
+                        self.input_mode = InputMode::React(i);
+                    }
+                    Some(Action::Open) => self.toggle_reaction(REACTIONS[i], tx, node).await,
+                    Some(Action::Back) => self.input_mode = InputMode::Normal,
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// The wheel moves through the messages, a click on the input writes
+    /// a message, and one on an emoji of the picker reacts with it.
+    pub async fn mouse(&mut self, m: MouseEvent, tx: &Sender<AppMessage>, node: &ChatNode) {
+        let (column, row) = (m.column, m.row);
+        match m.kind {
+            MouseEventKind::ScrollUp => self.previous(),
+            MouseEventKind::ScrollDown => match self.state.selected() {
+                // past the last message, follow the latest again
+                Some(i) if i + 1 >= self.visible().len() => self.state.select(None),
+                Some(_) => self.next(),
+                None => {}
+            },
+            MouseEventKind::Down(MouseButton::Left) => match self.input_mode {
+                InputMode::Insert if contains(self.messages_area, column, row) => {
+                    self.leave_insert_mode()
+                }
+                InputMode::Normal | InputMode::React(_)
+                    if contains(self.input_area, column, row) =>
+                {
+                    self.input_mode = InputMode::Insert
+                }
+                InputMode::React(_) if contains(self.help_area, column, row) => {
+                    if let Some(emoji) = self.picker_emoji(column) {
+                        self.toggle_reaction(emoji, tx, node).await;
+                    }
                 }
                 _ => {}
             },
+            _ => {}
+        }
+    }
+
+    /// The emoji of the picker drawn at `column` of the help line.
+    fn picker_emoji(&self, column: u16) -> Option<&'static str> {
+        // as drawn, after "React: " and with a space after each
+        let mut x = self.help_area.x as usize + "React: ".len();
+        for (i, emoji) in REACTIONS.iter().enumerate() {
+            let width = Span::raw(format!("{} {emoji}", i + 1)).width();
+            if (x..x + width).contains(&(column as usize)) {
+                return Some(emoji);
+            }
+            x += width + 1;
+        }
+        None
+    }
+
+    /// Back to normal mode, the edit or the reply is cancelled.
+    fn leave_insert_mode(&mut self) {
+        if self.editing.take().is_some() {
+            self.input.clear();
+        }
+        self.replying = None;
+        self.input_mode = InputMode::Normal;
+    }
+
+    async fn sent(&mut self, result: Result<ChatMessage, NodeError>, tx: &Sender<AppMessage>) {
+        match result {
+            Ok(message) => {
+                self.delivery
+                    .insert(message.id.clone(), DeliveryStatus::Pending);
+                // it is already shown if this is a retry
+                if self.message(&message.id).is_none() {
+                    self.input.clear();
+                    self.replying = None;
+                    self.typing_sent = None;
+                    self.messages.push(message);
+                }
+            }
+            Err(e) => {
+                let _ = tx
+                    .send(AppMessage::Error {
+                        error: e.to_string(),
+                    })
+                    .await;
+            }
+        }
+    }
+
+    /// Tell the room that we are typing, if there is
+    /// something in the input and we didn't just do it.
+    async fn send_typing(&mut self, node: &ChatNode) {
+        if self.input.is_empty() {
+            return;
+        }
+        let now = Instant::now();
+        if self
+            .typing_sent
+            .is_some_and(|sent| now.duration_since(sent) < TYPING_INTERVAL)
+        {
+            return;
+        }
+        self.typing_sent = Some(now);
+        // it is only a hint, the node reports if it is not running
+        let _ = node.typing(&self.name).await;
+    }
+
+    /// React to the selected message with `emoji`, or take
+    /// the reaction back if we already reacted with it.
+    async fn toggle_reaction(&mut self, emoji: &str, tx: &Sender<AppMessage>, node: &ChatNode) {
+        self.input_mode = InputMode::Normal;
+        let Some(message) = self.selected() else {
+            return;
+        };
+        let id = message.id.clone();
+        let added = !self.reactions.has_reacted(&id, &self.me, emoji);
+
+        match node.react(&self.name, &id, emoji, added).await {
+            Ok(reaction) => {
+                self.reactions.apply(reaction);
+            }
+            Err(e) => {
+                let _ = tx
+                    .send(AppMessage::Error {
+                        error: e.to_string(),
+                    })
+                    .await;
+            }
+        }
+    }
+
+    async fn edited(&mut self, result: Result<Edit, NodeError>, tx: &Sender<AppMessage>) {
+        match result {
+            Ok(edit) => {
+                self.edits.apply(edit);
+            }
+            Err(e) => {
+                let _ = tx
+                    .send(AppMessage::Error {
+                        error: e.to_string(),
+                    })
+                    .await;
+            }
+        }
+    }
+
+    /// The selected message, if we wrote it.
+    fn selected_own(&self) -> Option<&ChatMessage> {
+        self.selected().filter(|m| m.author == self.me)
+    }
+
+    /// Show an edit from a peer.
+    pub fn push_edit(&mut self, edit: Edit) {
+        self.edits.apply(edit);
+    }
+
+    /// Show the stored messages of the room, before
+    /// the ones that arrived since it was opened.
+    pub fn load(&mut self, history: RoomHistory) {
+        let mut messages: Vec<ChatMessage> = history
+            .messages
+            .into_iter()
+            .filter(|m| self.message(&m.id).is_none())
+            .collect();
+        messages.append(&mut self.messages);
+        self.messages = messages;
+        for reaction in history.reactions {
+            self.reactions.apply(reaction);
+        }
+        for edit in history.edits {
+            self.edits.apply(edit);
+        }
+        // a status that arrived since is newer
+        for id in history.queued {
+            self.delivery.entry(id).or_insert(DeliveryStatus::Queued);
+        }
+    }
+
+    /// Select the message `id`, out of the thread if
+    /// needed. Returns `false` if it is not loaded.
+    pub fn select_message(&mut self, id: &str) -> bool {
+        let Some(i) = self.messages.iter().position(|m| m.id == id) else {
+            return false;
+        };
+        self.thread = None;
+        self.state.select(Some(i));
+        true
+    }
+
+    /// Show a message from a peer.
+    pub fn push_message(&mut self, message: ChatMessage) {
+        // they are done typing it
+        self.typing.remove(&message.author);
+        self.messages.push(message);
+    }
+
+    /// Show a message that was sent just now, and reached us at `received`.
+    pub fn push_live_message(&mut self, message: ChatMessage, received: u64) {
+        self.received.insert(message.id.clone(), received);
+        self.push_message(message);
+    }
+
+    /// Show what happened to a message we sent.
+    pub fn push_delivery(&mut self, message_id: String, status: DeliveryStatus) {
+        self.delivery.insert(message_id, status);
+    }
+
+    /// Show that `peer_id` has seen `message_ids`.
+    pub fn push_receipt(&mut self, peer_id: String, message_ids: Vec<String>) {
+        for id in message_ids {
+            self.seen_by.entry(id).or_default().insert(peer_id.clone());
         }
     }
 
-    pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, ui: &AppStyle) {
+    /// The status shown after a message we sent.
+    fn delivery_status(&self, message: &ChatMessage, keymap: &Keymap) -> Option<String> {
+        let status = self.delivery.get(&message.id)?;
+        let seen = self.seen_by.get(&message.id).map_or(0, HashSet::len);
+        let status = match status {
+            DeliveryStatus::Failed { .. } => {
+                format!("failed, press {} to retry", keymap.label(Action::Resend))
+            }
+            _ if seen > 0 => format!("seen by {seen}"),
+            DeliveryStatus::Pending => "sending…".to_string(),
+            DeliveryStatus::Queued => "queued".to_string(),
+            DeliveryStatus::Published => "sent".to_string(),
+        };
+        Some(status)
+    }
+
+    /// Show the presence of a peer.
+    pub fn push_presence(&mut self, presence: Presence) {
+        self.presence.insert(presence.author.clone(), presence);
+    }
+
+    /// Forget the presence of a peer that went quiet.
+    pub fn remove_presence(&mut self, peer_id: &str) {
+        self.presence.remove(peer_id);
+    }
+
+    /// Show that `author` was typing at `at`.
+    pub fn push_typing(&mut self, author: String, at: Instant) {
+        if author != self.me {
+            self.typing.insert(author, at);
+        }
+    }
+
+    /// Who is typing, if anyone did in the last `TYPING_TIMEOUT`.
+    fn typing_line(&self) -> Option<String> {
+        let now = Instant::now();
+        let mut names: Vec<String> = self
+            .typing
+            .iter()
+            .filter(|(_, at)| now.duration_since(**at) < TYPING_TIMEOUT)
+            .map(|(author, _)| self.author_name(author))
+            .collect();
+        names.sort();
+        match names.as_slice() {
+            [] => None,
+            [name] => Some(format!("{name} is typing…")),
+            [a, b] => Some(format!("{a} and {b} are typing…")),
+            _ => Some("several people are typing…".to_string()),
+        }
+    }
+
+    /// Show a reaction from a peer.
+    pub fn push_reaction(&mut self, reaction: Reaction) {
+        self.reactions.apply(reaction);
+    }
+
+    /// Select the message the selected message replies to, in
+    /// a thread the thread moves up to the parent if needed.
+    async fn select_parent(&mut self, tx: &Sender<AppMessage>) {
+        let Some(parent) = self.selected().and_then(|m| m.reply_to.clone()) else {
+            return;
+        };
+        if !self.messages.iter().any(|m| m.id == parent) {
+            let _ = tx
+                .send(AppMessage::Error {
+                    error: "the message it replies to is not loaded".to_string(),
+                })
+                .await;
+            return;
+        }
+        if self.thread.is_some() {
+            self.thread = Some(parent.clone());
+        }
+        let i = self
+            .visible()
+            .iter()
+            .position(|&i| self.messages[i].id == parent);
+        self.state.select(i);
+    }
+
+    /// The indexes of the messages shown, all of them,
+    /// or the root of the thread and its replies.
+    fn visible(&self) -> Vec<usize> {
+        let Some(root) = &self.thread else {
+            return (0..self.messages.len()).collect();
+        };
+        let mut ids = vec![root.as_str()];
+        let mut visible = vec![];
+        for (i, m) in self.messages.iter().enumerate() {
+            let in_thread = m.id == *root
+                || m.reply_to
+                    .as_deref()
+                    .is_some_and(|parent| ids.contains(&parent));
+            if in_thread {
+                ids.push(&m.id);
+                visible.push(i);
+            }
+        }
+        visible
+    }
+
+    /// The message with the id `id`.
+    fn message(&self, id: &str) -> Option<&ChatMessage> {
+        self.messages.iter().find(|m| m.id == id)
+    }
+
+    /// The current body of a message, as shown in the list.
+    fn body(&self, message: &ChatMessage) -> String {
+        match self.edits.current(message) {
+            Version::Original(body) => body.to_string(),
+            Version::Edited(body) => format!("{body} (edited)"),
+            Version::Deleted => "message deleted".to_string(),
+        }
+    }
+
+    /// A line quoting the message `id`, that is replied to.
+    fn quote(&self, id: &str) -> String {
+        match self.message(id) {
+            Some(m) => format!("{}: {}", self.author_name(&m.author), self.body(m)),
+            None => "(not loaded)".to_string(),
+        }
+    }
+
+    pub fn selected(&self) -> Option<&ChatMessage> {
+        let i = self.state.selected()?;
+        self.visible().get(i).map(|&i| &self.messages[i])
+    }
+
+    /// Select the next message, starting from the latest.
+    pub fn next(&mut self) {
+        let Some(last) = self.visible().len().checked_sub(1) else {
+            return;
+        };
+        let i = self.state.selected().map_or(last, |i| (i + 1).min(last));
+        self.state.select(Some(i));
+    }
+
+    /// Select the previous message, starting from the latest.
+    pub fn previous(&mut self) {
+        let Some(last) = self.visible().len().checked_sub(1) else {
+            return;
+        };
+        let i = self.state.selected().map_or(last, |i| i.saturating_sub(1));
+        self.state.select(Some(i));
+    }
+
+    /// The name shown for the author of a message,
+    /// the end of the `PeerId` is enough to tell them apart.
+    fn author_name(&self, author: &str) -> String {
+        if author == self.me {
+            return "you".to_string();
+        }
+        short_name(author).to_string()
+    }
+
+    /// The name of the author of a message, with their presence.
+    fn author_label(&self, author: &str) -> String {
+        let name = self.author_name(author);
+        match self.presence.get(author) {
+            Some(presence) if author != self.me => {
+                format!("{} {name}", presence_indicator(presence.state))
+            }
+            _ => name,
+        }
+    }
+
+    pub fn draw<B: Backend>(
+        &mut self,
+        f: &mut Frame<B>,
+        area: Rect,
+        ui: &AppStyle,
+        keymap: &Keymap,
+    ) {
+        let typing = self.typing_line();
+        let mut constraints = vec![Constraint::Length(1), Constraint::Min(1)];
+        if typing.is_some() {
+            constraints.push(Constraint::Length(1));
+        }
+        constraints.push(Constraint::Length(input_height(&self.input, area.width)));
         let chunks = Layout::default()
             .direction(Direction::Vertical)
-            .constraints(
-                [
-                    Constraint::Length(1),
-                    Constraint::Min(1),
-                    Constraint::Length(3),
-                ]
-                .as_ref(),
-            )
-            .split(f.size());
+            .constraints(constraints)
+            .split(area);
+        let input_area = chunks[chunks.len() - 1];
+        self.help_area = chunks[0];
+        self.messages_area = chunks[1];
+        self.input_area = input_area;
+
+        if let Some(typing) = typing {
+            let typing =
+                Paragraph::new(typing).style(ui.system_style.add_modifier(Modifier::ITALIC));
+            f.render_widget(typing, chunks[2]);
+        }
 
         let (msg, style) = match self.input_mode {
+            InputMode::Normal if self.thread.is_some() => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled(
+                        keymap.label(Action::Back),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" to leave the thread, "),
+                    Span::styled(
+                        keymap.label(Action::Parent),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" to jump to the parent."),
+                ],
+                ui.base_style.add_modifier(Modifier::RAPID_BLINK),
+            ),
             InputMode::Normal => (
                 vec![
                     Span::raw("Press "),
-                    Span::styled("q", ui.normal_style.add_modifier(Modifier::BOLD)),
+                    Span::styled(
+                        keymap.label(Action::Back),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
                     Span::raw(" to exit, "),
-                    Span::styled("i", ui.normal_style.add_modifier(Modifier::BOLD)),
-                    Span::raw(" to enter insert mode."),
+                    Span::styled(
+                        keymap.label(Action::Insert),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" to enter insert mode, "),
+                    Span::styled(
+                        keymap.label(Action::React),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" to react."),
                 ],
                 ui.base_style.add_modifier(Modifier::RAPID_BLINK),
             ),
+            InputMode::Insert if self.editing.is_some() => (
+                vec![
+                    Span::raw("Press "),
+                    Span::styled(
+                        keymap.typing_label(Action::Back, KeyCode::Esc),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" to cancel, "),
+                    Span::styled(
+                        keymap.typing_label(Action::Open, KeyCode::Enter),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
+                    Span::raw(" to save the edit"),
+                ],
+                ui.base_style,
+            ),
             InputMode::Insert => (
                 vec![
                     Span::raw("Press "),
-                    Span::styled("Esc", ui.normal_style.add_modifier(Modifier::BOLD)),
+                    Span::styled(
+                        keymap.typing_label(Action::Back, KeyCode::Esc),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
                     Span::raw(" to enter normal mode, "),
-                    Span::styled("Enter", ui.normal_style.add_modifier(Modifier::BOLD)),
+                    Span::styled(
+                        keymap.typing_label(Action::Open, KeyCode::Enter),
+                        ui.normal_style.add_modifier(Modifier::BOLD),
+                    ),
                     Span::raw(" to send the message"),
                 ],
                 ui.base_style,
             ),
+            InputMode::React(selected) => {
+                let mut spans = vec![Span::raw("React: ")];
+                for (i, emoji) in REACTIONS.iter().enumerate() {
+                    let style = if i == selected {
+                        ui.selected_style
+                    } else {
+                        ui.base_style
+                    };
+                    spans.push(Span::styled(format!("{} {emoji}", i + 1), style));
+                    spans.push(Span::raw(" "));
+                }
+                (spans, ui.base_style)
+            }
         };
         let mut text = Text::from(Spans::from(msg));
         text.patch_style(style);
@@ -151,44 +788,164 @@ impl ChatRoom {
         // render help msg
         f.render_widget(help_message, chunks[0]);
 
-        let input = Paragraph::new(self.input.as_ref())
-            .style(ui.base_style)
-            .block(Block::default().borders(Borders::ALL).title("Message"));
-
-        // render the user input
-        f.render_widget(input, chunks[2]);
-
-        match self.input_mode {
-            InputMode::Normal => {}
-            InputMode::Insert => {
-                // Make the cursor visible and ask tui-rs to put it
-                // at the specified coordinates after rendering
-                f.set_cursor(
-                    // Put cursor past the end of the input text
-                    chunks[2].x + self.input.len() as u16 + 1,
-                    // Move one line down, from the border to the input line
-                    chunks[2].y + 1,
-                )
-            }
+        // quote the message we reply to
+        let title = match &self.replying {
+            Some(parent) => format!("Reply to {}", self.quote(parent)),
+            None => "Message".to_string(),
+        };
+        let cursor = draw_input(f, input_area, &self.input, title, ui);
+        if let InputMode::Insert = self.input_mode {
+            f.set_cursor(cursor.0, cursor.1)
         }
 
-        let messages: Vec<ListItem> = self
-            .items
+        let selected = self.state.selected();
+        let visible = self.visible();
+        // inside the borders
+        let width = chunks[1].width.saturating_sub(2) as usize;
+        // a line over the first message of each day, a timestamp of 0 is no time at all
+        let mut last_day = None;
+        let days: Vec<Option<String>> = visible
+            .iter()
+            .map(|&i| {
+                let timestamp = self.messages[i].timestamp;
+                let day = (timestamp > 0).then(|| self.time.day(timestamp))?;
+                (last_day.replace(day.clone()).as_ref() != Some(&day)).then_some(day)
+            })
+            .collect();
+        let now = now_millis();
+        let messages: Vec<Vec<Spans>> = visible
             .iter()
+            .map(|&i| &self.messages[i])
             .enumerate()
-            .map(|(_i, m)| {
-                // todo: get the ID of the user and pre-pend to the msg
-                // let content = vec![Spans::from(Span::raw(m))];
-                let content = Text::from(m.as_str());
-                ListItem::new(content)
+            .map(|(i, m)| {
+                let mut lines = vec![];
+                if let Some(day) = &days[i] {
+                    lines.push(Spans::from(Span::styled(
+                        format!("── {day} ──"),
+                        ui.system_style,
+                    )));
+                }
+                if let Some(parent) = &m.reply_to {
+                    lines.push(Spans::from(Span::styled(
+                        format!("  > {}", self.quote(parent)),
+                        ui.system_style,
+                    )));
+                }
+                let body_style = match self.edits.current(m) {
+                    Version::Deleted => ui.system_style.add_modifier(Modifier::ITALIC),
+                    _ if m.author != self.me && is_mention(&m.body, &self.mentions) => {
+                        ui.mention_style
+                    }
+                    _ => ui.base_style,
+                };
+                let mut line = vec![];
+                if m.timestamp > 0 {
+                    let time = self.time.time(m.timestamp, now);
+                    line.push(Span::styled(format!("{time} "), ui.system_style));
+                }
+                line.extend([
+                    Span::styled(self.author_label(&m.author), ui.author_style(&m.author)),
+                    Span::raw(": "),
+                    Span::styled(self.body(m), body_style),
+                ]);
+                if let Some(status) = self.delivery_status(m, keymap) {
+                    line.push(Span::styled(format!(" ({status})"), ui.system_style));
+                }
+                // a clock that is off, or a message sent again much later
+                let skew = self.received.get(&m.id).filter(|_| m.timestamp > 0);
+                if let Some(skew) = skew.and_then(|at| self.time.skew(m.timestamp, *at)) {
+                    line.push(Span::styled(
+                        format!(" (clock off by {skew})"),
+                        ui.error_style,
+                    ));
+                }
+                lines.push(Spans::from(line));
+
+                if selected == Some(i) && self.show_versions {
+                    lines.push(Spans::from(format!("  1. {}", m.body)));
+                    for (n, edit) in self.edits.history(m).enumerate() {
+                        let body = edit.body.as_deref().unwrap_or("(deleted)");
+                        lines.push(Spans::from(format!("  {}. {body}", n + 2)));
+                    }
+                }
+
+                let reactions = self.reactions.summary(&m.id);
+                if selected == Some(i) {
+                    // who reacted, for the selected message
+                    for (emoji, authors) in &reactions {
+                        let authors: Vec<String> =
+                            authors.iter().map(|a| self.author_name(a)).collect();
+                        lines.push(Spans::from(format!("  {emoji} {}", authors.join(", "))));
+                    }
+                } else if !reactions.is_empty() {
+                    let counts: Vec<String> = reactions
+                        .iter()
+                        .map(|(emoji, authors)| format!("{emoji} {}", authors.len()))
+                        .collect();
+                    lines.push(Spans::from(format!("  {}", counts.join("  "))));
+                }
+                lines.into_iter().flat_map(|l| wrap(l, width)).collect()
             })
             .collect();
 
-        let messages = List::new(messages)
-            .block(Block::default().borders(Borders::ALL).title("Messages"))
-            .style(ui.base_style);
+        let title = if self.thread.is_some() {
+            "Thread"
+        } else {
+            "Messages"
+        };
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(ui.border_style)
+            .title(title);
+
+        // follow the latest message when nothing is selected, with its
+        // last lines at the bottom, even if the message doesn't fit
+        if self.state.selected().is_none() {
+            let lines: Vec<Spans> = messages.into_iter().flatten().collect();
+            let height = chunks[1].height.saturating_sub(2) as usize;
+            let lines: Vec<ListItem> = lines
+                .into_iter()
+                .rev()
+                .take(height)
+                .rev()
+                .map(ListItem::new)
+                .collect();
+            let messages = List::new(lines).block(block).style(ui.base_style);
+            f.render_widget(messages, chunks[1]);
+            return;
+        }
 
         // render message list
-        f.render_widget(messages, chunks[1]);
+        let messages: Vec<ListItem> = messages.into_iter().map(ListItem::new).collect();
+        let messages = List::new(messages)
+            .block(block)
+            .style(ui.base_style)
+            .highlight_style(ui.selected_style);
+        f.render_stateful_widget(messages, chunks[1], &mut self.state);
     }
 }
+
+/// The query of `/search`, typed in the input instead of a message.
+fn search_command(input: &str) -> Option<&str> {
+    let (name, query) = input.split_once(' ').unwrap_or((input, ""));
+    (name == "/search").then(|| query.trim())
+}
+
+/// The name of `/theme`, empty to list the themes.
+fn theme_command(input: &str) -> Option<&str> {
+    let (name, theme) = input.split_once(' ').unwrap_or((input, ""));
+    (name == "/theme").then(|| theme.trim())
+}
+
+/// A command typed in the input instead of a message,
+/// `/online`, `/away` or `/dnd`, each with an optional status.
+fn command(input: &str) -> Option<(PresenceState, String)> {
+    let (name, status) = input.split_once(' ').unwrap_or((input, ""));
+    let state = match name {
+        "/online" => PresenceState::Online,
+        "/away" => PresenceState::Away,
+        "/dnd" => PresenceState::DoNotDisturb,
+        _ => return None,
+    };
+    Some((state, status.trim().to_string()))
+}
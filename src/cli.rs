@@ -0,0 +1,114 @@
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand};
+use libp2p::Multiaddr;
+use p2p_chat::{
+    config::default_config_path, models::archive::ExportFormat, ChatNode, ChatNodeBuilder,
+};
+
+#[derive(Parser, Debug)]
+#[clap(name = "p2p chat")]
+pub struct Opt {
+    #[clap(long)]
+    pub peer: Option<Multiaddr>,
+
+    #[clap(long)]
+    pub listen_address: Option<Multiaddr>,
+
+    /// run only the network daemon, without the TUI,
+    /// and expose the control socket.
+    #[clap(long)]
+    pub headless: bool,
+
+    /// path of the unix socket used by the control API.
+    #[clap(long, default_value_os_t = default_socket_path())]
+    pub socket: PathBuf,
+
+    /// keep the history of the rooms and the identity of the
+    /// node in this directory, they are lost on exit if this is not set.
+    #[clap(long)]
+    pub data_dir: Option<PathBuf>,
+
+    /// don't tell the peers which of their messages we have seen.
+    #[clap(long)]
+    pub no_read_receipts: bool,
+
+    /// path of the config file of the TUI.
+    #[clap(long, default_value_os_t = default_config_path())]
+    pub config: PathBuf,
+
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Non-interactive commands, for scripts. They talk to the
+/// headless daemon on `--socket`, if there is one running.
+#[derive(Subcommand, Debug, Clone)]
+pub enum Command {
+    /// send a message to a room and exit
+    Send {
+        #[clap(long)]
+        room: String,
+        #[clap(long)]
+        message: String,
+        /// how long to wait for peers in the room
+        #[clap(long, default_value_t = 10)]
+        wait: u64,
+    },
+    /// print the messages of a room to stdout, as JSON lines
+    Listen {
+        #[clap(long)]
+        room: String,
+    },
+    /// list the rooms joined by the daemon, or the
+    /// ones in the history without a daemon
+    Rooms,
+    /// print the peer id of the daemon, or the one in --data-dir
+    Id,
+    /// print the history of a room, with the signatures
+    Export {
+        #[clap(long)]
+        room: String,
+        /// jsonl, markdown or text, only jsonl can be imported
+        #[clap(long, default_value = "jsonl")]
+        format: ExportFormat,
+        /// write to this file instead of stdout
+        #[clap(long)]
+        output: Option<PathBuf>,
+    },
+    /// add a jsonl export to the history, the messages
+    /// that are not signed by their author are rejected
+    Import { file: PathBuf },
+}
+
+impl Opt {
+    /// The configuration of the node, from the CLI flags.
+    pub fn node_builder(&self) -> ChatNodeBuilder {
+        let mut builder = ChatNode::builder();
+        if let Some(addr) = &self.listen_address {
+            builder = builder.listen_address(addr.clone());
+        }
+        if let Some(addr) = &self.peer {
+            builder = builder.peer(addr.clone());
+        }
+        if let Some(dir) = &self.data_dir {
+            builder = builder.data_dir(dir.clone());
+        }
+        builder.read_receipts(!self.no_read_receipts)
+    }
+}
+
+/// The socket in the runtime directory of the user, or in a
+/// directory of their own under the temp dir, never a shared one.
+pub fn default_socket_path() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join("p2p-chat.sock"),
+        _ => {
+            // SAFETY: `getuid` can't fail and has no side effects
+            let uid = unsafe { libc::getuid() };
+            std::env::temp_dir()
+                .join(format!("p2p-chat-{uid}"))
+                .join("p2p-chat.sock")
+        }
+    }
+}
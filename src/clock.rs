@@ -0,0 +1,124 @@
+//! How the TUI shows the time of messages, in the timezone of the config.
+use serde::Deserialize;
+
+use crate::models::time::{format_date, MILLIS_PER_DAY};
+
+/// How far off the clock of a live message may be, in seconds.
+pub const DEFAULT_MAX_SKEW: u64 = 5 * 60;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeFormat {
+    /// the time of day, e.g. `14:05`
+    #[default]
+    Absolute,
+    /// how long ago, e.g. `5m ago`
+    Relative,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Timezone {
+    /// the timezone of the system, from `TZ` or `/etc/localtime`
+    #[default]
+    Local,
+    Utc,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimeConfig {
+    pub format: TimeFormat,
+    /// `2:05 PM` instead of `14:05`
+    pub twelve_hour: bool,
+    pub timezone: Timezone,
+    /// live messages further off our clock than this, in
+    /// seconds, are flagged, 0 to never flag them
+    pub max_skew: u64,
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        Self {
+            format: TimeFormat::default(),
+            twelve_hour: false,
+            timezone: Timezone::default(),
+            max_skew: DEFAULT_MAX_SKEW,
+        }
+    }
+}
+
+impl TimeConfig {
+    /// `timestamp`, in milliseconds since the unix epoch, moved to our timezone.
+    fn local(&self, timestamp: u64) -> u64 {
+        let offset = match self.timezone {
+            Timezone::Local => local_offset(timestamp),
+            Timezone::Utc => 0,
+        };
+        timestamp.saturating_add_signed(offset * 1000)
+    }
+
+    /// The time of a message sent at `timestamp`, when it is `now`.
+    pub fn time(&self, timestamp: u64, now: u64) -> String {
+        if self.format == TimeFormat::Relative {
+            return match now.saturating_sub(timestamp) {
+                ago if ago < 60 * 1000 => "now".to_string(),
+                ago if ago < 7 * MILLIS_PER_DAY => format!("{} ago", duration(ago)),
+                _ => self.day(timestamp),
+            };
+        }
+        let minutes = self.local(timestamp) % MILLIS_PER_DAY / (60 * 1000);
+        let (hour, minute) = (minutes / 60, minutes % 60);
+        if self.twelve_hour {
+            let half = if hour < 12 { "AM" } else { "PM" };
+            let hour = match hour % 12 {
+                0 => 12,
+                hour => hour,
+            };
+            format!("{hour}:{minute:02} {half}")
+        } else {
+            format!("{hour:02}:{minute:02}")
+        }
+    }
+
+    /// The day of `timestamp`, as `YYYY-MM-DD` in our timezone.
+    pub fn day(&self, timestamp: u64) -> String {
+        format_date(self.local(timestamp))
+    }
+
+    /// How far off our clock a message sent at `timestamp` is,
+    /// if it reached us at `received` and that is too far.
+    pub fn skew(&self, timestamp: u64, received: u64) -> Option<String> {
+        let skew = timestamp.abs_diff(received);
+        (self.max_skew > 0 && skew > self.max_skew * 1000).then(|| duration(skew))
+    }
+}
+
+/// `millis` in its largest unit, e.g. `45s`, `12m`, `3h` or `2d`.
+pub fn duration(millis: u64) -> String {
+    let seconds = millis / 1000;
+    match seconds {
+        s if s < 60 => format!("{s}s"),
+        s if s < 60 * 60 => format!("{}m", s / 60),
+        s if s < 24 * 60 * 60 => format!("{}h", s / 60 / 60),
+        s => format!("{}d", s / 24 / 60 / 60),
+    }
+}
+
+/// The offset of the local timezone from UTC at `timestamp`, in seconds.
+#[cfg(unix)]
+fn local_offset(timestamp: u64) -> i64 {
+    let time = (timestamp / 1000) as libc::time_t;
+    // SAFETY: `tm` is plain data, and `localtime_r` only writes to it
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    // `c_long` is only 32 bits on some platforms
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(not(unix))]
+fn local_offset(_timestamp: u64) -> i64 {
+    0
+}
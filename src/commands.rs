@@ -0,0 +1,290 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    path::Path,
+    time::Duration,
+};
+
+use p2p_chat::{
+    config::Config,
+    models::{
+        archive::{self, ImportReport},
+        control::{self, Client},
+        history::RoomHistory,
+    },
+    ChatNode, NetworkError,
+};
+use serde_json::{json, Value};
+use tokio::{io::DuplexStream, net::UnixStream, task::JoinHandle, time::timeout};
+
+use crate::cli::{Command, Opt};
+
+/// How long `send` waits for its message to be published.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Either the headless daemon, or a node that lives
+/// only while the command runs.
+enum Node {
+    Daemon(Client<UnixStream>),
+    ShortLived {
+        client: Client<DuplexStream>,
+        node: Box<ChatNode>,
+        driver: JoinHandle<Result<(), NetworkError>>,
+    },
+}
+
+impl Node {
+    async fn call(&mut self, method: &str, params: Value) -> io::Result<Value> {
+        match self {
+            Node::Daemon(client) => client.call(method, params).await,
+            Node::ShortLived { client, .. } => client.call(method, params).await,
+        }
+    }
+
+    async fn next_event(&mut self) -> io::Result<Value> {
+        match self {
+            Node::Daemon(client) => client.next_event().await,
+            Node::ShortLived { client, .. } => client.next_event().await,
+        }
+    }
+
+    /// Stop the short-lived node, once its history is on disk.
+    async fn close(self) -> Result<(), String> {
+        if let Node::ShortLived { node, driver, .. } = self {
+            let _ = node.quit().await;
+            match driver.await {
+                Ok(result) => result.map_err(|e| e.to_string())?,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Run a non-interactive command and exit.
+pub async fn run(command: Command, opt: &Opt) -> Result<(), String> {
+    let socket = opt.socket.as_path();
+    let daemon = Client::connect(socket).await.ok();
+
+    match command {
+        Command::Send {
+            room,
+            message,
+            wait,
+        } => {
+            let mut node = match daemon {
+                Some(client) => Node::Daemon(client),
+                None => short_lived_node(opt),
+            };
+
+            node.call("subscribe", Value::Null)
+                .await
+                .map_err(rpc_error)?;
+            node.call("join", json!({ "room": room }))
+                .await
+                .map_err(rpc_error)?;
+            wait_for_peer(&mut node, &room, Duration::from_secs(wait)).await?;
+
+            let sent = node
+                .call("send", json!({ "room": room, "message": message }))
+                .await
+                .map_err(rpc_error)?;
+
+            // tell the caller if the message didn't make it out,
+            // the short-lived node stops with the command.
+            let id = sent["id"].as_str().unwrap_or_default();
+            let delivered = wait_for_delivery(&mut node, &room, id).await;
+            node.close().await?;
+            delivered?;
+        }
+        Command::Listen { room } => {
+            let mut node = match daemon {
+                Some(client) => Node::Daemon(client),
+                None => short_lived_node(opt),
+            };
+
+            node.call("subscribe", Value::Null)
+                .await
+                .map_err(rpc_error)?;
+            node.call("join", json!({ "room": room }))
+                .await
+                .map_err(rpc_error)?;
+
+            let mut stdout = io::stdout();
+            loop {
+                let event = node.next_event().await.map_err(rpc_error)?;
+                if event["type"] == "message_received" && event["topic"] == room.as_str() {
+                    writeln!(stdout, "{event}").map_err(|e| e.to_string())?;
+                    stdout.flush().map_err(|e| e.to_string())?;
+                }
+            }
+        }
+        Command::Rooms => {
+            // without a daemon, nothing is joined but the history is there
+            let mut node = history_node(daemon, opt)?;
+            let method = match node {
+                Node::Daemon(_) => "rooms",
+                Node::ShortLived { .. } => "stored_rooms",
+            };
+            let rooms = node.call(method, Value::Null).await.map_err(rpc_error)?;
+            node.close().await?;
+            for room in rooms.as_array().into_iter().flatten() {
+                println!("{}", room.as_str().unwrap_or_default());
+            }
+        }
+        Command::Id => {
+            let mut node = match daemon {
+                Some(client) => Node::Daemon(client),
+                None => short_lived_node(opt),
+            };
+            let id = node.call("id", Value::Null).await.map_err(rpc_error)?;
+            node.close().await?;
+            println!("{}", id.as_str().unwrap_or_default());
+        }
+        Command::Export {
+            room,
+            format,
+            output,
+        } => {
+            let mut node = history_node(daemon, opt)?;
+            let history = node
+                .call("history", json!({ "room": room }))
+                .await
+                .map_err(rpc_error)?;
+            let history: RoomHistory =
+                serde_json::from_value(history).map_err(|e| e.to_string())?;
+
+            // we only know our own nickname
+            let mut nicknames = HashMap::new();
+            let config = Config::load(&opt.config).map_err(|e| e.to_string())?;
+            if let Some(nickname) = config.nickname {
+                let id = node.call("id", Value::Null).await.map_err(rpc_error)?;
+                nicknames.insert(id.as_str().unwrap_or_default().to_string(), nickname);
+            }
+            node.close().await?;
+
+            let export = archive::export(&room, &history, &nicknames, format);
+            match output {
+                Some(path) => std::fs::write(&path, export)
+                    .map_err(|e| format!("could not write {}: {e}", path.display()))?,
+                None => print!("{export}"),
+            }
+        }
+        Command::Import { file } => {
+            let text = std::fs::read_to_string(&file)
+                .map_err(|e| format!("could not read {}: {e}", file.display()))?;
+            let rooms = archive::import(&text).map_err(|e| e.to_string())?;
+
+            let mut node = history_node(daemon, opt)?;
+            let mut report = ImportReport::default();
+            for (room, history) in rooms {
+                let mut params = serde_json::to_value(history).map_err(|e| e.to_string())?;
+                params["room"] = json!(room);
+                let result = node.call("import", params).await.map_err(rpc_error)?;
+                report.add(serde_json::from_value(result).map_err(|e| e.to_string())?);
+            }
+            node.close().await?;
+            println!("{report}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Start a `Network` in this process and talk to
+/// it with the same API as the daemon.
+fn short_lived_node(opt: &Opt) -> Node {
+    let (node, driver) = opt.node_builder().build();
+
+    let driver = tokio::spawn(driver.run());
+
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(control::handle_client(server, node.clone()));
+
+    Node::ShortLived {
+        client: Client::new(client),
+        node: Box::new(node),
+        driver,
+    }
+}
+
+/// The daemon, or a node with the history in `--data-dir`.
+fn history_node(daemon: Option<Client<UnixStream>>, opt: &Opt) -> Result<Node, String> {
+    match daemon {
+        Some(client) => Ok(Node::Daemon(client)),
+        None if opt.data_dir.is_some() => Ok(short_lived_node(opt)),
+        None => Err(format!(
+            "{}, and there is no history without --data-dir",
+            no_daemon(&opt.socket)
+        )),
+    }
+}
+
+/// Wait until someone else joins `room`, so there
+/// is someone to receive our messages.
+async fn wait_for_peer(node: &mut Node, room: &str, wait: Duration) -> Result<(), String> {
+    // the daemon may be in the room with its peers already
+    let peers = node
+        .call("room_peers", json!({ "room": room }))
+        .await
+        .map_err(rpc_error)?;
+    if peers.as_array().is_some_and(|peers| !peers.is_empty()) {
+        return Ok(());
+    }
+
+    let subscribed = async {
+        loop {
+            let event = node.next_event().await?;
+            if event["type"] == "peer_subscribed" && event["topic"] == room {
+                return Ok::<_, io::Error>(());
+            }
+        }
+    };
+
+    match timeout(wait, subscribed).await {
+        Ok(result) => result.map_err(rpc_error),
+        Err(_) => Err(format!(
+            "no peers joined room {room} within {} seconds",
+            wait.as_secs()
+        )),
+    }
+}
+
+/// Wait until the message `id` is published to the peers of `room`.
+async fn wait_for_delivery(node: &mut Node, room: &str, id: &str) -> Result<(), String> {
+    let delivered = async {
+        loop {
+            let event = node.next_event().await.map_err(rpc_error)?;
+            if event["type"] != "delivery" || event["message_id"] != id {
+                continue;
+            }
+            match event["status"].as_str() {
+                Some("published") => return Ok(()),
+                Some("queued") => return Err(format!("no peer is in room {room} to send it to")),
+                Some("failed") => {
+                    return Err(format!(
+                        "could not send the message: {}",
+                        event["reason"].as_str().unwrap_or_default()
+                    ))
+                }
+                _ => {}
+            }
+        }
+    };
+
+    match timeout(DELIVERY_TIMEOUT, delivered).await {
+        Ok(result) => result,
+        Err(_) => Err(format!(
+            "the message was not sent within {} seconds",
+            DELIVERY_TIMEOUT.as_secs()
+        )),
+    }
+}
+
+fn no_daemon(socket: &Path) -> String {
+    format!("no daemon is listening on {}", socket.display())
+}
+
+fn rpc_error(e: io::Error) -> String {
+    e.to_string()
+}
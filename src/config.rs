@@ -0,0 +1,173 @@
+//! The config file of the TUI, `~/.config/p2p-chat/config.toml`.
+//!
+//! Every setting is optional, a missing file is the same as an empty one.
+use std::{collections::HashMap, fmt, io, path::Path, path::PathBuf, str::FromStr};
+
+use serde::Deserialize;
+
+use crate::{
+    clock::TimeConfig,
+    keymap::KeysConfig,
+    logger::LogConfig,
+    theme::{self, AppStyle, ThemeConfig},
+};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// the name others use to mention us,
+    /// the end of our `PeerId` always works too
+    pub nickname: Option<String>,
+    /// words that notify us like a mention does
+    pub keywords: Vec<String>,
+    pub notifications: Notifications,
+    /// the theme at startup, built in or in `themes`, `dark` if not set
+    pub theme: Option<String>,
+    /// name -> custom theme
+    pub themes: HashMap<String, ThemeConfig>,
+    pub keys: KeysConfig,
+    pub log: LogConfig,
+    /// the TUI takes the mouse, turn it off to select text in the terminal
+    pub mouse: bool,
+    /// how the time of messages is shown
+    pub time: TimeConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            nickname: None,
+            keywords: vec![],
+            notifications: Notifications::default(),
+            theme: None,
+            themes: HashMap::new(),
+            keys: KeysConfig::default(),
+            log: LogConfig::default(),
+            mouse: true,
+            time: TimeConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    /// how the terminal tells us
+    pub alert: Alert,
+    /// a shell command to run on every notification, the message is
+    /// in `P2P_CHAT_ROOM`, `P2P_CHAT_AUTHOR` and `P2P_CHAT_MESSAGE`
+    pub command: Option<String>,
+    /// the rule of the rooms that are not in `rooms`
+    pub default: Rule,
+    /// room -> rule
+    pub rooms: HashMap<String, Rule>,
+}
+
+/// Which messages of a room notify us.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Rule {
+    All,
+    #[default]
+    Mentions,
+    None,
+}
+
+/// How the terminal tells us about a notification.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Alert {
+    /// ring the bell
+    #[default]
+    Bell,
+    /// a desktop notification with OSC 9, e.g. iTerm2 and kitty
+    Osc9,
+    /// a desktop notification with OSC 777, e.g. foot and urxvt
+    Osc777,
+    /// only run the command, if there is one
+    None,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    /// a theme that doesn't exist, or inherits one that doesn't
+    UnknownTheme(PathBuf, String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "could not read {}: {e}", path.display()),
+            ConfigError::Parse(path, e) => write!(f, "invalid config {}: {e}", path.display()),
+            ConfigError::UnknownTheme(path, name) => {
+                write!(f, "invalid config {}: unknown theme {name}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl FromStr for Config {
+    type Err = toml::de::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s)
+    }
+}
+
+impl Config {
+    /// Load the config at `path`, the default config if there is no such file.
+    pub fn load(path: &Path) -> Result<Self, ConfigError> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(ConfigError::Read(path.to_path_buf(), e)),
+        };
+        let config: Config = text
+            .parse()
+            .map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+        config
+            .check_themes()
+            .map_err(|name| ConfigError::UnknownTheme(path.to_path_buf(), name))?;
+        Ok(config)
+    }
+
+    /// The name of the first theme that can't be built, if any.
+    pub fn check_themes(&self) -> Result<(), String> {
+        for (name, theme) in &self.themes {
+            if theme.style().is_none() {
+                return Err(theme.inherits.clone().unwrap_or_else(|| name.clone()));
+            }
+        }
+        match &self.theme {
+            Some(name) if self.style(name).is_none() => Err(name.clone()),
+            _ => Ok(()),
+        }
+    }
+
+    /// The style of the theme `name`, from `themes` or built in.
+    pub fn style(&self, name: &str) -> Option<AppStyle> {
+        theme::theme(name, &self.themes)
+    }
+
+    /// Which messages of `room` notify us.
+    pub fn rule(&self, room: &str) -> Rule {
+        self.notifications
+            .rooms
+            .get(room)
+            .copied()
+            .unwrap_or(self.notifications.default)
+    }
+}
+
+/// `$XDG_CONFIG_HOME/p2p-chat/config.toml`, or under `~/.config`.
+pub fn default_config_path() -> PathBuf {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+        .unwrap_or_default();
+    dir.join("p2p-chat").join("config.toml")
+}
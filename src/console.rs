@@ -0,0 +1,220 @@
+use crossterm::event::{KeyCode, KeyEvent, MouseEvent, MouseEventKind};
+use log::LevelFilter;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::Modifier,
+    text::{Span, Spans, Text},
+    widgets::{Block, Borders, List, ListItem, Paragraph},
+    Frame,
+};
+use unicode_width::UnicodeWidthStr;
+
+use crate::{
+    app::AppStyle,
+    keymap::{is_chord, Action, Keymap},
+    logger::{LogBuffer, LogRecord},
+    models::time::format_time,
+};
+
+/// The levels the console can show, from the least verbose.
+const LEVELS: [LevelFilter; 5] = [
+    LevelFilter::Error,
+    LevelFilter::Warn,
+    LevelFilter::Info,
+    LevelFilter::Debug,
+    LevelFilter::Trace,
+];
+
+/// The log, in a pane under the page. While it is open, it takes the keys.
+#[derive(Debug, Clone)]
+pub struct Console {
+    pub log: LogBuffer,
+    pub open: bool,
+    /// the most verbose level shown
+    pub level: LevelFilter,
+    /// the words the records must have
+    pub search: String,
+    /// typing into `search`
+    pub editing: bool,
+    /// how many of the shown records are below the screen, 0 follows the log
+    pub scroll: usize,
+    /// where it was last drawn, empty if it is closed
+    pub area: Rect,
+}
+
+impl Console {
+    pub fn new(log: LogBuffer) -> Self {
+        Self {
+            log,
+            open: false,
+            level: LevelFilter::Trace,
+            search: String::new(),
+            editing: false,
+            scroll: 0,
+            area: Rect::default(),
+        }
+    }
+
+    /// The records that pass the level and the search, oldest first.
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.log
+            .records()
+            .into_iter()
+            .filter(|r| r.matches(self.level, &self.search))
+            .collect()
+    }
+
+    pub fn keybindings(&mut self, k: KeyEvent, keymap: &Keymap) {
+        if self.editing {
+            match k.code {
+                KeyCode::Enter => self.editing = false,
+                KeyCode::Char(c) if !is_chord(k) => {
+                    self.search.push(c);
+                    self.scroll = 0;
+                }
+                KeyCode::Backspace => {
+                    self.search.pop();
+                    self.scroll = 0;
+                }
+                _ if k.code == KeyCode::Esc
+                    || is_chord(k) && keymap.action(k) == Some(Action::Back) =>
+                {
+                    self.editing = false
+                }
+                _ => {}
+            }
+            return;
+        }
+
+        match keymap.action(k) {
+            Some(Action::Console | Action::Back) => self.open = false,
+            Some(Action::Search | Action::Insert) => self.editing = true,
+            Some(Action::Up) => self.scroll_up(),
+            Some(Action::Down) => self.scroll = self.scroll.saturating_sub(1),
+            Some(Action::Latest) => self.scroll = 0,
+            Some(Action::Left) => self.set_level(-1),
+            Some(Action::Right) => self.set_level(1),
+            _ => {}
+        }
+    }
+
+    /// The wheel scrolls the log.
+    pub fn mouse(&mut self, m: MouseEvent) {
+        match m.kind {
+            MouseEventKind::ScrollUp => self.scroll_up(),
+            MouseEventKind::ScrollDown => self.scroll = self.scroll.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    fn scroll_up(&mut self) {
+        let shown = self.records().len();
+        self.scroll = (self.scroll + 1).min(shown.saturating_sub(1));
+    }
+
+    /// Show a level more, or less, verbose than now.
+    fn set_level(&mut self, step: isize) {
+        let i = LEVELS.iter().position(|l| *l == self.level).unwrap_or(0);
+        let i = (i as isize + step).clamp(0, LEVELS.len() as isize - 1);
+        self.level = LEVELS[i as usize];
+        self.scroll = 0;
+    }
+
+    /// Draw the console on the bottom of `area`, if it
+    /// is open, and return what is left for the page.
+    pub fn draw<B: Backend>(
+        &mut self,
+        f: &mut Frame<B>,
+        area: Rect,
+        ui: &AppStyle,
+        keymap: &Keymap,
+    ) -> Rect {
+        self.area = Rect::default();
+        if !self.open || area.height < 8 {
+            return area;
+        }
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)].as_ref())
+            .split(area);
+        self.area = chunks[1];
+
+        let records = self.records();
+        let block = Block::default()
+            .borders(Borders::ALL)
+            .border_style(ui.border_style)
+            .title(format!(
+                "Log, {} and above, {} records",
+                self.level.as_str().to_lowercase(),
+                records.len()
+            ));
+        let inner = block.inner(chunks[1]);
+        f.render_widget(block, chunks[1]);
+
+        let inner = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+            .split(inner);
+
+        // the last records that fit, above the ones scrolled past
+        let end = records.len() - self.scroll.min(records.len());
+        let start = end.saturating_sub(inner[0].height as usize);
+        let lines: Vec<ListItem> = records[start..end]
+            .iter()
+            .map(|record| {
+                let style = match record.level {
+                    log::Level::Error => ui.error_style,
+                    log::Level::Warn => ui.mention_style,
+                    log::Level::Info => ui.base_style,
+                    log::Level::Debug | log::Level::Trace => ui.system_style,
+                };
+                ListItem::new(Spans::from(vec![
+                    Span::styled(format_time(record.timestamp), ui.system_style),
+                    Span::styled(format!(" {:5} ", record.level), style),
+                    Span::styled(format!("{}: ", record.target), ui.system_style),
+                    Span::styled(record.message.clone(), style),
+                ]))
+            })
+            .collect();
+        f.render_widget(List::new(lines).style(ui.base_style), inner[0]);
+
+        let bold = ui.normal_style.add_modifier(Modifier::BOLD);
+        let msg = if self.editing {
+            vec![
+                Span::styled("Search: ", bold),
+                Span::raw(self.search.clone()),
+            ]
+        } else {
+            let mut msg = vec![
+                Span::raw("Press "),
+                Span::styled(
+                    format!(
+                        "{}/{}",
+                        keymap.label(Action::Left),
+                        keymap.label(Action::Right)
+                    ),
+                    bold,
+                ),
+                Span::raw(" for the level, "),
+                Span::styled(keymap.label(Action::Search), bold),
+                Span::raw(" to search, "),
+                Span::styled(keymap.label(Action::Console), bold),
+                Span::raw(" to close."),
+            ];
+            if !self.search.is_empty() {
+                msg.push(Span::raw(format!(" Search: {}", self.search)));
+            }
+            msg
+        };
+        let mut text = Text::from(Spans::from(msg));
+        text.patch_style(ui.base_style);
+        f.render_widget(Paragraph::new(text), inner[1]);
+        if self.editing {
+            let x = inner[1].x + "Search: ".len() as u16 + self.search.width() as u16;
+            f.set_cursor(x, inner[1].y)
+        }
+
+        chunks[0]
+    }
+}
@@ -0,0 +1,412 @@
+//! The keys of the TUI, key chords mapped to the actions of the pages.
+use std::{fmt, str::FromStr};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{de, Deserialize, Deserializer};
+
+/// What a key does, each page handles the actions that make sense to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Action {
+    Up,
+    Down,
+    Left,
+    Right,
+    Open,
+    Back,
+    Search,
+    Insert,
+    Latest,
+    React,
+    Edit,
+    Delete,
+    Reply,
+    Thread,
+    Parent,
+    Resend,
+    Versions,
+    Console,
+    Help,
+}
+
+/// Every action, with its name in the config and what it does,
+/// in the order of the help.
+pub const ACTIONS: [(Action, &str, &str); 19] = [
+    (Action::Up, "up", "select the previous item"),
+    (Action::Down, "down", "select the next item"),
+    (Action::Left, "left", "the previous reaction in the picker"),
+    (Action::Right, "right", "the next reaction in the picker"),
+    (
+        Action::Open,
+        "open",
+        "open the room or result, pick the reaction",
+    ),
+    (Action::Back, "back", "go back, quit from the rooms"),
+    (Action::Search, "search", "search the messages"),
+    (Action::Insert, "insert", "write a message"),
+    (Action::Latest, "latest", "follow the latest message"),
+    (Action::React, "react", "react to the message"),
+    (Action::Edit, "edit", "edit your message"),
+    (Action::Delete, "delete", "delete your message"),
+    (Action::Reply, "reply", "reply to the message"),
+    (Action::Thread, "thread", "show the thread of the message"),
+    (
+        Action::Parent,
+        "parent",
+        "jump to the message it replies to",
+    ),
+    (Action::Resend, "resend", "send a failed message again"),
+    (
+        Action::Versions,
+        "versions",
+        "show the edits of the message",
+    ),
+    (Action::Console, "console", "show the log"),
+    (Action::Help, "help", "show the keys"),
+];
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ACTIONS
+            .iter()
+            .find(|(_, name, _)| *name == s)
+            .map(|(action, ..)| *action)
+            .ok_or_else(|| format!("unknown action {s}"))
+    }
+}
+
+/// A key with its modifiers, e.g. `ctrl-n`, `alt-r`, `G` or `Enter`.
+///
+/// Shift is part of the character, `shift-r` is the same as `R`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyChord {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyChord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        let mut modifiers =
+            modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            KeyCode::BackTab => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            code => code,
+        };
+        Self { code, modifiers }
+    }
+}
+
+impl From<KeyEvent> for KeyChord {
+    fn from(k: KeyEvent) -> Self {
+        Self::new(k.code, k.modifiers)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidKey(pub String);
+
+impl fmt::Display for InvalidKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid key {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidKey {}
+
+impl FromStr for KeyChord {
+    type Err = InvalidKey;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut key = s;
+        // a key on its own can be `-`, so there is always something after a prefix
+        while let Some((prefix, rest)) = key.split_once('-').filter(|(_, rest)| !rest.is_empty()) {
+            modifiers |= match prefix.to_lowercase().as_str() {
+                "ctrl" | "c" => KeyModifiers::CONTROL,
+                "alt" | "meta" | "m" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(InvalidKey(s.to_string())),
+            };
+            key = rest;
+        }
+
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match key.to_lowercase().as_str() {
+                "enter" | "return" => KeyCode::Enter,
+                "esc" | "escape" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "backspace" => KeyCode::Backspace,
+                "space" => KeyCode::Char(' '),
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                "delete" | "del" => KeyCode::Delete,
+                "insert" => KeyCode::Insert,
+                name => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                    Some(n @ 1..=12) => KeyCode::F(n),
+                    _ => return Err(InvalidKey(s.to_string())),
+                },
+            },
+        };
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "ctrl-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "alt-")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "shift-")?;
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::BackTab => write!(f, "shift-Tab"),
+            KeyCode::PageUp => write!(f, "PageUp"),
+            KeyCode::PageDown => write!(f, "PageDown"),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            code => write!(f, "{code:?}"),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyChord {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
+
+/// What a key of the config does, `"none"` takes it out of the preset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Binding(pub Option<Action>);
+
+impl<'de> Deserialize<'de> for Binding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        if s == "none" {
+            return Ok(Binding(None));
+        }
+        s.parse()
+            .map(|a| Binding(Some(a)))
+            .map_err(de::Error::custom)
+    }
+}
+
+/// The keys the config starts from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    #[default]
+    Vim,
+    Emacs,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeysConfig {
+    pub preset: Preset,
+    /// key -> action, on top of the preset, in the order of the config
+    #[serde(deserialize_with = "in_order")]
+    pub bindings: Vec<(KeyChord, Binding)>,
+}
+
+/// The entries of a map, in the order they are written.
+fn in_order<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<(KeyChord, Binding)>, D::Error> {
+    struct Entries;
+
+    impl<'de> de::Visitor<'de> for Entries {
+        type Value = Vec<(KeyChord, Binding)>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a table of keys and actions")
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut entries = Vec::new();
+            while let Some(entry) = map.next_entry()? {
+                entries.push(entry);
+            }
+            Ok(entries)
+        }
+    }
+
+    deserializer.deserialize_map(Entries)
+}
+
+/// The key chords of each action. Typing in an input
+/// doesn't go through it, only `Enter`, `Esc` and
+/// the chords with ctrl or alt do.
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    /// in the order of the preset, then the config
+    bindings: Vec<(KeyChord, Action)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::vim()
+    }
+}
+
+impl Keymap {
+    fn from_preset(bindings: &[(Action, &[&str])]) -> Self {
+        let bindings = bindings
+            .iter()
+            .flat_map(|(action, keys)| {
+                keys.iter()
+                    .map(|k| (k.parse().expect("preset keys to be valid"), *action))
+            })
+            .collect();
+        Self { bindings }
+    }
+
+    pub fn vim() -> Self {
+        Self::from_preset(&[
+            (Action::Up, &["k", "Up"]),
+            (Action::Down, &["j", "Down"]),
+            (Action::Left, &["h", "Left"]),
+            (Action::Right, &["l", "Right"]),
+            (Action::Open, &["Enter"]),
+            (Action::Back, &["q", "Esc"]),
+            (Action::Search, &["/"]),
+            (Action::Insert, &["i"]),
+            (Action::Latest, &["G", "End"]),
+            (Action::React, &["r"]),
+            (Action::Edit, &["e"]),
+            (Action::Delete, &["d"]),
+            (Action::Reply, &["R"]),
+            (Action::Thread, &["t"]),
+            (Action::Parent, &["p"]),
+            (Action::Resend, &["s"]),
+            (Action::Versions, &["v"]),
+            (Action::Console, &["`", "F12"]),
+            (Action::Help, &["?", "F1"]),
+        ])
+    }
+
+    pub fn emacs() -> Self {
+        Self::from_preset(&[
+            (Action::Up, &["ctrl-p", "Up"]),
+            (Action::Down, &["ctrl-n", "Down"]),
+            (Action::Left, &["ctrl-b", "Left"]),
+            (Action::Right, &["ctrl-f", "Right"]),
+            (Action::Open, &["Enter"]),
+            (Action::Back, &["ctrl-g", "Esc"]),
+            (Action::Search, &["ctrl-s"]),
+            (Action::Insert, &["alt-i"]),
+            (Action::Latest, &["alt->", "End"]),
+            (Action::React, &["alt-r"]),
+            (Action::Edit, &["alt-e"]),
+            (Action::Delete, &["alt-d"]),
+            (Action::Reply, &["alt-R"]),
+            (Action::Thread, &["alt-t"]),
+            (Action::Parent, &["alt-p"]),
+            (Action::Resend, &["alt-s"]),
+            (Action::Versions, &["alt-v"]),
+            (Action::Console, &["F12", "alt-`"]),
+            (Action::Help, &["F1", "alt-?"]),
+        ])
+    }
+
+    pub fn new(config: &KeysConfig) -> Self {
+        let mut keymap = match config.preset {
+            Preset::Vim => Self::vim(),
+            Preset::Emacs => Self::emacs(),
+        };
+        for (chord, Binding(action)) in &config.bindings {
+            keymap.bindings.retain(|(c, _)| c != chord);
+            if let Some(action) = action {
+                keymap.bindings.push((*chord, *action));
+            }
+        }
+        keymap
+    }
+
+    /// The action of `k`, if it has one.
+    pub fn action(&self, k: KeyEvent) -> Option<Action> {
+        let chord = KeyChord::from(k);
+        self.bindings
+            .iter()
+            .find(|(c, _)| *c == chord)
+            .map(|(_, action)| *action)
+    }
+
+    /// The chords of `action`.
+    pub fn keys(&self, action: Action) -> impl Iterator<Item = &KeyChord> {
+        self.bindings
+            .iter()
+            .filter(move |(_, a)| *a == action)
+            .map(|(c, _)| c)
+    }
+
+    /// The first chord of `action`, as shown in the help line of a page.
+    pub fn label(&self, action: Action) -> String {
+        self.keys(action)
+            .next()
+            .map_or_else(|| "(unbound)".to_string(), ToString::to_string)
+    }
+
+    /// `k` does `action` while typing: it is `key`, which always
+    /// does, or a chord with ctrl or alt that is bound to it.
+    pub fn while_typing(&self, k: KeyEvent, action: Action, key: KeyCode) -> bool {
+        k.code == key || is_chord(k) && self.action(k) == Some(action)
+    }
+
+    /// The first chord of `action` that works while typing, see `while_typing`.
+    pub fn typing_label(&self, action: Action, key: KeyCode) -> String {
+        let key = KeyChord::new(key, KeyModifiers::NONE);
+        self.keys(action)
+            .find(|c| {
+                **c == key
+                    || c.modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+            })
+            .unwrap_or(&key)
+            .to_string()
+    }
+
+    /// The chords and description of every action that has any.
+    pub fn help(&self) -> Vec<(String, &'static str)> {
+        ACTIONS
+            .iter()
+            .filter_map(|(action, _, description)| {
+                let keys: Vec<String> = self.keys(*action).map(ToString::to_string).collect();
+                (!keys.is_empty()).then(|| (keys.join(", "), *description))
+            })
+            .collect()
+    }
+}
+
+/// `k` has ctrl or alt, so it is not typed into an input.
+pub fn is_chord(k: KeyEvent) -> bool {
+    k.modifiers
+        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT)
+}
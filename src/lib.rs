@@ -0,0 +1,39 @@
+//! A peer-to-peer, encrypted chat built on rust-libp2p.
+//!
+//! `ChatNode` is the entry point, it joins rooms, sends messages
+//! and emits the events of the network. The TUI, behind the `tui`
+//! feature, is one consumer of it.
+pub mod models;
+pub mod node;
+pub mod supervisor;
+
+#[cfg(feature = "tui")]
+pub mod app;
+#[cfg(feature = "tui")]
+pub mod chat_room;
+#[cfg(feature = "tui")]
+pub mod clock;
+#[cfg(feature = "tui")]
+pub mod config;
+#[cfg(feature = "tui")]
+pub mod console;
+#[cfg(feature = "tui")]
+pub mod keymap;
+#[cfg(feature = "tui")]
+pub mod logger;
+#[cfg(feature = "tui")]
+pub mod notify;
+#[cfg(feature = "tui")]
+pub mod search;
+#[cfg(feature = "tui")]
+pub mod status_bar;
+#[cfg(feature = "tui")]
+pub mod theme;
+#[cfg(feature = "tui")]
+pub mod topic_list;
+#[cfg(feature = "tui")]
+pub mod ui;
+
+pub use models::{error::NetworkError, message::ChatMessage, network::NetworkEvent};
+pub use node::{ChatNode, ChatNodeBuilder, EventStream, NodeConfig, NodeDriver, NodeError};
+pub use supervisor::{Supervisor, TaskError, TaskResult};
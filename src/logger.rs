@@ -0,0 +1,244 @@
+//! The log of the TUI. Writing to stderr would draw over the
+//! screen, so the records are kept in memory for the console,
+//! and written to a file if the config asks for one.
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use log::{Level, LevelFilter, Log, Metadata, Record, SetLoggerError};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::models::{message::now_millis, time::format_datetime};
+
+/// How many records are kept in memory, the oldest ones are dropped first.
+pub const LOG_CAPACITY: usize = 1000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// the most verbose level that is kept, `RUST_LOG` wins if it is a level
+    #[serde(deserialize_with = "deserialize_level")]
+    pub level: LevelFilter,
+    /// also write the log to this file
+    pub file: Option<PathBuf>,
+    /// the size in bytes at which the file is rotated
+    pub max_size: u64,
+    /// how many rotated files are kept, `log.1` being the newest
+    pub files: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            file: None,
+            max_size: 1024 * 1024,
+            files: 3,
+        }
+    }
+}
+
+fn deserialize_level<'de, D: Deserializer<'de>>(deserializer: D) -> Result<LevelFilter, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    LevelFilter::from_str(&s).map_err(|_| {
+        de::Error::custom(format!(
+            "invalid level {s}, expected off, error, warn, info, debug or trace"
+        ))
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    /// in milliseconds since the unix epoch
+    pub timestamp: u64,
+    pub level: Level,
+    /// the module that logged it
+    pub target: String,
+    pub message: String,
+}
+
+impl LogRecord {
+    /// The record is at least as severe as `level`, and has
+    /// every word of `search` in its target or message, ignoring case.
+    pub fn matches(&self, level: LevelFilter, search: &str) -> bool {
+        if self.level > level {
+            return false;
+        }
+        let text = format!("{} {}", self.target, self.message).to_lowercase();
+        search
+            .split_whitespace()
+            .all(|word| text.contains(&word.to_lowercase()))
+    }
+}
+
+/// The last `LOG_CAPACITY` records, shared by the logger and the console.
+#[derive(Debug, Clone, Default)]
+pub struct LogBuffer(Arc<Mutex<VecDeque<LogRecord>>>);
+
+impl LogBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, record: LogRecord) {
+        let mut records = self.0.lock().unwrap();
+        if records.len() == LOG_CAPACITY {
+            records.pop_front();
+        }
+        records.push_back(record);
+    }
+
+    /// The records, oldest first.
+    pub fn records(&self) -> Vec<LogRecord> {
+        self.0.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+/// A file that is moved to `<file>.1` once it reaches `max_size`,
+/// `<file>.1` to `<file>.2`, and so on, up to `files` of them.
+#[derive(Debug)]
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Append to the file at `path`, creating it if it doesn't exist.
+    pub fn open(path: &Path, max_size: u64, files: usize) -> io::Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            max_size,
+            files,
+            file,
+            size,
+        })
+    }
+
+    /// Write `line` and a newline, rotating first if it would not fit.
+    pub fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.size > 0 && self.size + len > self.max_size {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{line}")?;
+        self.size += len;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |i: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{i}"));
+            PathBuf::from(name)
+        };
+        if self.files > 0 {
+            // the oldest one is overwritten
+            for i in (1..self.files).rev() {
+                match fs::rename(rotated(i), rotated(i + 1)) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+                    _ => {}
+                }
+            }
+            fs::rename(&self.path, rotated(1))?;
+        }
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+/// Keeps the records in a `LogBuffer`, and in a `RotatingFile` if there is one.
+#[derive(Debug)]
+pub struct Logger {
+    level: LevelFilter,
+    buffer: LogBuffer,
+    file: Option<Mutex<RotatingFile>>,
+}
+
+impl Logger {
+    /// A logger for `config`, `RUST_LOG` overrides its level
+    /// when it is only a level, e.g. `RUST_LOG=debug`.
+    pub fn new(config: &LogConfig) -> io::Result<Self> {
+        let level = std::env::var("RUST_LOG")
+            .ok()
+            .and_then(|level| level.parse().ok())
+            .unwrap_or(config.level);
+        let file = match &config.file {
+            Some(path) => Some(Mutex::new(RotatingFile::open(
+                path,
+                config.max_size,
+                config.files,
+            )?)),
+            None => None,
+        };
+        Ok(Self {
+            level,
+            buffer: LogBuffer::new(),
+            file,
+        })
+    }
+
+    /// The records this logger keeps.
+    pub fn buffer(&self) -> LogBuffer {
+        self.buffer.clone()
+    }
+
+    /// Make this the logger of the `log` macros.
+    pub fn install(self) -> Result<(), SetLoggerError> {
+        log::set_max_level(self.level);
+        log::set_boxed_logger(Box::new(self))
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let record = LogRecord {
+            timestamp: now_millis(),
+            level: record.level(),
+            target: record.target().to_string(),
+            message: record.args().to_string(),
+        };
+        if let Some(file) = &self.file {
+            let line = format!(
+                "{} {:5} {}: {}",
+                format_datetime(record.timestamp),
+                record.level,
+                record.target,
+                record.message
+            );
+            // there is nowhere to report it
+            let _ = file.lock().unwrap().write_line(&line);
+        }
+        self.buffer.push(record);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            let _ = file.lock().unwrap().file.flush();
+        }
+    }
+}
@@ -1,40 +1,125 @@
-mod app;
-mod chat_room;
-mod models;
-mod topic_list;
-mod ui;
-use app::{AppHandle, AppMessage};
+mod cli;
+mod commands;
 
-use models::network::{NetworkMessage, Network};
-use tokio::sync::mpsc::{self, Receiver, Sender};
-
-#[tokio::main]
-async fn start_tokio(tx: Sender<NetworkMessage>, rx: Receiver<NetworkMessage>, tx_app: Sender<AppMessage>) {
-    let mut network = Network::new(tx, rx);
-    network.daemon(tx_app).await;
-}
+use clap::Parser;
+use cli::Opt;
+use log::error;
+use p2p_chat::{
+    app::{restore_terminal, AppHandle, AppMessage},
+    config::Config,
+    logger::Logger,
+    models::control,
+    Supervisor, TaskResult,
+};
+use tokio::{
+    select,
+    signal::unix::{signal, SignalKind},
+    sync::mpsc::{self, Sender},
+};
 
 #[tokio::main]
 async fn main() -> Result<(), String> {
-    pretty_env_logger::init();
+    let opt = Opt::parse();
 
-    // `Network` will own these channels, but
-    // `Frontend` will also have a `tx` to it.
-    let (tx_network, rx_network) = mpsc::channel::<NetworkMessage>(200);
-    let tx_network_cloned = tx_network.clone();
+    if let Some(command) = &opt.command {
+        pretty_env_logger::init();
+        return commands::run(command.clone(), &opt).await;
+    }
+    if opt.headless {
+        pretty_env_logger::init();
+        return headless(&opt).await;
+    }
 
-    // `Network` will communicate with the frontend,
-    // using this `tx`.
-    let (tx_app, rx_app) = mpsc::channel::<AppMessage>(200);
-    let tx_app_cloned = tx_app.clone();
+    // the TUI keeps its log away from the screen. It is set up before
+    // the node starts, to keep its first records and to not start the
+    // network with a config that is invalid.
+    let config = Config::load(&opt.config).map_err(|e| e.to_string())?;
+    let logger = Logger::new(&config.log).map_err(|e| format!("could not open the log: {e}"))?;
+    let log = logger.buffer();
+    logger.install().map_err(|e| e.to_string())?;
+
+    // the network, the storage and the UI all run
+    // on this runtime, as tasks of the supervisor.
+    let mut supervisor = Supervisor::new();
+    let (node, driver) = opt.node_builder().build();
+    driver
+        .supervise(&mut supervisor)
+        .map_err(|e| e.to_string())?;
 
-    AppHandle::new(tx_app, rx_app, tx_network);
+    let (tx_app, rx_app) = mpsc::channel::<AppMessage>(200);
+    let app = AppHandle::new(tx_app, rx_app, node.clone(), config, log, &mut supervisor)
+        .map_err(|e| format!("could not start the terminal: {e}"))?;
+    supervisor.spawn("signals", quit_on_signal(app.tx.clone()));
 
-    let daemon_handle = std::thread::spawn(move || {
-        start_tokio(tx_network_cloned, rx_network, tx_app_cloned);
+    // if any task dies, take the others down with it
+    let tx = app.tx.clone();
+    supervisor.on_failure(move |_| {
+        let _ = restore_terminal();
+        let _ = tx.try_send(AppMessage::Quit);
+        let node = node.clone();
+        tokio::spawn(async move { node.quit().await });
     });
 
-    daemon_handle.join().unwrap();
+    supervisor.run().await.map_err(|e| e.to_string())
+}
+
+/// Run only the node, with the control socket.
+async fn headless(opt: &Opt) -> Result<(), String> {
+    let mut supervisor = Supervisor::new();
+    let (node, driver) = opt.node_builder().build();
+    driver
+        .supervise(&mut supervisor)
+        .map_err(|e| e.to_string())?;
+
+    // stop when either the control socket or the tasks fail,
+    // or when we are asked to with a signal
+    let tasks = supervisor.run();
+    tokio::pin!(tasks);
+    let served = select! {
+        result = control::serve(&opt.socket, node.clone()) => {
+            result.map_err(|e| format!("control socket failed: {e}"))
+        }
+        result = &mut tasks => {
+            return result.map_err(|e| e.to_string());
+        }
+        _ = shutdown_signal() => Ok(()),
+    };
+    // stop the node in order, even when the socket failed
+    let _ = node.quit().await;
+    let stopped = tasks.await.map_err(|e| e.to_string());
+    if served.is_ok() {
+        let _ = std::fs::remove_file(&opt.socket);
+    }
+    served.and(stopped)
+}
 
+/// Ask the app to quit on a signal, the app
+/// restores the terminal and stops the node.
+async fn quit_on_signal(tx: Sender<AppMessage>) -> TaskResult {
+    select! {
+        _ = shutdown_signal() => {
+            let _ = tx.send(AppMessage::Quit).await;
+        }
+        _ = tx.closed() => {}
+    }
     Ok(())
 }
+
+/// Wait for SIGINT, SIGTERM or SIGHUP.
+///
+/// In the TUI the terminal is in raw mode, so Ctrl-C arrives
+/// as a key press instead, see `ui`.
+async fn shutdown_signal() {
+    let (Ok(mut term), Ok(mut hup)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+    ) else {
+        error!("could not install the signal handlers");
+        return std::future::pending().await;
+    };
+    select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = term.recv() => {}
+        _ = hup.recv() => {}
+    }
+}
@@ -0,0 +1,256 @@
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    edit::{Edits, Version},
+    history::RoomHistory,
+    message::Edit,
+    storage::{Entry, Record},
+    time::format_datetime,
+};
+
+/// What a room can be exported to. Only JSON Lines can be imported back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// the lines of the history file, with the nicknames
+    #[default]
+    Jsonl,
+    Markdown,
+    Text,
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "jsonl" => Ok(Self::Jsonl),
+            "markdown" | "md" => Ok(Self::Markdown),
+            "text" | "txt" => Ok(Self::Text),
+            _ => Err(format!(
+                "unknown format {s}, expected jsonl, markdown or text"
+            )),
+        }
+    }
+}
+
+/// A line of a JSON Lines export.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ArchiveRecord {
+    #[serde(flatten)]
+    pub record: Record,
+    /// the nickname of the author, as the exporter knew them,
+    /// it is not signed and ignored on import
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nickname: Option<String>,
+}
+
+/// What happened to the records of an import.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ImportReport {
+    /// added to the history
+    pub imported: usize,
+    /// already in the history, not signed so they can't be trusted,
+    /// or the reactions and edits of messages we don't have
+    pub skipped: usize,
+    /// the signature is invalid, or not made by the author
+    pub rejected: usize,
+}
+
+impl ImportReport {
+    pub fn add(&mut self, other: ImportReport) {
+        self.imported += other.imported;
+        self.skipped += other.skipped;
+        self.rejected += other.rejected;
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} imported, {} skipped, {} rejected",
+            self.imported, self.skipped, self.rejected
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct ImportError {
+    /// starting at 1
+    pub line: usize,
+    pub error: serde_json::Error,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid record on line {}: {}", self.line, self.error)
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Export the history of `room` in `format`. `nicknames` are
+/// the names we know the authors by, by `PeerId`.
+pub fn export(
+    room: &str,
+    history: &RoomHistory,
+    nicknames: &HashMap<String, String>,
+    format: ExportFormat,
+) -> String {
+    match format {
+        ExportFormat::Jsonl => export_jsonl(room, history, nicknames),
+        ExportFormat::Markdown | ExportFormat::Text => {
+            export_document(room, history, nicknames, format)
+        }
+    }
+}
+
+fn export_jsonl(room: &str, history: &RoomHistory, nicknames: &HashMap<String, String>) -> String {
+    let entries = history
+        .messages
+        .iter()
+        .map(|m| (&m.author, Entry::Message(m.clone())))
+        .chain(
+            history
+                .edits
+                .iter()
+                .map(|e| (&e.author, Entry::Edit(e.clone()))),
+        )
+        .chain(
+            history
+                .reactions
+                .iter()
+                .map(|r| (&r.author, Entry::Reaction(r.clone()))),
+        );
+
+    let mut out = String::new();
+    for (author, entry) in entries {
+        let record = ArchiveRecord {
+            record: Record {
+                room: room.to_string(),
+                entry,
+            },
+            nickname: nicknames.get(author).cloned(),
+        };
+        out.push_str(&serde_json::to_string(&record).expect("records to be serializable"));
+        out.push('\n');
+    }
+    out
+}
+
+/// Markdown and plain text, for people to read.
+fn export_document(
+    room: &str,
+    history: &RoomHistory,
+    nicknames: &HashMap<String, String>,
+    format: ExportFormat,
+) -> String {
+    let markdown = format == ExportFormat::Markdown;
+    let code = |s: &str| {
+        if markdown {
+            format!("`{s}`")
+        } else {
+            s.to_string()
+        }
+    };
+    let mut edits = Edits::default();
+    for edit in &history.edits {
+        edits.apply(edit.clone());
+    }
+
+    let mut out = if markdown {
+        format!("# {room}\n")
+    } else {
+        format!("{room}\n")
+    };
+    for m in &history.messages {
+        let author = match nicknames.get(&m.author) {
+            Some(nickname) => format!("{nickname} ({})", code(&m.author)),
+            None => code(&m.author),
+        };
+        let body = match edits.current(m) {
+            Version::Original(body) => body.to_string(),
+            Version::Edited(body) => format!("{body} (edited)"),
+            Version::Deleted => "(deleted)".to_string(),
+        };
+
+        let mut details = vec![format!("id: {}", code(&m.id))];
+        if let Some(parent) = &m.reply_to {
+            details.push(format!("reply to: {}", code(parent)));
+        }
+        details.extend(signature(&m.public_key, &m.signature, &code));
+        for edit in edits.history(m) {
+            details.push(edit_line(edit, &code));
+        }
+
+        out.push('\n');
+        if markdown {
+            out.push_str(&format!(
+                "### {} · {author}\n",
+                format_datetime(m.timestamp)
+            ));
+            for line in body.lines() {
+                out.push_str(&format!("> {line}\n"));
+            }
+            out.push('\n');
+            for detail in details {
+                out.push_str(&format!("- {detail}\n"));
+            }
+        } else {
+            out.push_str(&format!("[{}] {author}\n", format_datetime(m.timestamp)));
+            for line in body.lines() {
+                out.push_str(&format!("    {line}\n"));
+            }
+            for detail in details {
+                out.push_str(&format!("  {detail}\n"));
+            }
+        }
+    }
+    out
+}
+
+fn signature(public_key: &str, signature: &str, code: &impl Fn(&str) -> String) -> Vec<String> {
+    if signature.is_empty() {
+        return vec!["not signed".to_string()];
+    }
+    vec![
+        format!("public key: {}", code(public_key)),
+        format!("signature: {}", code(signature)),
+    ]
+}
+
+fn edit_line(edit: &Edit, code: &impl Fn(&str) -> String) -> String {
+    let change = match &edit.body {
+        Some(body) => format!("edited to \"{body}\""),
+        None => "deleted".to_string(),
+    };
+    format!(
+        "{change} at {}, signature: {}",
+        format_datetime(edit.timestamp),
+        code(&edit.signature)
+    )
+}
+
+/// Read a JSON Lines export, the records are grouped by room.
+/// The signatures are checked by the node that imports them.
+pub fn import(text: &str) -> Result<HashMap<String, RoomHistory>, ImportError> {
+    let mut rooms: HashMap<String, RoomHistory> = HashMap::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let ArchiveRecord { record, .. } =
+            serde_json::from_str(line).map_err(|error| ImportError { line: i + 1, error })?;
+        let room = rooms.entry(record.room).or_default();
+        match record.entry {
+            Entry::Message(message) => room.messages.push(message),
+            Entry::Edit(edit) => room.edits.push(edit),
+            Entry::Reaction(reaction) => room.reactions.push(reaction),
+            // the outbox is not part of an export
+            Entry::Queued(_) | Entry::Dequeued { .. } => {}
+        }
+    }
+    Ok(rooms)
+}
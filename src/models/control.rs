@@ -0,0 +1,428 @@
+use std::{
+    collections::VecDeque,
+    fs::{DirBuilder, Permissions},
+    io,
+    os::unix::fs::{DirBuilderExt, PermissionsExt},
+    path::Path,
+};
+
+use log::info;
+use serde::{Deserialize, Deserializer};
+use serde_json::{json, Value};
+use tokio::{
+    io::{
+        self as tokio_io, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, Lines,
+        ReadHalf, WriteHalf,
+    },
+    net::{UnixListener, UnixStream},
+    select,
+};
+
+use super::{
+    history::RoomHistory, message::ChatMessage, network::NetworkEvent, presence::PresenceState,
+    search::SearchQuery,
+};
+use crate::node::{ChatNode, EventStream, NodeError};
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// A JSON-RPC request, one per line.
+#[derive(Debug, Deserialize)]
+struct Request {
+    /// always `"2.0"`
+    jsonrpc: String,
+    /// `None` for a notification, which gets no response, `null` is an id
+    #[serde(default, deserialize_with = "present")]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoomParams {
+    room: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SendParams {
+    room: String,
+    message: String,
+    /// the id of the message to reply to
+    #[serde(default)]
+    reply_to: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ResendParams {
+    room: String,
+    /// the message as returned by `send`
+    message: ChatMessage,
+}
+
+#[derive(Debug, Deserialize)]
+struct SeenParams {
+    room: String,
+    message_ids: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PresenceParams {
+    state: PresenceState,
+    #[serde(default)]
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    room: String,
+    #[serde(flatten)]
+    history: RoomHistory,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageParams {
+    room: String,
+    message_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EditParams {
+    room: String,
+    message_id: String,
+    message: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReactParams {
+    room: String,
+    message_id: String,
+    emoji: String,
+    /// take the reaction back instead
+    #[serde(default)]
+    remove: bool,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+/// Listen on a unix socket and serve the control API, which lets
+/// scripts, bots and other clients drive a running `ChatNode`.
+///
+/// The protocol is newline-delimited JSON-RPC 2.0 with the methods:
+/// `join`, `leave`, `send`, `react`, `edit`, `delete`, `edit_history`,
+/// `resend`, `mark_seen`, `typing`, `set_presence`, `presence`, `history`,
+/// `search`, `import`, `peers`, `room_peers`, `rooms`, `stored_rooms`,
+/// `status`, `id` and `subscribe`. After
+/// `subscribe`, every `NetworkEvent` is pushed to the client as an `event` notification.
+///
+/// The socket is only for the user running the daemon. If another daemon
+/// answers on `path`, this fails instead of taking the socket away from it.
+pub async fn serve(path: &Path, node: ChatNode) -> io::Result<()> {
+    if path.exists() {
+        if UnixStream::connect(path).await.is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("another daemon is listening on {}", path.display()),
+            ));
+        }
+        // a previous daemon left the socket behind
+        std::fs::remove_file(path)?;
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.exists()) {
+        DirBuilder::new().recursive(true).mode(0o700).create(dir)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, Permissions::from_mode(0o600))?;
+    info!("control socket listening on {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let node = node.clone();
+
+        tokio::spawn(async move {
+            if let Err(e) = handle_client(stream, node).await {
+                info!("control client disconnected {:?}", e);
+            }
+        });
+    }
+}
+
+/// Serve the control API to a single client, until it disconnects.
+pub async fn handle_client<S: AsyncRead + AsyncWrite>(stream: S, node: ChatNode) -> io::Result<()> {
+    let (reader, mut writer) = tokio_io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+    let mut events: Option<EventStream> = None;
+
+    loop {
+        select! {
+            line = lines.next_line() => {
+                let Some(line) = line? else { return Ok(()) };
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let request = serde_json::from_str::<Value>(&line)
+                    .map_err(|e| RpcError::new(PARSE_ERROR, e.to_string()))
+                    .and_then(|value| {
+                        serde_json::from_value::<Request>(value)
+                            .map_err(|e| RpcError::new(INVALID_REQUEST, e.to_string()))
+                    });
+                let response = match request {
+                    Ok(req) if req.jsonrpc != "2.0" => Some(response(
+                        req.id.unwrap_or_default(),
+                        Err(RpcError::new(INVALID_REQUEST, r#"jsonrpc must be "2.0""#)),
+                    )),
+                    Ok(req) => {
+                        let result = if req.method == "subscribe" {
+                            events = Some(node.events());
+                            Ok(json!(true))
+                        } else {
+                            call(&req.method, req.params, &node).await
+                        };
+                        req.id.map(|id| response(id, result))
+                    }
+                    Err(e) => Some(response(Value::Null, Err(e))),
+                };
+                if let Some(response) = response {
+                    write_line(&mut writer, &response).await?;
+                }
+            },
+            Some(event) = next_event(&mut events) => {
+                let notification = json!({
+                    "jsonrpc": "2.0",
+                    "method": "event",
+                    "params": event,
+                });
+                write_line(&mut writer, &notification).await?;
+            },
+        }
+    }
+}
+
+async fn call(method: &str, params: Value, node: &ChatNode) -> Result<Value, RpcError> {
+    match method {
+        "join" => {
+            let p: RoomParams = parse_params(params)?;
+            node.join(&p.room).await?;
+            Ok(json!(true))
+        }
+        "leave" => {
+            let p: RoomParams = parse_params(params)?;
+            node.leave(&p.room).await?;
+            Ok(json!(true))
+        }
+        "resend" => {
+            let p: ResendParams = parse_params(params)?;
+            Ok(json!(node.resend(&p.room, p.message).await?))
+        }
+        "mark_seen" => {
+            let p: SeenParams = parse_params(params)?;
+            node.mark_seen(&p.room, p.message_ids).await?;
+            Ok(json!(true))
+        }
+        "set_presence" => {
+            let p: PresenceParams = parse_params(params)?;
+            node.set_presence(p.state, &p.status).await?;
+            Ok(json!(true))
+        }
+        "presence" => Ok(json!(node.presence().await?)),
+        "typing" => {
+            let p: RoomParams = parse_params(params)?;
+            node.typing(&p.room).await?;
+            Ok(json!(true))
+        }
+        "send" => {
+            let p: SendParams = parse_params(params)?;
+            let message = match &p.reply_to {
+                Some(parent) => node.reply(&p.room, parent, &p.message).await?,
+                None => node.send(&p.room, &p.message).await?,
+            };
+            Ok(json!(message))
+        }
+        "react" => {
+            let p: ReactParams = parse_params(params)?;
+            let reaction = node
+                .react(&p.room, &p.message_id, &p.emoji, !p.remove)
+                .await?;
+            Ok(json!(reaction))
+        }
+        "edit" => {
+            let p: EditParams = parse_params(params)?;
+            Ok(json!(node.edit(&p.room, &p.message_id, &p.message).await?))
+        }
+        "delete" => {
+            let p: MessageParams = parse_params(params)?;
+            Ok(json!(node.delete(&p.room, &p.message_id).await?))
+        }
+        "edit_history" => {
+            let p: MessageParams = parse_params(params)?;
+            Ok(json!(node.edit_history(&p.room, &p.message_id).await?))
+        }
+        "history" => {
+            let p: RoomParams = parse_params(params)?;
+            Ok(json!(node.history(&p.room).await?))
+        }
+        "import" => {
+            let p: ImportParams = parse_params(params)?;
+            Ok(json!(node.import(&p.room, p.history).await?))
+        }
+        "search" => {
+            let query: SearchQuery = parse_params(params)?;
+            Ok(json!(node.search(query).await?))
+        }
+        "peers" => {
+            let peers: Vec<String> = node.peers().await?.iter().map(|p| p.to_string()).collect();
+            Ok(json!(peers))
+        }
+        "room_peers" => {
+            let p: RoomParams = parse_params(params)?;
+            let peers = node.room_peers(&p.room).await?;
+            Ok(json!(peers
+                .iter()
+                .map(|p| p.to_string())
+                .collect::<Vec<_>>()))
+        }
+        "rooms" => Ok(json!(node.rooms().await?)),
+        "stored_rooms" => Ok(json!(node.stored_rooms().await?)),
+        "status" => Ok(json!(node.status().await?)),
+        "id" => Ok(json!(node.peer_id().to_string())),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("unknown method {method}"),
+        )),
+    }
+}
+
+impl From<NodeError> for RpcError {
+    fn from(e: NodeError) -> Self {
+        RpcError::new(INTERNAL_ERROR, e.to_string())
+    }
+}
+
+/// An `id` that is in the request, even if it is `null`.
+fn present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Value>, D::Error> {
+    Value::deserialize(deserializer).map(Some)
+}
+
+fn parse_params<T: for<'de> Deserialize<'de>>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": e.code, "message": e.message },
+        }),
+    }
+}
+
+/// Wait for the next event, or forever if the client is not subscribed.
+async fn next_event(events: &mut Option<EventStream>) -> Option<NetworkEvent> {
+    match events {
+        Some(events) => events.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn write_line<W: AsyncWriteExt + Unpin>(writer: &mut W, value: &Value) -> io::Result<()> {
+    let mut line = value.to_string();
+    line.push('\n');
+    writer.write_all(line.as_bytes()).await
+}
+
+/// A client of the control API.
+pub struct Client<S> {
+    lines: Lines<BufReader<ReadHalf<S>>>,
+    writer: WriteHalf<S>,
+    next_id: u64,
+    /// notifications that arrived while waiting for a response
+    events: VecDeque<Value>,
+}
+
+impl Client<UnixStream> {
+    pub async fn connect(path: &Path) -> io::Result<Self> {
+        Ok(Self::new(UnixStream::connect(path).await?))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite> Client<S> {
+    pub fn new(stream: S) -> Self {
+        let (reader, writer) = tokio_io::split(stream);
+        Self {
+            lines: BufReader::new(reader).lines(),
+            writer,
+            next_id: 0,
+            events: VecDeque::new(),
+        }
+    }
+
+    /// Call `method` and wait for its result.
+    pub async fn call(&mut self, method: &str, params: Value) -> io::Result<Value> {
+        self.next_id += 1;
+        let id = self.next_id;
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": params,
+        });
+        write_line(&mut self.writer, &request).await?;
+
+        loop {
+            let mut value = self.read().await?;
+            if value.get("method").is_some() {
+                self.events.push_back(value);
+                continue;
+            }
+            if value["id"] != id {
+                continue;
+            }
+            if let Some(error) = value.get("error") {
+                let message = error["message"].as_str().unwrap_or("unknown error");
+                return Err(io::Error::other(message.to_string()));
+            }
+            return Ok(value["result"].take());
+        }
+    }
+
+    /// Wait for the next event, after a call to `subscribe`.
+    pub async fn next_event(&mut self) -> io::Result<Value> {
+        loop {
+            let mut value = match self.events.pop_front() {
+                Some(value) => value,
+                None => self.read().await?,
+            };
+            if value["method"] == "event" {
+                return Ok(value["params"].take());
+            }
+        }
+    }
+
+    async fn read(&mut self) -> io::Result<Value> {
+        let line =
+            self.lines.next_line().await?.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "control socket closed")
+            })?;
+        serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
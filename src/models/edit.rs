@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+
+use super::message::{ChatMessage, Edit};
+
+/// What is shown for a message, after its edits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version<'a> {
+    Original(&'a str),
+    Edited(&'a str),
+    Deleted,
+}
+
+/// The edits of the messages of a room.
+///
+/// Edits are kept even if they come before their message, but only
+/// the ones signed by the author of the message are ever applied.
+#[derive(Debug, Clone, Default)]
+pub struct Edits {
+    /// message id -> edits, oldest first
+    by_message: HashMap<String, Vec<Edit>>,
+}
+
+impl Edits {
+    /// Add `edit`, which must be verified already.
+    /// Returns `false` if it was already known.
+    pub fn apply(&mut self, edit: Edit) -> bool {
+        let edits = self.by_message.entry(edit.message_id.clone()).or_default();
+        if edits.iter().any(|e| e.signature == edit.signature) {
+            return false;
+        }
+        let i = edits.partition_point(|e| e.timestamp <= edit.timestamp);
+        edits.insert(i, edit);
+        true
+    }
+
+    /// The edits of `message` made by its author, oldest first.
+    pub fn history<'a>(&'a self, message: &'a ChatMessage) -> impl Iterator<Item = &'a Edit> {
+        self.by_message
+            .get(&message.id)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.author == message.author)
+    }
+
+    /// What should be shown for `message`.
+    pub fn current<'a>(&'a self, message: &'a ChatMessage) -> Version<'a> {
+        match self.history(message).last() {
+            None => Version::Original(&message.body),
+            Some(Edit {
+                body: Some(body), ..
+            }) => Version::Edited(body),
+            Some(Edit { body: None, .. }) => Version::Deleted,
+        }
+    }
+
+    /// Every edit of `message_id`, to share them with a peer.
+    pub fn of(&self, message_id: &str) -> impl Iterator<Item = &Edit> {
+        self.by_message.get(message_id).into_iter().flatten()
+    }
+
+    /// Forget the edits of a message that is gone.
+    pub fn remove_message(&mut self, message_id: &str) {
+        self.by_message.remove(message_id);
+    }
+}
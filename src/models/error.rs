@@ -0,0 +1,101 @@
+use std::fmt;
+
+use serde::Serialize;
+
+/// Something that went wrong in the `Network`.
+///
+/// Once the node runs, these are reported as `NetworkEvent::Error` and
+/// the node keeps running. The errors while it starts, like `Setup`,
+/// `Listen` or reading the history, stop it instead, as does a `Task`
+/// that fails for good: `NodeDriver::run` returns them.
+/// The libp2p errors are kept as strings, so the events can be cloned.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum NetworkError {
+    /// the node could not be created
+    Setup {
+        reason: String,
+    },
+    /// we could not listen on the listen address, the node doesn't start
+    Listen {
+        address: String,
+        reason: String,
+    },
+    Dial {
+        address: String,
+        reason: String,
+    },
+    Subscribe {
+        topic: String,
+        reason: String,
+    },
+    Unsubscribe {
+        topic: String,
+        reason: String,
+    },
+    Publish {
+        topic: String,
+        reason: String,
+    },
+    /// a peer sent something that we could not understand
+    InvalidMessage {
+        peer_id: String,
+        reason: String,
+    },
+    /// we tried to edit a message that is not ours
+    Edit {
+        message_id: String,
+        reason: String,
+    },
+    /// we tried to send again a message that is not ours,
+    /// or that did not fail
+    Resend {
+        message_id: String,
+        reason: String,
+    },
+    /// the history could not be read or written
+    Storage {
+        reason: String,
+    },
+    /// a task of the node failed and could not be restarted
+    Task {
+        task: String,
+        reason: String,
+    },
+}
+
+impl fmt::Display for NetworkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NetworkError::Setup { reason } => write!(f, "could not start the node: {reason}"),
+            NetworkError::Listen { address, reason } => {
+                write!(f, "could not listen on {address}: {reason}")
+            }
+            NetworkError::Dial { address, reason } => {
+                write!(f, "could not dial {address}: {reason}")
+            }
+            NetworkError::Subscribe { topic, reason } => {
+                write!(f, "could not join room {topic}: {reason}")
+            }
+            NetworkError::Unsubscribe { topic, reason } => {
+                write!(f, "could not leave room {topic}: {reason}")
+            }
+            NetworkError::Publish { topic, reason } => {
+                write!(f, "could not send message to room {topic}: {reason}")
+            }
+            NetworkError::InvalidMessage { peer_id, reason } => {
+                write!(f, "invalid message from {peer_id}: {reason}")
+            }
+            NetworkError::Edit { message_id, reason } => {
+                write!(f, "could not edit message {message_id}: {reason}")
+            }
+            NetworkError::Resend { message_id, reason } => {
+                write!(f, "could not resend message {message_id}: {reason}")
+            }
+            NetworkError::Storage { reason } => write!(f, "could not store the history: {reason}"),
+            NetworkError::Task { task, reason } => write!(f, "the {task} task failed: {reason}"),
+        }
+    }
+}
+
+impl std::error::Error for NetworkError {}
@@ -0,0 +1,225 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::Path,
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use super::{
+    edit::{Edits, Version},
+    message::{ChatMessage, Edit, Reaction},
+    outbox::Outbox,
+    reaction::Reactions,
+    search::{SearchHit, SearchQuery},
+    storage::{Entry, Record},
+};
+
+/// How many messages are kept per room.
+const MAX_MESSAGES: usize = 1000;
+
+/// How many messages a search returns, the latest ones.
+const MAX_SEARCH_HITS: usize = 100;
+
+/// The stored messages of a room, with their reactions and edits.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RoomHistory {
+    /// oldest first
+    pub messages: Vec<ChatMessage>,
+    pub reactions: Vec<Reaction>,
+    pub edits: Vec<Edit>,
+    /// the ids of our messages that still wait in the outbox
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub queued: Vec<String>,
+}
+
+/// The messages of the rooms we are in, so we
+/// can share them with peers that join later.
+#[derive(Debug, Default)]
+pub struct History {
+    rooms: HashMap<String, Vec<ChatMessage>>,
+    seen: HashSet<String>,
+    reactions: Reactions,
+    edits: Edits,
+    /// our messages that no one got yet
+    pub outbox: Outbox,
+}
+
+impl History {
+    /// Load the history from the JSON lines file at `path`,
+    /// it is empty if the file doesn't exist yet.
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let mut history = History::default();
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(history),
+            Err(e) => return Err(e),
+        };
+
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str::<Record>(&line) {
+                Ok(Record {
+                    room,
+                    entry: Entry::Message(message),
+                }) => {
+                    history.insert(&room, message);
+                }
+                Ok(Record {
+                    entry: Entry::Reaction(reaction),
+                    ..
+                }) => {
+                    history.react(reaction);
+                }
+                Ok(Record {
+                    entry: Entry::Edit(edit),
+                    ..
+                }) => {
+                    history.edit(edit);
+                }
+                Ok(Record {
+                    room,
+                    entry: Entry::Queued(message),
+                }) => {
+                    history.outbox.push(&room, message);
+                }
+                Ok(Record {
+                    room,
+                    entry: Entry::Dequeued { message_id },
+                }) => {
+                    history.outbox.remove(&room, &message_id);
+                }
+                Err(e) => warn!("skipping invalid line in {}: {e}", path.display()),
+            }
+        }
+
+        Ok(history)
+    }
+
+    /// Store `message`, returns `false` if it was already stored.
+    pub fn insert(&mut self, room: &str, message: ChatMessage) -> bool {
+        if !self.seen.insert(message.id.clone()) {
+            return false;
+        }
+
+        let messages = self.rooms.entry(room.to_string()).or_default();
+        // history from other peers may arrive out of order
+        let i = messages.partition_point(|m| m.timestamp <= message.timestamp);
+        messages.insert(i, message);
+
+        if messages.len() > MAX_MESSAGES {
+            let removed = messages.remove(0);
+            self.seen.remove(&removed.id);
+            self.reactions.remove_message(&removed.id);
+            self.edits.remove_message(&removed.id);
+        }
+        true
+    }
+
+    /// Apply `reaction`, returns `false` if it changed nothing.
+    pub fn react(&mut self, reaction: Reaction) -> bool {
+        self.reactions.apply(reaction)
+    }
+
+    /// Add a verified `edit`, returns `false` if it was already known.
+    pub fn edit(&mut self, edit: Edit) -> bool {
+        self.edits.apply(edit)
+    }
+
+    pub fn message(&self, room: &str, id: &str) -> Option<&ChatMessage> {
+        self.rooms.get(room)?.iter().find(|m| m.id == id)
+    }
+
+    /// The edits of the message `id` by its author, oldest first.
+    pub fn edit_history(&self, room: &str, id: &str) -> Vec<Edit> {
+        match self.message(room, id) {
+            Some(message) => self.edits.history(message).cloned().collect(),
+            None => vec![],
+        }
+    }
+
+    /// The edits of `messages`, to share them with a peer.
+    pub fn edits_to(&self, messages: &[ChatMessage]) -> Vec<Edit> {
+        messages
+            .iter()
+            .flat_map(|m| self.edits.of(&m.id))
+            .cloned()
+            .collect()
+    }
+
+    /// The reactions to `messages`, to share them with a peer.
+    pub fn reactions_to(&self, messages: &[ChatMessage]) -> Vec<Reaction> {
+        messages
+            .iter()
+            .flat_map(|m| self.reactions.of(&m.id))
+            .cloned()
+            .collect()
+    }
+
+    /// The rooms with stored messages, sorted.
+    pub fn rooms(&self) -> Vec<String> {
+        let mut rooms: Vec<String> = self
+            .rooms
+            .iter()
+            .filter(|(_, messages)| !messages.is_empty())
+            .map(|(room, _)| room.clone())
+            .collect();
+        rooms.sort();
+        rooms
+    }
+
+    /// Everything stored about `room`.
+    pub fn room(&self, room: &str) -> RoomHistory {
+        let messages = self.last(room, MAX_MESSAGES).to_vec();
+        RoomHistory {
+            reactions: self.reactions_to(&messages),
+            edits: self.edits_to(&messages),
+            queued: self.outbox.ids(room),
+            messages,
+        }
+    }
+
+    /// The messages that match `query`, newest first.
+    pub fn search(&self, query: &SearchQuery) -> Vec<SearchHit> {
+        let mut hits = vec![];
+        for (room, messages) in &self.rooms {
+            for (i, message) in messages.iter().enumerate() {
+                let Some(shown) = self.shown(message) else {
+                    continue;
+                };
+                if !query.matches(room, message, &shown.body) {
+                    continue;
+                }
+                hits.push(SearchHit {
+                    room: room.clone(),
+                    message: shown,
+                    previous: i.checked_sub(1).and_then(|i| self.shown(&messages[i])),
+                    next: messages.get(i + 1).and_then(|m| self.shown(m)),
+                });
+            }
+        }
+        hits.sort_by_key(|hit| std::cmp::Reverse(hit.message.timestamp));
+        hits.truncate(MAX_SEARCH_HITS);
+        hits
+    }
+
+    /// `message` with the body after its edits, `None` if it was deleted.
+    fn shown(&self, message: &ChatMessage) -> Option<ChatMessage> {
+        match self.edits.current(message) {
+            Version::Original(body) | Version::Edited(body) => Some(ChatMessage {
+                body: body.to_string(),
+                ..message.clone()
+            }),
+            Version::Deleted => None,
+        }
+    }
+
+    /// The last `n` messages of `room`, oldest first.
+    pub fn last(&self, room: &str, n: usize) -> &[ChatMessage] {
+        let messages = self.rooms.get(room).map(Vec::as_slice).unwrap_or_default();
+        &messages[messages.len().saturating_sub(n)..]
+    }
+}
@@ -0,0 +1,291 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use libp2p::{
+    identity::{Keypair, PublicKey},
+    PeerId,
+};
+use serde::{Deserialize, Serialize};
+
+use super::presence::Presence;
+
+/// A message sent by someone to a room.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    /// unique id, used to deduplicate messages
+    pub id: String,
+    /// the `PeerId` of the author
+    pub author: String,
+    pub body: String,
+    /// milliseconds since the unix epoch, as told by the author
+    pub timestamp: u64,
+    /// the id of the message this one replies to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// the protobuf encoded public key of `author`, in hex,
+    /// empty if the message is not signed
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub public_key: String,
+    /// the signature of everything above, in hex
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+impl ChatMessage {
+    pub fn new(author: &PeerId, body: String) -> Self {
+        Self {
+            id: format!("{:016x}", rand::random::<u64>()),
+            author: author.to_string(),
+            body,
+            timestamp: now_millis(),
+            reply_to: None,
+            public_key: String::new(),
+            signature: String::new(),
+        }
+    }
+
+    /// Make this message a reply to the message `parent`.
+    pub fn in_reply_to(mut self, parent: String) -> Self {
+        self.reply_to = Some(parent);
+        self
+    }
+
+    /// Sign the message with the key of its author, it must not change after this.
+    pub fn signed(mut self, keypair: &Keypair) -> Self {
+        let (public_key, signature) = sign(keypair, &self.payload());
+        self.public_key = public_key;
+        self.signature = signature;
+        self
+    }
+
+    /// If the signature is valid and made by `author`. Messages shared
+    /// by peers that are not the author are only trusted if it is.
+    pub fn verify(&self) -> bool {
+        verify(
+            &self.author,
+            &self.public_key,
+            &self.signature,
+            &self.payload(),
+        )
+    }
+
+    /// The bytes that are signed.
+    fn payload(&self) -> Vec<u8> {
+        serde_json::to_vec(&(
+            &self.id,
+            &self.author,
+            &self.body,
+            self.timestamp,
+            &self.reply_to,
+        ))
+        .expect("messages to be serializable")
+    }
+}
+
+/// An emoji added to, or removed from, a message, signed by who reacted.
+///
+/// Reactions are shared with the history too, so like edits
+/// they are signed instead of trusting the gossipsub source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Reaction {
+    /// the id of the `ChatMessage` that is reacted to
+    pub message_id: String,
+    /// the `PeerId` of who reacted
+    pub author: String,
+    pub emoji: String,
+    /// `false` if the reaction was taken back
+    pub added: bool,
+    /// milliseconds since the unix epoch, the latest reaction wins
+    pub timestamp: u64,
+    /// the protobuf encoded public key of `author`, in hex,
+    /// empty if the reaction is not signed
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub public_key: String,
+    /// the signature of everything above, in hex
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub signature: String,
+}
+
+impl Reaction {
+    pub fn new(keypair: &Keypair, message_id: String, emoji: String, added: bool) -> Self {
+        let mut reaction = Self {
+            message_id,
+            author: keypair.public().to_peer_id().to_string(),
+            emoji,
+            added,
+            timestamp: now_millis(),
+            public_key: String::new(),
+            signature: String::new(),
+        };
+        (reaction.public_key, reaction.signature) = sign(keypair, &reaction.payload());
+        reaction
+    }
+
+    /// If the signature is valid and made by `author`.
+    pub fn verify(&self) -> bool {
+        verify(
+            &self.author,
+            &self.public_key,
+            &self.signature,
+            &self.payload(),
+        )
+    }
+
+    /// The bytes that are signed.
+    fn payload(&self) -> Vec<u8> {
+        serde_json::to_vec(&(
+            &self.message_id,
+            &self.author,
+            &self.emoji,
+            self.added,
+            self.timestamp,
+        ))
+        .expect("reactions to be serializable")
+    }
+}
+
+/// What happened to a message we sent.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// not handed to the network yet
+    Pending,
+    /// no one is in the room, it is sent once someone joins
+    Queued,
+    /// sent to the peers of the room
+    Published,
+    /// no peer got it, it can be sent again
+    Failed { reason: String },
+}
+
+/// A new body for a message, or its deletion, signed by its author.
+///
+/// Edits are shared with the history, by peers that are not the
+/// author, so they are signed instead of trusting the gossipsub source.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    /// the id of the `ChatMessage` that is edited
+    pub message_id: String,
+    /// the `PeerId` of the signer
+    pub author: String,
+    /// the new body, `None` if the message was deleted
+    pub body: Option<String>,
+    /// milliseconds since the unix epoch, the latest edit wins
+    pub timestamp: u64,
+    /// the protobuf encoded public key of `author`, in hex
+    pub public_key: String,
+    /// the signature of everything above, in hex
+    pub signature: String,
+}
+
+impl Edit {
+    pub fn new(keypair: &Keypair, message_id: String, body: Option<String>) -> Self {
+        let mut edit = Self {
+            message_id,
+            author: keypair.public().to_peer_id().to_string(),
+            body,
+            timestamp: now_millis(),
+            public_key: String::new(),
+            signature: String::new(),
+        };
+        (edit.public_key, edit.signature) = sign(keypair, &edit.payload());
+        edit
+    }
+
+    /// If the signature is valid and made by `author`.
+    pub fn verify(&self) -> bool {
+        verify(
+            &self.author,
+            &self.public_key,
+            &self.signature,
+            &self.payload(),
+        )
+    }
+
+    /// The bytes that are signed.
+    fn payload(&self) -> Vec<u8> {
+        serde_json::to_vec(&(&self.message_id, &self.author, &self.body, self.timestamp))
+            .expect("edits to be serializable")
+    }
+}
+
+/// Sign `payload`, returns the protobuf encoded public
+/// key of `keypair` and the signature, in hex.
+fn sign(keypair: &Keypair, payload: &[u8]) -> (String, String) {
+    let signature = keypair.sign(payload).expect("ed25519 signing to not fail");
+    (
+        hex::encode(keypair.public().encode_protobuf()),
+        hex::encode(signature),
+    )
+}
+
+/// If `signature` of `payload` is valid and made with the key of `author`.
+fn verify(author: &str, public_key: &str, signature: &str, payload: &[u8]) -> bool {
+    let (Ok(public_key), Ok(signature)) = (hex::decode(public_key), hex::decode(signature)) else {
+        return false;
+    };
+    let Ok(public_key) = PublicKey::try_decode_protobuf(&public_key) else {
+        return false;
+    };
+    public_key.to_peer_id().to_string() == author && public_key.verify(payload, &signature)
+}
+
+/// What is published on the gossipsub topic of a room.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WireMessage {
+    Chat {
+        message: ChatMessage,
+    },
+    Reaction {
+        reaction: Reaction,
+    },
+    Edit {
+        edit: Edit,
+    },
+    /// `author` is typing in the room, it is never stored
+    Typing {
+        author: String,
+    },
+    /// our messages that waited in the outbox, oldest first, they are
+    /// sent together because gossipsub may reorder a burst of messages
+    Outbox {
+        messages: Vec<ChatMessage>,
+    },
+    /// sent on `PRESENCE_TOPIC` only
+    Presence {
+        presence: Presence,
+    },
+    /// `author` has seen the messages `message_ids`, it is never stored
+    Receipt {
+        author: String,
+        message_ids: Vec<String>,
+    },
+    /// asks the peers in the room for the messages sent before we joined
+    HistoryRequest,
+    History {
+        messages: Vec<ChatMessage>,
+        /// the reactions to `messages`
+        #[serde(default)]
+        reactions: Vec<Reaction>,
+        /// the edits of `messages`
+        #[serde(default)]
+        edits: Vec<Edit>,
+    },
+}
+
+impl WireMessage {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("wire messages to be serializable")
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_json::Error> {
+        serde_json::from_slice(bytes)
+    }
+}
+
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
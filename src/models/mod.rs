@@ -1,2 +1,14 @@
-pub mod cli;
+pub mod archive;
+pub mod control;
+pub mod edit;
+pub mod error;
+pub mod history;
+pub mod message;
 pub mod network;
+pub mod outbox;
+pub mod presence;
+pub mod reaction;
+pub mod search;
+pub mod status;
+pub mod storage;
+pub mod time;
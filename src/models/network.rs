@@ -1,27 +1,79 @@
-use clap::Parser;
 use libp2p::{
     core::upgrade,
     futures::StreamExt,
-    gossipsub::{self, IdentTopic},
+    gossipsub::{self, IdentTopic, PublishError, TopicHash},
     identity::Keypair,
     kad::{store::MemoryStore, Kademlia, KademliaEvent},
     mdns,
     multiaddr::Protocol,
-    swarm::{NetworkBehaviour, SwarmBuilder, SwarmEvent},
+    swarm::{behaviour::toggle::Toggle, NetworkBehaviour, SwarmBuilder, SwarmEvent},
     tcp::{self, Config},
     yamux, Multiaddr, PeerId, Swarm, Transport,
 };
 use libp2p_noise as noise;
-use log::info;
-use std::time::Duration;
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::{
+    collections::{BTreeMap, HashSet},
+    time::Duration,
+};
 use tokio::{
     select,
-    sync::mpsc::{Receiver, Sender},
+    sync::{
+        broadcast,
+        mpsc::{Receiver, Sender, UnboundedSender},
+        oneshot,
+    },
+    time::{interval, sleep, sleep_until, timeout_at, Instant},
+};
+
+use super::{
+    archive::ImportReport,
+    error::NetworkError,
+    history::{History, RoomHistory},
+    message::{now_millis, ChatMessage, DeliveryStatus, Edit, Reaction, WireMessage},
+    presence::{Presence, PresenceState, Presences},
+    search::{SearchHit, SearchQuery},
+    status::{is_public, NatStatus, NodeStatus},
+    storage::{Entry, Record, StorageMessage},
 };
+use crate::node::NodeConfig;
+
+/// How many messages are sent to a peer that asks for the history.
+/// Gossipsub messages are limited to 64KiB, so this can't be too large.
+const HISTORY_SYNC_LIMIT: usize = 50;
+
+/// How many reactions to those messages are sent with them, the latest ones.
+const REACTION_SYNC_LIMIT: usize = 150;
+
+/// How many edits of those messages are sent with them, the latest ones.
+const EDIT_SYNC_LIMIT: usize = 50;
+
+/// How long the peers are given to learn that we left
+/// our rooms before the connections are closed.
+const UNSUBSCRIBE_GRACE: Duration = Duration::from_millis(300);
+
+/// How large a batch of queued messages may be, gossipsub
+/// messages are limited to 64KiB.
+const OUTBOX_BATCH_BYTES: usize = 48 * 1024;
+
+/// The topic every node is in to tell the others
+/// about its presence, it is not a room.
+pub const PRESENCE_TOPIC: &str = "p2p-chat/presence";
+
+/// How often we tell the peers that we are still here.
+const PRESENCE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How long until we forget the presence of a peer we don't hear from.
+pub const PRESENCE_TIMEOUT: Duration = Duration::from_secs(30);
 
-use crate::app::AppMessage;
+/// How often the size of the gossipsub meshes is checked,
+/// gossipsub only changes them on its own heartbeat.
+const MESH_INTERVAL: Duration = Duration::from_secs(1);
 
-use super::cli::Opt;
+/// The longest a shutdown may take, connections that
+/// are still open after that are simply dropped.
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(2);
 
 // defines the behaviour of the current peer
 // on the network
@@ -30,7 +82,7 @@ use super::cli::Opt;
 pub struct AppBehaviour {
     pub gossipsub: gossipsub::Behaviour,
     pub kademlia: Kademlia<MemoryStore>,
-    pub mdns: mdns::tokio::Behaviour,
+    pub mdns: Toggle<mdns::tokio::Behaviour>,
 }
 
 #[derive(Debug)]
@@ -41,8 +93,110 @@ pub enum NetworkMessage {
     Mdns(mdns::Event),
 
     Quit,
-    MessageReceived(IdentTopic, String),
+    MessageReceived(IdentTopic, ChatMessage),
+    /// send our message again, after its delivery failed
+    Resend(IdentTopic, ChatMessage),
+    Reaction(IdentTopic, Reaction),
+    Edit(IdentTopic, Edit),
+    /// we are typing in a room
+    Typing(IdentTopic),
+    /// we have seen these messages of a room
+    Seen(IdentTopic, Vec<String>),
+    /// change our presence
+    SetPresence(PresenceState, String),
+    ListPresence(oneshot::Sender<Vec<Presence>>),
+    /// the edits of a message in a room
+    EditHistory(String, String, oneshot::Sender<Vec<Edit>>),
+    /// the stored messages of a room
+    RoomHistory(String, oneshot::Sender<RoomHistory>),
+    /// add exported messages to the history of a room
+    Import(String, RoomHistory, oneshot::Sender<ImportReport>),
+    Search(SearchQuery, oneshot::Sender<Vec<SearchHit>>),
     Subscribed(IdentTopic),
+    Unsubscribed(IdentTopic),
+    ListPeers(oneshot::Sender<Vec<PeerId>>),
+    /// the peers we know are in a room
+    RoomPeers(String, oneshot::Sender<Vec<PeerId>>),
+    ListRooms(oneshot::Sender<Vec<String>>),
+    /// the rooms with messages in the history
+    StoredRooms(oneshot::Sender<Vec<String>>),
+    Status(oneshot::Sender<NodeStatus>),
+}
+
+/// Events emitted by `Network` to whoever is listening,
+/// the TUI or the clients of the control socket.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NetworkEvent {
+    Listening {
+        address: String,
+    },
+    /// we stopped listening on `address`
+    ListenAddressExpired {
+        address: String,
+    },
+    /// our gossipsub mesh of `topic` has `peers` peers now
+    MeshChanged {
+        topic: String,
+        peers: usize,
+    },
+    NatChanged {
+        nat: NatStatus,
+    },
+    MessageReceived {
+        topic: String,
+        message: ChatMessage,
+        /// sent just now, not shared with the history or an outbox,
+        /// so its timestamp should be close to our clock
+        live: bool,
+    },
+    ReactionReceived {
+        topic: String,
+        reaction: Reaction,
+    },
+    EditReceived {
+        topic: String,
+        edit: Edit,
+    },
+    /// `peer_id` is typing in `topic`
+    Typing {
+        topic: String,
+        peer_id: String,
+    },
+    /// what happened to a message we sent
+    Delivery {
+        topic: String,
+        message_id: String,
+        #[serde(flatten)]
+        status: DeliveryStatus,
+    },
+    /// a peer came online, or changed its presence
+    PresenceReceived {
+        presence: Presence,
+    },
+    /// we haven't heard from `peer_id` for a while
+    PresenceExpired {
+        peer_id: String,
+    },
+    /// `peer_id` has seen the messages `message_ids`
+    ReceiptReceived {
+        topic: String,
+        peer_id: String,
+        message_ids: Vec<String>,
+    },
+    PeerConnected {
+        peer_id: String,
+    },
+    PeerDisconnected {
+        peer_id: String,
+    },
+    PeerSubscribed {
+        topic: String,
+        peer_id: String,
+    },
+    Error {
+        error: NetworkError,
+    },
 }
 
 impl From<KademliaEvent> for NetworkMessage {
@@ -68,14 +222,38 @@ pub struct Network {
     pub swarm: Swarm<AppBehaviour>,
     pub event_receiver: Receiver<NetworkMessage>,
     pub event_sender: Sender<NetworkMessage>,
+    pub tx_events: broadcast::Sender<NetworkEvent>,
+    /// the peer to dial once we are listening
+    pub bootstrap_peer: Option<Multiaddr>,
+    pub history: History,
+    /// the storage task, if the history is persisted
+    pub storage: Option<UnboundedSender<StorageMessage>>,
+    /// rooms we joined, but didn't ask the history for yet
+    pub pending_history: HashSet<TopicHash>,
+    /// tell the peers which messages we have seen
+    pub read_receipts: bool,
+    /// our presence, as we tell the peers
+    pub presence: Presence,
+    /// the presence of the peers
+    pub presences: Presences,
+    /// the addresses, peers and meshes, as last reported
+    pub status: NodeStatus,
+    /// a peer with a public address dialed us
+    pub dialed_from_public: bool,
+    /// our messages that could not be published, they may be sent again
+    pub failed: HashSet<String>,
 }
 
 impl Network {
-    pub fn new(tx: Sender<NetworkMessage>, rx: Receiver<NetworkMessage>) -> Self {
-        // get the object representing the CLI flags
-        let opt = Opt::parse();
-        // generate the peer public key (peerId)
-        let keypair = Keypair::generate_ed25519();
+    pub fn new(
+        config: NodeConfig,
+        keypair: Keypair,
+        tx: Sender<NetworkMessage>,
+        rx: Receiver<NetworkMessage>,
+        tx_events: broadcast::Sender<NetworkEvent>,
+        history: History,
+        storage: Option<UnboundedSender<StorageMessage>>,
+    ) -> Result<Self, NetworkError> {
         let peer_id = keypair.public().to_peer_id();
 
         // instantiate the TCP protocol, with noise cryptography
@@ -83,10 +261,7 @@ impl Network {
         let transport_config = Config::new().port_reuse(true);
         let transport = tcp::tokio::Transport::new(transport_config)
             .upgrade(upgrade::Version::V1Lazy)
-            .authenticate(
-                noise::Config::new(&keypair)
-                    .expect("Signing libp2p-noise static DH keypair failed."),
-            )
+            .authenticate(noise::Config::new(&keypair).map_err(setup_error)?)
             .multiplex(yamux::Config::default())
             .boxed();
 
@@ -98,99 +273,271 @@ impl Network {
         // this is not being used at the moment.
         let kademlia = Kademlia::new(peer_id, MemoryStore::new(peer_id));
 
-        let mdns = mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id).unwrap();
+        let mdns = if config.mdns {
+            Some(
+                mdns::tokio::Behaviour::new(mdns::Config::default(), peer_id)
+                    .map_err(setup_error)?,
+            )
+        } else {
+            None
+        };
 
         // protocol - gossipsub
         let gossipsub_config = gossipsub::ConfigBuilder::default()
             .heartbeat_interval(Duration::from_secs(10)) // This is set to aid debugging by not cluttering the log space
             .validation_mode(gossipsub::ValidationMode::Strict) // This sets the kind of message validation. The default is Strict (enforce message signing)
             .build()
-            .expect("Valid config");
+            .map_err(setup_error)?;
 
         let gossipsub = gossipsub::Behaviour::new(message_authenticity, gossipsub_config)
-            .expect("could not create gossipsub interface");
+            .map_err(setup_error)?;
 
         // swarm manages all events, events, and protocols
         let mut swarm = {
             let behaviour = AppBehaviour {
                 gossipsub,
                 kademlia,
-                mdns,
+                mdns: mdns.into(),
             };
             SwarmBuilder::with_tokio_executor(transport, behaviour, peer_id).build()
         };
 
-        let multiaddr: Multiaddr = match &opt.listen_address {
-            Some(addr) => addr.clone(),
-            None => "/ip4/0.0.0.0/tcp/0".parse().expect("address to be valid"),
-        };
+        let presence_topic = IdentTopic::new(PRESENCE_TOPIC);
+        swarm
+            .behaviour_mut()
+            .gossipsub
+            .subscribe(&presence_topic)
+            .map_err(|e| NetworkError::Subscribe {
+                topic: PRESENCE_TOPIC.to_string(),
+                reason: format!("{e:?}"),
+            })?;
 
         // this peer will listen to events on the network
         swarm
-            .listen_on(multiaddr)
-            .expect("could not listen on swarm");
+            .listen_on(config.listen_address.clone())
+            .map_err(|e| NetworkError::Listen {
+                address: config.listen_address.to_string(),
+                reason: e.to_string(),
+            })?;
 
-        Self {
+        Ok(Self {
             swarm,
             peer_id,
             event_receiver: rx,
             event_sender: tx,
-        }
+            tx_events,
+            bootstrap_peer: config.peer,
+            history,
+            storage,
+            pending_history: HashSet::new(),
+            read_receipts: config.read_receipts,
+            presence: Presence::new(&peer_id, PresenceState::Online, String::new()),
+            presences: Presences::default(),
+            status: NodeStatus::default(),
+            dialed_from_public: false,
+            failed: HashSet::new(),
+        })
     }
 
-    pub async fn daemon<'a>(&mut self, tx_app: Sender<AppMessage<'a>>) {
+    pub async fn daemon(&mut self) {
+        let mut heartbeat = interval(PRESENCE_INTERVAL);
+        let mut mesh = interval(MESH_INTERVAL);
         loop {
             select! {
+                _ = mesh.tick() => self.update_mesh(),
+                _ = heartbeat.tick() => {
+                    self.announce_presence();
+                    for peer_id in self.presences.expire(Instant::now(), PRESENCE_TIMEOUT) {
+                        let _ = self.tx_events.send(NetworkEvent::PresenceExpired { peer_id });
+                    }
+                },
                 event = self.event_receiver.recv() => {
-                    match event.unwrap() {
-                        NetworkMessage::Dial(addr) => {
-                            let peer_id = match addr.iter().last() {
-                                Some(Protocol::P2p(hash)) => PeerId::from_multihash(hash).expect("Valid hash."),
-                                _ => return 
-                            };
-                            self.swarm.dial(addr.clone()).expect("to call addr");
-                            self.swarm.behaviour_mut().kademlia.add_address(&peer_id, addr);
-                        },
+                    // every handle to the node was dropped
+                    let Some(event) = event else { return self.shutdown().await };
+                    match event {
+                        NetworkMessage::Dial(addr) => self.dial(addr),
                         NetworkMessage::Kademlia(e) => {info!("unhandled {:#?}", e)},
                         NetworkMessage::MessageReceived(topic, message) => {
-                            if let Err(e) =
-                                self.swarm.behaviour_mut()
-                                .gossipsub.publish(topic, message.as_bytes())
+                            self.remember(topic.hash().as_str(), message.clone());
+                            self.send_chat(topic.hash(), message);
+                        },
+                        NetworkMessage::Resend(topic, message) => {
+                            let room = topic.hash().into_string();
+                            let reason = if message.author != self.peer_id.to_string() {
+                                Some("only its author can send it")
+                            } else if !message.verify() {
+                                Some("its signature is not valid")
+                            } else if !self.failed.contains(&message.id)
+                                && !self.history.outbox.contains(&room, &message.id)
                             {
-                                info!("could not send msg from daemon {:?}", e);
+                                Some("it was not queued and did not fail")
+                            } else {
+                                None
+                            };
+                            if let Some(reason) = reason {
+                                self.report(NetworkError::Resend {
+                                    message_id: message.id,
+                                    reason: reason.to_string(),
+                                });
+                                continue;
+                            }
+                            self.send_chat(topic.hash(), message);
+                        },
+                        NetworkMessage::Reaction(topic, reaction) => {
+                            self.remember_reaction(topic.hash().as_str(), reaction.clone());
+                            let _ = self.publish(topic.hash(), &WireMessage::Reaction { reaction });
+                        },
+                        NetworkMessage::Edit(topic, edit) => {
+                            let room = topic.hash().into_string();
+                            let reason = match self.history.message(&room, &edit.message_id) {
+                                None => Some("no such message"),
+                                Some(m) if m.author != self.peer_id.to_string() => {
+                                    Some("only its author can edit it")
+                                }
+                                Some(_) => None,
                             };
+                            if let Some(reason) = reason {
+                                self.report(NetworkError::Edit {
+                                    message_id: edit.message_id,
+                                    reason: reason.to_string(),
+                                });
+                                continue;
+                            }
+                            self.remember_edit(&room, edit.clone());
+                            let _ = self.publish(topic.hash(), &WireMessage::Edit { edit });
+                        },
+                        NetworkMessage::Typing(topic) => {
+                            let author = self.peer_id.to_string();
+                            // it is only a hint, so it is fine if no one gets it
+                            self.publish_quietly(topic.hash(), &WireMessage::Typing { author });
+                        },
+                        NetworkMessage::Seen(topic, message_ids) => {
+                            if self.read_receipts && !message_ids.is_empty() {
+                                let author = self.peer_id.to_string();
+                                let wire = WireMessage::Receipt { author, message_ids };
+                                self.publish_quietly(topic.hash(), &wire);
+                            }
+                        },
+                        NetworkMessage::EditHistory(room, id, tx) => {
+                            let _ = tx.send(self.history.edit_history(&room, &id));
+                        },
+                        NetworkMessage::RoomHistory(room, tx) => {
+                            let _ = tx.send(self.history.room(&room));
                         },
-                        NetworkMessage::Quit => return ,
+                        NetworkMessage::StoredRooms(tx) => {
+                            let _ = tx.send(self.history.rooms());
+                        },
+                        NetworkMessage::Import(room, history, tx) => {
+                            let _ = tx.send(self.import(&room, history));
+                        },
+                        NetworkMessage::Search(query, tx) => {
+                            let _ = tx.send(self.history.search(&query));
+                        },
+                        NetworkMessage::Quit => return self.shutdown().await,
                         NetworkMessage::Subscribed(topic) => {
                             info!("subscribed to {topic}");
-                            self.swarm.behaviour_mut().gossipsub
-                                .subscribe(&topic)
-                                .expect("could not subscribe to topic");
+                            if let Err(e) = self.swarm.behaviour_mut().gossipsub.subscribe(&topic) {
+                                self.report(NetworkError::Subscribe {
+                                    topic: topic.to_string(),
+                                    reason: format!("{e:?}"),
+                                });
+                                continue;
+                            }
+                            self.pending_history.insert(topic.hash());
+                            self.request_history(topic.hash());
+                        },
+                        NetworkMessage::Unsubscribed(topic) => {
+                            info!("unsubscribed from {topic}");
+                            if let Err(e) = self.swarm.behaviour_mut().gossipsub.unsubscribe(&topic) {
+                                self.report(NetworkError::Unsubscribe {
+                                    topic: topic.to_string(),
+                                    reason: format!("{e:?}"),
+                                });
+                            }
+                            self.pending_history.remove(&topic.hash());
+                        },
+                        NetworkMessage::ListPeers(tx) => {
+                            let _ = tx.send(self.swarm.connected_peers().copied().collect());
+                        },
+                        NetworkMessage::RoomPeers(room, tx) => {
+                            let topic = IdentTopic::new(room).hash();
+                            let peers = self.swarm.behaviour().gossipsub.all_peers();
+                            let peers = peers
+                                .filter(|(_, topics)| topics.contains(&&topic))
+                                .map(|(peer, _)| *peer)
+                                .collect();
+                            let _ = tx.send(peers);
+                        },
+                        NetworkMessage::ListRooms(tx) => {
+                            let rooms = self.swarm.behaviour().gossipsub.topics();
+                            let rooms = rooms
+                                .map(|t| t.to_string())
+                                .filter(|t| t != PRESENCE_TOPIC)
+                                .collect();
+                            let _ = tx.send(rooms);
+                        },
+                        NetworkMessage::SetPresence(state, status) => {
+                            self.presence = Presence::new(&self.peer_id, state, status);
+                            self.announce_presence();
+                        },
+                        NetworkMessage::ListPresence(tx) => {
+                            let _ = tx.send(self.presences.list());
+                        },
+                        NetworkMessage::Status(tx) => {
+                            self.status.peers =
+                                self.swarm.connected_peers().map(|p| p.to_string()).collect();
+                            let _ = tx.send(self.status.clone());
                         },
                         _ => {info!("not handled kademlia event received")}
                     };
                 },
                 swarm_event = self.swarm.select_next_some() => match swarm_event {
                     SwarmEvent::NewListenAddr { address, .. } => {
-                        info!(
-                            "local node is listening on {:?}",
-                            address.with(Protocol::P2p(self.peer_id.into()))
-                        );
-                        let opt = Opt::parse();
-                        if let Some(addr) = &opt.peer {
-                            self.event_sender
-                                .send(NetworkMessage::Dial(addr.clone())).await.unwrap();
+                        let address = address.with(Protocol::P2p(self.peer_id.into()));
+                        info!("local node is listening on {:?}", address);
+                        self.status.listen_addresses.insert(address.to_string());
+                        let _ = self.tx_events.send(NetworkEvent::Listening {
+                            address: address.to_string(),
+                        });
+                        self.update_nat();
+                        if let Some(addr) = self.bootstrap_peer.take() {
+                            self.dial(addr);
                         };
                     },
+                    SwarmEvent::ExpiredListenAddr { address, .. } => {
+                        let address = address.with(Protocol::P2p(self.peer_id.into()));
+                        self.status.listen_addresses.remove(&address.to_string());
+                        let _ = self.tx_events.send(NetworkEvent::ListenAddressExpired {
+                            address: address.to_string(),
+                        });
+                        self.update_nat();
+                    },
                     SwarmEvent::Behaviour(NetworkMessage::Kademlia(_e)) => {
                         // info!("Received kademlia event {:#?}", e);
                     },
                     SwarmEvent::ConnectionEstablished { peer_id, endpoint, .. } => {
                         if endpoint.is_dialer() {
                             info!("connection established - peerId: {peer_id}");
+                        } else if is_public(endpoint.get_remote_address()) {
+                            self.dialed_from_public = true;
+                            self.update_nat();
                         }
+                        let _ = self.tx_events.send(NetworkEvent::PeerConnected {
+                            peer_id: peer_id.to_string(),
+                        });
+                    }
+                    SwarmEvent::ConnectionClosed { peer_id, num_established: 0, .. } => {
+                        let _ = self.tx_events.send(NetworkEvent::PeerDisconnected {
+                            peer_id: peer_id.to_string(),
+                        });
                     }
                     SwarmEvent::Dialing(peer_id) => info!("Dialing {peer_id}"),
+                    SwarmEvent::OutgoingConnectionError { peer_id, error } => {
+                        self.report(NetworkError::Dial {
+                            address: peer_id.map(|p| p.to_string()).unwrap_or_default(),
+                            reason: error.to_string(),
+                        });
+                    }
                     SwarmEvent::Behaviour(NetworkMessage::Gossipsub(gossipsub::Event::Subscribed {
                         peer_id,
                         topic,
@@ -198,23 +545,33 @@ impl Network {
                         info!(
                             "{peer_id} subscribed to {topic}"
                         );
+                        if topic.as_str() == PRESENCE_TOPIC {
+                            // don't make them wait for the next heartbeat
+                            self.announce_presence();
+                            continue;
+                        }
+                        let _ = self.tx_events.send(NetworkEvent::PeerSubscribed {
+                            topic: topic.to_string(),
+                            peer_id: peer_id.to_string(),
+                        });
+                        // now there is someone to send to, and to ask
+                        self.flush_outbox(topic.clone());
+                        self.request_history(topic);
                     }
                     SwarmEvent::Behaviour(NetworkMessage::Gossipsub(gossipsub::Event::Message {
-                        propagation_source: peer_id,
+                        propagation_source,
                         message,
                         ..
                     })) => {
-                        let peer_id = peer_id.to_string();
-                        let peer_id = peer_id[peer_id.len() - 7..].to_string();
-
-                        let message = format!(
-                            "{peer_id}: {}",
-                            String::from_utf8_lossy(&message.data),
-                        );
-
-                        info!("got msg {message}");
-
-                        tx_app.send(AppMessage::MessageReceived{ message }).await.unwrap();
+                        // messages are signed, so the source is always there
+                        let source = message.source.unwrap_or(propagation_source);
+                        match WireMessage::from_bytes(&message.data) {
+                            Ok(wire) => self.handle_wire_message(source, message.topic, wire),
+                            Err(e) => self.report(NetworkError::InvalidMessage {
+                                peer_id: source.to_string(),
+                                reason: e.to_string(),
+                            }),
+                        }
                     },
                     SwarmEvent::Behaviour(NetworkMessage::Mdns(mdns::Event::Discovered(list))) => {
                         for (peer_id, _multiaddr) in list {
@@ -232,4 +589,530 @@ impl Network {
             };
         }
     }
+
+    fn dial(&mut self, addr: Multiaddr) {
+        if let Err(e) = self.swarm.dial(addr.clone()) {
+            self.report(NetworkError::Dial {
+                address: addr.to_string(),
+                reason: e.to_string(),
+            });
+            return;
+        }
+        // kademlia needs to know who is on the other end
+        if let Some(Protocol::P2p(hash)) = addr.iter().last() {
+            if let Ok(peer_id) = PeerId::from_multihash(hash) {
+                self.swarm
+                    .behaviour_mut()
+                    .kademlia
+                    .add_address(&peer_id, addr);
+            }
+        }
+    }
+
+    /// Leave every room, flush the history and close all
+    /// connections, waiting at most `SHUTDOWN_DEADLINE`.
+    async fn shutdown(&mut self) {
+        info!("shutting down");
+        let deadline = Instant::now() + SHUTDOWN_DEADLINE;
+
+        let topics: Vec<TopicHash> = self.swarm.behaviour().gossipsub.topics().cloned().collect();
+        for topic in topics {
+            let topic = IdentTopic::new(topic.into_string());
+            let _ = self.swarm.behaviour_mut().gossipsub.unsubscribe(&topic);
+        }
+
+        if let Some(storage) = &self.storage {
+            let (tx, rx) = oneshot::channel();
+            if storage.send(StorageMessage::Flush(tx)).is_ok() {
+                match timeout_at(deadline, rx).await {
+                    Ok(Ok(Err(e))) => self.report(storage_error(e)),
+                    Err(_) => warn!("the history was not flushed in time"),
+                    _ => {}
+                }
+            }
+        }
+
+        // let the swarm send the unsubscriptions before disconnecting
+        let grace = sleep(UNSUBSCRIBE_GRACE);
+        tokio::pin!(grace);
+        loop {
+            select! {
+                _ = &mut grace => break,
+                _ = sleep_until(deadline) => return,
+                _ = self.swarm.select_next_some() => {}
+            }
+        }
+
+        let peers: Vec<PeerId> = self.swarm.connected_peers().copied().collect();
+        for peer in peers {
+            let _ = self.swarm.disconnect_peer_id(peer);
+        }
+        while self.swarm.connected_peers().next().is_some() {
+            select! {
+                _ = sleep_until(deadline) => {
+                    warn!("connections still open after {SHUTDOWN_DEADLINE:?}, dropping them");
+                    return;
+                }
+                _ = self.swarm.select_next_some() => {}
+            }
+        }
+    }
+
+    /// Publish `wire` to `topic`, and report it if that fails.
+    fn publish(&mut self, topic: TopicHash, wire: &WireMessage) -> Result<(), NetworkError> {
+        if let Err(e) = self.try_publish(&topic, wire) {
+            let error = NetworkError::Publish {
+                topic: topic.into_string(),
+                reason: format!("{e:?}"),
+            };
+            self.report(error.clone());
+            return Err(error);
+        };
+        Ok(())
+    }
+
+    /// Publish our `message`, or queue it if no one is in the room.
+    fn send_chat(&mut self, topic: TopicHash, message: ChatMessage) {
+        let room = topic.as_str().to_string();
+        // the queued messages go first, to keep them in order
+        if !self.history.outbox.is_empty(&room) {
+            self.queue(&room, message);
+            return self.flush_outbox(topic);
+        }
+
+        let wire = WireMessage::Chat {
+            message: message.clone(),
+        };
+        let status = match self.try_publish(&topic, &wire) {
+            Ok(()) => DeliveryStatus::Published,
+            Err(PublishError::InsufficientPeers) => return self.queue(&room, message),
+            Err(e) => self.publish_failed(&room, e),
+        };
+        self.delivered(&room, message.id, status);
+    }
+
+    /// Publish the queued messages of `topic` in order,
+    /// until there is no one to send them to.
+    fn flush_outbox(&mut self, topic: TopicHash) {
+        let room = topic.as_str().to_string();
+        loop {
+            let messages = self.history.outbox.batch(&room, OUTBOX_BATCH_BYTES);
+            if messages.is_empty() {
+                return;
+            }
+            let wire = WireMessage::Outbox {
+                messages: messages.clone(),
+            };
+            let status = match self.try_publish(&topic, &wire) {
+                Ok(()) => DeliveryStatus::Published,
+                Err(PublishError::InsufficientPeers) => return,
+                Err(e) => self.publish_failed(&room, e),
+            };
+            for message in messages {
+                self.history.outbox.remove(&room, &message.id);
+                self.store(
+                    &room,
+                    Entry::Dequeued {
+                        message_id: message.id.clone(),
+                    },
+                );
+                self.delivered(&room, message.id, status.clone());
+            }
+        }
+    }
+
+    /// Put our `message` in the outbox, unless it already is.
+    fn queue(&mut self, room: &str, message: ChatMessage) {
+        let message_id = message.id.clone();
+        if self.history.outbox.push(room, message.clone()) {
+            self.store(room, Entry::Queued(message));
+        }
+        self.delivered(room, message_id, DeliveryStatus::Queued);
+    }
+
+    fn publish_failed(&self, room: &str, e: PublishError) -> DeliveryStatus {
+        let error = NetworkError::Publish {
+            topic: room.to_string(),
+            reason: format!("{e:?}"),
+        };
+        let reason = error.to_string();
+        self.report(error);
+        DeliveryStatus::Failed { reason }
+    }
+
+    /// Tell the listeners what happened to our message `message_id`.
+    fn delivered(&mut self, room: &str, message_id: String, status: DeliveryStatus) {
+        match status {
+            DeliveryStatus::Failed { .. } => self.failed.insert(message_id.clone()),
+            _ => self.failed.remove(&message_id),
+        };
+        let _ = self.tx_events.send(NetworkEvent::Delivery {
+            topic: room.to_string(),
+            message_id,
+            status,
+        });
+    }
+
+    fn try_publish(&mut self, topic: &TopicHash, wire: &WireMessage) -> Result<(), PublishError> {
+        self.swarm
+            .behaviour_mut()
+            .gossipsub
+            .publish(topic.clone(), wire.to_bytes())
+            .map(|_| ())
+    }
+
+    /// Tell the listeners about the meshes that grew or shrank.
+    fn update_mesh(&mut self) {
+        let gossipsub = &self.swarm.behaviour().gossipsub;
+        let mesh: BTreeMap<String, usize> = gossipsub
+            .topics()
+            .filter(|topic| topic.as_str() != PRESENCE_TOPIC)
+            .map(|topic| (topic.to_string(), gossipsub.mesh_peers(topic).count()))
+            .collect();
+        for (topic, peers) in &mesh {
+            if self.status.mesh.get(topic) != Some(peers) {
+                let _ = self.tx_events.send(NetworkEvent::MeshChanged {
+                    topic: topic.clone(),
+                    peers: *peers,
+                });
+            }
+        }
+        self.status.mesh = mesh;
+    }
+
+    /// Guess the NAT status again, after our addresses
+    /// changed or a peer dialed us.
+    fn update_nat(&mut self) {
+        let nat = if self.dialed_from_public || self.swarm.listeners().any(is_public) {
+            NatStatus::Public
+        } else if self.status.listen_addresses.is_empty() {
+            NatStatus::Unknown
+        } else {
+            NatStatus::Private
+        };
+        if nat != self.status.nat {
+            self.status.nat = nat;
+            let _ = self.tx_events.send(NetworkEvent::NatChanged { nat });
+        }
+    }
+
+    /// Tell the peers our presence, it is repeated on every heartbeat.
+    fn announce_presence(&mut self) {
+        let mut presence = self.presence.clone();
+        presence.timestamp = now_millis();
+        let topic = IdentTopic::new(PRESENCE_TOPIC).hash();
+        self.publish_quietly(topic, &WireMessage::Presence { presence });
+    }
+
+    /// Publish a hint that no one has to get, like a typing signal.
+    fn publish_quietly(&mut self, topic: TopicHash, wire: &WireMessage) {
+        if let Err(e) = self.try_publish(&topic, wire) {
+            info!("hint not sent to {topic}: {e:?}");
+        }
+    }
+
+    /// Tell the listeners that something went wrong.
+    fn report(&self, error: NetworkError) {
+        warn!("{error}");
+        let _ = self.tx_events.send(NetworkEvent::Error { error });
+    }
+
+    fn handle_wire_message(&mut self, source: PeerId, topic: TopicHash, wire: WireMessage) {
+        match wire {
+            WireMessage::Chat { message } => {
+                if message.author != source.to_string() {
+                    info!("{source} sent a message as {}", message.author);
+                    return;
+                }
+                info!("got msg {}", message.body);
+                self.message_received(topic, message, true);
+            }
+            WireMessage::Reaction { reaction } => {
+                if reaction.author != source.to_string() {
+                    info!("{source} reacted as {}", reaction.author);
+                    return;
+                }
+                self.reaction_received(source, topic, reaction);
+            }
+            WireMessage::Edit { edit } => self.edit_received(source, topic, edit),
+            WireMessage::Outbox { messages } => {
+                for message in messages {
+                    if message.author != source.to_string() {
+                        info!("{source} sent a message as {}", message.author);
+                        continue;
+                    }
+                    self.message_received(topic.clone(), message, false);
+                }
+            }
+            WireMessage::Presence { presence } => {
+                if presence.author != source.to_string() || topic.as_str() != PRESENCE_TOPIC {
+                    info!("{source} sent a presence as {}", presence.author);
+                    return;
+                }
+                if self.presences.apply(presence.clone(), Instant::now()) {
+                    let _ = self
+                        .tx_events
+                        .send(NetworkEvent::PresenceReceived { presence });
+                }
+            }
+            WireMessage::Receipt {
+                author,
+                message_ids,
+            } => {
+                if author != source.to_string() {
+                    info!("{source} sent a receipt as {author}");
+                    return;
+                }
+                let _ = self.tx_events.send(NetworkEvent::ReceiptReceived {
+                    topic: topic.into_string(),
+                    peer_id: author,
+                    message_ids,
+                });
+            }
+            WireMessage::Typing { author } => {
+                if author != source.to_string() {
+                    info!("{source} typed as {author}");
+                    return;
+                }
+                let _ = self.tx_events.send(NetworkEvent::Typing {
+                    topic: topic.into_string(),
+                    peer_id: author,
+                });
+            }
+            WireMessage::HistoryRequest => {
+                let messages = self
+                    .history
+                    .last(topic.as_str(), HISTORY_SYNC_LIMIT)
+                    .to_vec();
+                let mut reactions = self.history.reactions_to(&messages);
+                reactions.sort_by_key(|r| std::cmp::Reverse(r.timestamp));
+                reactions.truncate(REACTION_SYNC_LIMIT);
+                let mut edits = self.history.edits_to(&messages);
+                edits.sort_by_key(|e| std::cmp::Reverse(e.timestamp));
+                edits.truncate(EDIT_SYNC_LIMIT);
+                if !messages.is_empty() {
+                    let _ = self.publish(
+                        topic,
+                        &WireMessage::History {
+                            messages,
+                            reactions,
+                            edits,
+                        },
+                    );
+                }
+            }
+            WireMessage::History {
+                messages,
+                reactions,
+                edits,
+            } => {
+                for message in messages {
+                    // anyone can share the history, so only the signature tells who wrote it
+                    if !message.verify() {
+                        self.report(NetworkError::InvalidMessage {
+                            peer_id: source.to_string(),
+                            reason: format!(
+                                "shared a message of {} that is not signed by them",
+                                message.author
+                            ),
+                        });
+                        continue;
+                    }
+                    self.message_received(topic.clone(), message, false);
+                }
+                for reaction in reactions {
+                    self.reaction_received(source, topic.clone(), reaction);
+                }
+                for edit in edits {
+                    self.edit_received(source, topic.clone(), edit);
+                }
+            }
+        }
+    }
+
+    /// Add `message` to the history and persist it,
+    /// returns `false` if we have already seen it.
+    fn remember(&mut self, room: &str, message: ChatMessage) -> bool {
+        if !self.history.insert(room, message.clone()) {
+            return false;
+        }
+        self.store(room, Entry::Message(message));
+        true
+    }
+
+    /// Apply `reaction` and persist it, returns `false` if it changed
+    /// nothing. Like edits, the reactions to unknown messages are dropped.
+    fn remember_reaction(&mut self, room: &str, reaction: Reaction) -> bool {
+        if self.history.message(room, &reaction.message_id).is_none() {
+            debug!(
+                "dropped a reaction to unknown message {}",
+                reaction.message_id
+            );
+            return false;
+        }
+        if !self.history.react(reaction.clone()) {
+            return false;
+        }
+        self.store(room, Entry::Reaction(reaction));
+        true
+    }
+
+    fn store(&self, room: &str, entry: Entry) {
+        if let Some(storage) = &self.storage {
+            let record = Record {
+                room: room.to_string(),
+                entry,
+            };
+            // only fails if the storage task is gone, which is already reported
+            let _ = storage.send(StorageMessage::Append(record));
+        }
+    }
+
+    /// Add `edit` to the history and persist it, returns `false` if it
+    /// was already known. The edits of messages we don't have are dropped,
+    /// or anyone could fill the history with them.
+    fn remember_edit(&mut self, room: &str, edit: Edit) -> bool {
+        if self.history.message(room, &edit.message_id).is_none() {
+            debug!("dropped an edit of unknown message {}", edit.message_id);
+            return false;
+        }
+        if !self.history.edit(edit.clone()) {
+            return false;
+        }
+        self.store(room, Entry::Edit(edit));
+        true
+    }
+
+    /// Add the messages, reactions and edits of an export to the history
+    /// of `room`. They are checked like the ones shared by peers.
+    fn import(&mut self, room: &str, history: RoomHistory) -> ImportReport {
+        let mut report = ImportReport::default();
+        for message in history.messages {
+            if !message.verify() {
+                report.rejected += 1;
+            } else if self.remember(room, message) {
+                report.imported += 1;
+            } else {
+                report.skipped += 1;
+            }
+        }
+        for reaction in history.reactions {
+            if !reaction.verify() {
+                report.rejected += 1;
+            } else if self.remember_reaction(room, reaction) {
+                report.imported += 1;
+            } else {
+                report.skipped += 1;
+            }
+        }
+        for edit in history.edits {
+            let by_author = self
+                .history
+                .message(room, &edit.message_id)
+                .is_none_or(|m| m.author == edit.author);
+            if !edit.verify() || !by_author {
+                report.rejected += 1;
+            } else if self.remember_edit(room, edit) {
+                report.imported += 1;
+            } else {
+                report.skipped += 1;
+            }
+        }
+        report
+    }
+
+    /// Check that `edit` was signed by the author of the
+    /// message, store it and tell the listeners.
+    fn edit_received(&mut self, source: PeerId, topic: TopicHash, edit: Edit) {
+        let reason = if !edit.verify() {
+            Some("the edit has an invalid signature")
+        } else {
+            match self.history.message(topic.as_str(), &edit.message_id) {
+                Some(m) if m.author != edit.author => Some("the edit is not by the author"),
+                _ => None,
+            }
+        };
+        if let Some(reason) = reason {
+            self.report(NetworkError::InvalidMessage {
+                peer_id: source.to_string(),
+                reason: reason.to_string(),
+            });
+            return;
+        }
+
+        if !self.remember_edit(topic.as_str(), edit.clone()) {
+            return;
+        }
+        let _ = self.tx_events.send(NetworkEvent::EditReceived {
+            topic: topic.into_string(),
+            edit,
+        });
+    }
+
+    /// Check that `reaction` was signed by who reacted, apply it
+    /// and tell the listeners, if it changed anything.
+    fn reaction_received(&mut self, source: PeerId, topic: TopicHash, reaction: Reaction) {
+        if !reaction.verify() {
+            self.report(NetworkError::InvalidMessage {
+                peer_id: source.to_string(),
+                reason: format!(
+                    "the reaction of {} has an invalid signature",
+                    reaction.author
+                ),
+            });
+            return;
+        }
+        if !self.remember_reaction(topic.as_str(), reaction.clone()) {
+            return;
+        }
+        let _ = self.tx_events.send(NetworkEvent::ReactionReceived {
+            topic: topic.into_string(),
+            reaction,
+        });
+    }
+
+    /// Store `message` and tell the listeners, if we haven't seen it yet.
+    fn message_received(&mut self, topic: TopicHash, message: ChatMessage, live: bool) {
+        if !self.remember(topic.as_str(), message.clone()) {
+            return;
+        }
+        // there may be no one listening, e.g. a headless
+        // daemon without any control clients.
+        let _ = self.tx_events.send(NetworkEvent::MessageReceived {
+            topic: topic.into_string(),
+            message,
+            live,
+        });
+    }
+
+    /// Ask the peers in `topic` for its history, if we
+    /// still need it and there is anyone to ask.
+    fn request_history(&mut self, topic: TopicHash) {
+        if !self.pending_history.contains(&topic) {
+            return;
+        }
+        let has_peers = self
+            .swarm
+            .behaviour()
+            .gossipsub
+            .all_peers()
+            .any(|(_, topics)| topics.contains(&&topic));
+
+        if has_peers {
+            self.pending_history.remove(&topic);
+            let _ = self.publish(topic, &WireMessage::HistoryRequest);
+        }
+    }
+}
+
+fn setup_error(e: impl std::fmt::Display) -> NetworkError {
+    NetworkError::Setup {
+        reason: e.to_string(),
+    }
+}
+
+pub(crate) fn storage_error(e: std::io::Error) -> NetworkError {
+    NetworkError::Storage {
+        reason: e.to_string(),
+    }
 }
@@ -0,0 +1,84 @@
+use std::collections::{HashMap, VecDeque};
+
+use super::message::ChatMessage;
+
+/// The messages we sent while no one else was in the room.
+///
+/// They are published in order once a peer joins, a message
+/// is only queued once however many times it is sent.
+#[derive(Debug, Clone, Default)]
+pub struct Outbox {
+    /// room -> messages, oldest first
+    rooms: HashMap<String, VecDeque<ChatMessage>>,
+}
+
+impl Outbox {
+    /// Queue `message`, returns `false` if it already was.
+    pub fn push(&mut self, room: &str, message: ChatMessage) -> bool {
+        if self.contains(room, &message.id) {
+            return false;
+        }
+        self.rooms
+            .entry(room.to_string())
+            .or_default()
+            .push_back(message);
+        true
+    }
+
+    /// The oldest messages of `room`, as many as fit in `max_bytes`
+    /// once encoded, but at least one if there is any.
+    pub fn batch(&self, room: &str, max_bytes: usize) -> Vec<ChatMessage> {
+        let mut bytes = 0;
+        self.rooms
+            .get(room)
+            .into_iter()
+            .flatten()
+            .take_while(|m| {
+                let first = bytes == 0;
+                bytes += serde_json::to_vec(m).map_or(0, |m| m.len());
+                first || bytes <= max_bytes
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Take `message_id` out of the queue of `room`,
+    /// returns `false` if it wasn't there.
+    pub fn remove(&mut self, room: &str, message_id: &str) -> bool {
+        let Some(messages) = self.rooms.get_mut(room) else {
+            return false;
+        };
+        let len = messages.len();
+        messages.retain(|m| m.id != message_id);
+        let removed = messages.len() != len;
+        if messages.is_empty() {
+            self.rooms.remove(room);
+        }
+        removed
+    }
+
+    pub fn contains(&self, room: &str, message_id: &str) -> bool {
+        self.rooms
+            .get(room)
+            .is_some_and(|messages| messages.iter().any(|m| m.id == message_id))
+    }
+
+    /// The ids of the messages that wait in `room`, oldest first.
+    pub fn ids(&self, room: &str) -> Vec<String> {
+        self.rooms
+            .get(room)
+            .into_iter()
+            .flatten()
+            .map(|m| m.id.clone())
+            .collect()
+    }
+
+    /// How many messages wait in `room`.
+    pub fn len(&self, room: &str) -> usize {
+        self.rooms.get(room).map_or(0, VecDeque::len)
+    }
+
+    pub fn is_empty(&self, room: &str) -> bool {
+        self.len(room) == 0
+    }
+}
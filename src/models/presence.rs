@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use libp2p::PeerId;
+use serde::{Deserialize, Serialize};
+use tokio::time::{Duration, Instant};
+
+use super::message::now_millis;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceState {
+    #[default]
+    Online,
+    Away,
+    /// don't show notifications
+    DoNotDisturb,
+}
+
+/// What a peer tells everyone about itself, on every heartbeat.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Presence {
+    /// the `PeerId` of the peer
+    pub author: String,
+    pub state: PresenceState,
+    /// a few words of the peer's choosing, can be empty
+    pub status: String,
+    /// milliseconds since the unix epoch, the latest presence wins
+    pub timestamp: u64,
+}
+
+impl Presence {
+    pub fn new(author: &PeerId, state: PresenceState, status: String) -> Self {
+        Self {
+            author: author.to_string(),
+            state,
+            status,
+            timestamp: now_millis(),
+        }
+    }
+}
+
+/// The presence of the peers, forgotten when they go quiet.
+#[derive(Debug, Default)]
+pub struct Presences {
+    /// peer id -> latest presence, and when we heard of it
+    latest: HashMap<String, (Presence, Instant)>,
+}
+
+impl Presences {
+    /// Apply `presence`, heard at `now`. Returns `false` if
+    /// the state and status of the peer didn't change.
+    pub fn apply(&mut self, presence: Presence, now: Instant) -> bool {
+        match self.latest.get_mut(&presence.author) {
+            Some((latest, _)) if latest.timestamp > presence.timestamp => false,
+            Some((latest, heard)) => {
+                let changed = (latest.state, &latest.status) != (presence.state, &presence.status);
+                *latest = presence;
+                *heard = now;
+                changed
+            }
+            None => {
+                self.latest.insert(presence.author.clone(), (presence, now));
+                true
+            }
+        }
+    }
+
+    /// Forget the peers we haven't heard from for `timeout`,
+    /// returns their ids.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<String> {
+        let expired: Vec<String> = self
+            .latest
+            .iter()
+            .filter(|(_, (_, heard))| now.duration_since(*heard) >= timeout)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+        for peer_id in &expired {
+            self.latest.remove(peer_id);
+        }
+        expired
+    }
+
+    /// The presence of every peer we know of, sorted by peer id.
+    pub fn list(&self) -> Vec<Presence> {
+        let mut list: Vec<Presence> = self.latest.values().map(|(p, _)| p.clone()).collect();
+        list.sort_by(|a, b| a.author.cmp(&b.author));
+        list
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::message::Reaction;
+
+/// The reactions to the messages of a room.
+///
+/// Only the latest reaction of an author with an emoji counts,
+/// so they can arrive in any order and more than once. When two
+/// have the same timestamp, taking the reaction back wins.
+#[derive(Debug, Clone, Default)]
+pub struct Reactions {
+    /// message id -> (emoji, author) -> latest reaction
+    latest: HashMap<String, BTreeMap<(String, String), Reaction>>,
+}
+
+impl Reactions {
+    /// Apply `reaction`, returns `false` if it changed nothing.
+    pub fn apply(&mut self, reaction: Reaction) -> bool {
+        let key = (reaction.emoji.clone(), reaction.author.clone());
+        let reactions = self.latest.entry(reaction.message_id.clone()).or_default();
+
+        match reactions.get(&key) {
+            Some(latest)
+                if (latest.timestamp, !latest.added) >= (reaction.timestamp, !reaction.added) =>
+            {
+                false
+            }
+            _ => {
+                reactions.insert(key, reaction);
+                true
+            }
+        }
+    }
+
+    /// The emojis on `message_id` and who reacted with them,
+    /// sorted by emoji. Removed reactions are left out.
+    pub fn summary(&self, message_id: &str) -> Vec<(&str, Vec<&str>)> {
+        let mut summary: Vec<(&str, Vec<&str>)> = Vec::new();
+        let Some(reactions) = self.latest.get(message_id) else {
+            return summary;
+        };
+
+        for ((emoji, author), _) in reactions.iter().filter(|(_, r)| r.added) {
+            match summary.last_mut() {
+                Some((last, authors)) if last == emoji => authors.push(author),
+                _ => summary.push((emoji, vec![author])),
+            }
+        }
+        summary
+    }
+
+    /// If `author` currently reacts to `message_id` with `emoji`.
+    pub fn has_reacted(&self, message_id: &str, author: &str, emoji: &str) -> bool {
+        self.latest
+            .get(message_id)
+            .and_then(|r| r.get(&(emoji.to_string(), author.to_string())))
+            .is_some_and(|r| r.added)
+    }
+
+    /// Every reaction to `message_id`, including the removed ones,
+    /// so a peer that has an older state can catch up.
+    pub fn of(&self, message_id: &str) -> impl Iterator<Item = &Reaction> {
+        self.latest
+            .get(message_id)
+            .into_iter()
+            .flat_map(|r| r.values())
+    }
+
+    /// Forget the reactions to a message that is gone.
+    pub fn remove_message(&mut self, message_id: &str) {
+        self.latest.remove(message_id);
+    }
+}
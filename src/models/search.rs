@@ -0,0 +1,84 @@
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    message::ChatMessage,
+    time::{parse_date, InvalidDate},
+};
+
+/// A full-text search over the stored messages.
+///
+/// As typed in the TUI, the filters are words with a prefix and
+/// everything else is the text, e.g.
+/// `room:rust from:abc1234 after:2024-05-01 before:2024-06-01 tokio`
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SearchQuery {
+    /// words that must all be in the message, ignoring case
+    pub text: String,
+    pub room: Option<String>,
+    /// the `PeerId` of the author, or its end
+    pub author: Option<String>,
+    /// only messages sent at or after this, in milliseconds since the unix epoch
+    pub after: Option<u64>,
+    /// only messages sent before this, in milliseconds since the unix epoch
+    pub before: Option<u64>,
+}
+
+/// A message that matches a `SearchQuery`, with the messages
+/// around it in its room. Bodies are the ones after the edits.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub room: String,
+    pub message: ChatMessage,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub previous: Option<ChatMessage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<ChatMessage>,
+}
+
+impl SearchQuery {
+    /// If `message` of `room` matches, `body` is its body after the edits.
+    pub fn matches(&self, room: &str, message: &ChatMessage, body: &str) -> bool {
+        if self.room.as_ref().is_some_and(|r| r != room)
+            || self
+                .author
+                .as_ref()
+                .is_some_and(|a| !message.author.ends_with(a.as_str()))
+            || self.after.is_some_and(|after| message.timestamp < after)
+            || self
+                .before
+                .is_some_and(|before| message.timestamp >= before)
+        {
+            return false;
+        }
+        let body = body.to_lowercase();
+        self.text
+            .split_whitespace()
+            .all(|word| body.contains(&word.to_lowercase()))
+    }
+}
+
+impl FromStr for SearchQuery {
+    type Err = InvalidDate;
+
+    /// Dates are days in UTC, `before` is the first day left out.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut query = SearchQuery::default();
+        let mut text = vec![];
+        for word in s.split_whitespace() {
+            match word.split_once(':') {
+                Some(("room", room)) if !room.is_empty() => query.room = Some(room.to_string()),
+                Some(("from", author)) if !author.is_empty() => {
+                    query.author = Some(author.to_string())
+                }
+                Some(("after", date)) => query.after = Some(parse_date(date)?),
+                Some(("before", date)) => query.before = Some(parse_date(date)?),
+                _ => text.push(word),
+            }
+        }
+        query.text = text.join(" ");
+        Ok(query)
+    }
+}
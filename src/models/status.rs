@@ -0,0 +1,88 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::IpAddr,
+};
+
+use libp2p::{multiaddr::Protocol, Multiaddr};
+use serde::{Deserialize, Serialize};
+
+use super::network::NetworkEvent;
+
+/// Whether peers outside our network can reach us. There are no
+/// probes, it is guessed from our addresses and who dialed us.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NatStatus {
+    /// we are not listening yet
+    #[default]
+    Unknown,
+    /// we listen on a public address, or a peer from one dialed us
+    Public,
+    /// we only listen on private addresses, most likely behind a NAT
+    Private,
+}
+
+/// What the node knows about its place in the network.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub listen_addresses: BTreeSet<String>,
+    /// the peers we have a connection with
+    pub peers: BTreeSet<String>,
+    /// room -> the peers in our gossipsub mesh of the room
+    pub mesh: BTreeMap<String, usize>,
+    pub nat: NatStatus,
+}
+
+impl NodeStatus {
+    /// Follow `event`, returns `false` if it is not about the status.
+    pub fn apply(&mut self, event: &NetworkEvent) -> bool {
+        match event {
+            NetworkEvent::Listening { address } => {
+                self.listen_addresses.insert(address.clone());
+            }
+            NetworkEvent::ListenAddressExpired { address } => {
+                self.listen_addresses.remove(address);
+            }
+            NetworkEvent::PeerConnected { peer_id } => {
+                self.peers.insert(peer_id.clone());
+            }
+            NetworkEvent::PeerDisconnected { peer_id } => {
+                self.peers.remove(peer_id);
+            }
+            NetworkEvent::MeshChanged { topic, peers } => {
+                self.mesh.insert(topic.clone(), *peers);
+            }
+            NetworkEvent::NatChanged { nat } => self.nat = *nat,
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// The address is on the internet, not on a LAN or this machine.
+pub fn is_public(address: &Multiaddr) -> bool {
+    let ip = match address.iter().next() {
+        Some(Protocol::Ip4(ip)) => IpAddr::V4(ip),
+        Some(Protocol::Ip6(ip)) => IpAddr::V6(ip),
+        _ => return false,
+    };
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // the shared address space of carrier-grade NATs
+                || ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+        }
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            !(ip.is_loopback()
+                || ip.is_unspecified()
+                // unique local and link local
+                || first & 0xfe00 == 0xfc00
+                || first & 0xffc0 == 0xfe80)
+        }
+    }
+}
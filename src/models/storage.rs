@@ -0,0 +1,80 @@
+use std::{io, path::PathBuf, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tokio::{
+    fs::OpenOptions,
+    io::{AsyncWriteExt, BufWriter},
+    sync::{mpsc::UnboundedReceiver, oneshot, Mutex},
+};
+
+use super::message::{ChatMessage, Edit, Reaction};
+
+/// A line of the history file.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Record {
+    pub room: String,
+    #[serde(flatten)]
+    pub entry: Entry,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Entry {
+    Message(ChatMessage),
+    Reaction(Reaction),
+    Edit(Edit),
+    /// a message of ours waits in the outbox
+    Queued(ChatMessage),
+    /// a message left the outbox
+    Dequeued {
+        message_id: String,
+    },
+}
+
+#[derive(Debug)]
+pub enum StorageMessage {
+    /// append a record to the history file
+    Append(Record),
+    /// write what is buffered to disk
+    Flush(oneshot::Sender<io::Result<()>>),
+}
+
+/// Appends the messages and reactions of the `Network` to the history file.
+///
+/// The receiver is shared so the task can be restarted
+/// without the `Network` noticing.
+pub struct Storage {
+    path: PathBuf,
+    rx: Arc<Mutex<UnboundedReceiver<StorageMessage>>>,
+}
+
+impl Storage {
+    pub fn new(path: PathBuf, rx: Arc<Mutex<UnboundedReceiver<StorageMessage>>>) -> Self {
+        Self { path, rx }
+    }
+
+    /// Run until the `Network` is dropped.
+    pub async fn run(self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        let mut file = BufWriter::new(file);
+        let mut rx = self.rx.lock().await;
+
+        while let Some(msg) = rx.recv().await {
+            match msg {
+                StorageMessage::Append(record) => {
+                    let mut line = serde_json::to_vec(&record)?;
+                    line.push(b'\n');
+                    file.write_all(&line).await?;
+                }
+                StorageMessage::Flush(reply) => {
+                    let _ = reply.send(file.flush().await);
+                }
+            }
+        }
+        file.flush().await
+    }
+}
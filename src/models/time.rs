@@ -0,0 +1,89 @@
+use std::fmt;
+
+pub const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidDate(pub String);
+
+impl fmt::Display for InvalidDate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid date {}, expected YYYY-MM-DD", self.0)
+    }
+}
+
+impl std::error::Error for InvalidDate {}
+
+/// Midnight UTC of the day `YYYY-MM-DD`, in milliseconds since the unix epoch.
+pub fn parse_date(date: &str) -> Result<u64, InvalidDate> {
+    let invalid = || InvalidDate(date.to_string());
+    let mut parts = date
+        .splitn(3, '-')
+        .map(|p| p.parse::<u64>().map_err(|_| invalid()));
+    let (Some(year), Some(month), Some(day)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(invalid());
+    };
+    let (year, month, day) = (year?, month?, day?);
+    if year < 1970 || !(1..=12).contains(&month) || !(1..=days_in_month(year, month)).contains(&day)
+    {
+        return Err(invalid());
+    }
+    Ok(days_from_civil(year, month, day) * MILLIS_PER_DAY)
+}
+
+/// The day of `timestamp`, in milliseconds since the unix epoch, as `YYYY-MM-DD` in UTC.
+pub fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_from_days(timestamp / MILLIS_PER_DAY);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// `timestamp`, in milliseconds since the unix epoch, as `YYYY-MM-DD HH:MM:SS UTC`.
+pub fn format_datetime(timestamp: u64) -> String {
+    format!("{} {} UTC", format_date(timestamp), format_time(timestamp))
+}
+
+/// The time of day of `timestamp`, in milliseconds since the unix epoch, as `HH:MM:SS` in UTC.
+pub fn format_time(timestamp: u64) -> String {
+    let seconds = timestamp % MILLIS_PER_DAY / 1000;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+fn days_in_month(year: u64, month: u64) -> u64 {
+    let leap = year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+    match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// the algorithms of http://howardhinnant.github.io/date_algorithms.html,
+// for the days since 1970-01-01
+
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: u64) -> (u64, u64, u64) {
+    let days = days + 719468;
+    let era = days / 146097;
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
@@ -0,0 +1,504 @@
+use std::{
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    os::unix::fs::OpenOptionsExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use libp2p::{gossipsub::IdentTopic, identity::Keypair, Multiaddr, PeerId};
+use log::warn;
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    mpsc::{self, Sender},
+    oneshot, Mutex,
+};
+
+use crate::{
+    models::{
+        archive::ImportReport,
+        error::NetworkError,
+        history::{History, RoomHistory},
+        message::{ChatMessage, Edit, Reaction},
+        network::{storage_error, Network, NetworkEvent, NetworkMessage},
+        presence::{Presence, PresenceState},
+        search::{SearchHit, SearchQuery},
+        status::NodeStatus,
+        storage::Storage,
+    },
+    supervisor::Supervisor,
+};
+
+/// How many times the storage task is restarted before giving up.
+const STORAGE_RESTARTS: usize = 3;
+
+/// Configuration of a `ChatNode`.
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    /// the address where the node will listen for connections
+    pub listen_address: Multiaddr,
+    /// a peer to dial as soon as the node is listening
+    pub peer: Option<Multiaddr>,
+    /// discover peers on the local network with mDNS
+    pub mdns: bool,
+    /// where the history and the identity of the node are stored,
+    /// they are only kept in memory if `None`
+    pub data_dir: Option<PathBuf>,
+    /// tell the peers which of their messages we have seen
+    pub read_receipts: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        Self {
+            listen_address: "/ip4/0.0.0.0/tcp/0".parse().expect("address to be valid"),
+            peer: None,
+            mdns: true,
+            data_dir: None,
+            read_receipts: true,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ChatNodeBuilder {
+    config: NodeConfig,
+}
+
+impl ChatNodeBuilder {
+    pub fn listen_address(mut self, addr: Multiaddr) -> Self {
+        self.config.listen_address = addr;
+        self
+    }
+
+    pub fn peer(mut self, addr: Multiaddr) -> Self {
+        self.config.peer = Some(addr);
+        self
+    }
+
+    pub fn mdns(mut self, enabled: bool) -> Self {
+        self.config.mdns = enabled;
+        self
+    }
+
+    pub fn data_dir(mut self, dir: PathBuf) -> Self {
+        self.config.data_dir = Some(dir);
+        self
+    }
+
+    pub fn read_receipts(mut self, enabled: bool) -> Self {
+        self.config.read_receipts = enabled;
+        self
+    }
+
+    /// Build the node without starting it. The returned `NodeDriver`
+    /// must be run on a tokio runtime for the node to do anything.
+    pub fn build(self) -> (ChatNode, NodeDriver) {
+        let (tx, rx) = mpsc::channel::<NetworkMessage>(200);
+        let (tx_events, _) = broadcast::channel::<NetworkEvent>(200);
+        // the peer public key (peerId), it is kept with the history
+        // so our queued messages are still ours after a restart.
+        let keypair = match &self.config.data_dir {
+            Some(dir) => load_identity(dir).unwrap_or_else(|e| {
+                warn!("using a new identity, {}: {e}", dir.display());
+                Keypair::generate_ed25519()
+            }),
+            None => Keypair::generate_ed25519(),
+        };
+
+        let node = ChatNode {
+            keypair: keypair.clone(),
+            peer_id: keypair.public().to_peer_id(),
+            tx: tx.clone(),
+            tx_events: tx_events.clone(),
+        };
+        let driver = NodeDriver {
+            config: self.config,
+            keypair,
+            tx,
+            rx,
+            tx_events,
+        };
+
+        (node, driver)
+    }
+
+    /// Build the node and run it on the current tokio runtime.
+    pub fn spawn(self) -> ChatNode {
+        let (node, driver) = self.build();
+        tokio::spawn(driver.run());
+        node
+    }
+}
+
+/// The keypair stored in `dir`, it is created the first time.
+fn load_identity(dir: &Path) -> io::Result<Keypair> {
+    let path = dir.join("identity.key");
+    match std::fs::read(&path) {
+        Ok(bytes) => Keypair::from_protobuf_encoding(&bytes).map_err(io::Error::other),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let keypair = Keypair::generate_ed25519();
+            let bytes = keypair.to_protobuf_encoding().map_err(io::Error::other)?;
+            std::fs::create_dir_all(dir)?;
+            // only we may read the private key
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path)?
+                .write_all(&bytes)?;
+            Ok(keypair)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Runs the `Network` of a `ChatNode`.
+pub struct NodeDriver {
+    config: NodeConfig,
+    keypair: Keypair,
+    tx: Sender<NetworkMessage>,
+    rx: mpsc::Receiver<NetworkMessage>,
+    tx_events: broadcast::Sender<NetworkEvent>,
+}
+
+impl NodeDriver {
+    /// Run until the node is asked to quit.
+    ///
+    /// Fails only if the node can't be started or one of its tasks
+    /// fails for good, other errors are sent as `NetworkEvent::Error`.
+    pub async fn run(self) -> Result<(), NetworkError> {
+        let mut supervisor = Supervisor::new();
+        self.supervise(&mut supervisor)?;
+        supervisor.run().await.map_err(|e| NetworkError::Task {
+            task: e.task.to_string(),
+            reason: e.reason,
+        })
+    }
+
+    /// Start the tasks of the node, the network and the storage
+    /// of the history, under `supervisor`.
+    pub fn supervise(self, supervisor: &mut Supervisor) -> Result<(), NetworkError> {
+        let tx_events = self.tx_events.clone();
+        let report = |error: NetworkError| {
+            let _ = tx_events.send(NetworkEvent::Error {
+                error: error.clone(),
+            });
+            error
+        };
+
+        let (history, storage) = match &self.config.data_dir {
+            Some(dir) => {
+                std::fs::create_dir_all(dir).map_err(|e| report(storage_error(e)))?;
+                let path = dir.join("history.jsonl");
+                let history = History::load(&path).map_err(|e| report(storage_error(e)))?;
+
+                // once storage failed for good, its factory and the receiver
+                // are dropped, so the records are not queued forever
+                let (tx, rx) = mpsc::unbounded_channel();
+                let rx = Arc::new(Mutex::new(rx));
+                supervisor.spawn_restartable("storage", STORAGE_RESTARTS, move || {
+                    let storage = Storage::new(path.clone(), rx.clone());
+                    async move { Ok(storage.run().await?) }
+                });
+                (history, Some(tx))
+            }
+            None => (History::default(), None),
+        };
+
+        let mut network = Network::new(
+            self.config,
+            self.keypair,
+            self.tx,
+            self.rx,
+            self.tx_events,
+            history,
+            storage,
+        )
+        .map_err(report)?;
+
+        supervisor.spawn("network", async move {
+            network.daemon().await;
+            Ok(())
+        });
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum NodeError {
+    /// the `NodeDriver` is not running anymore
+    NotRunning,
+}
+
+impl fmt::Display for NodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NodeError::NotRunning => write!(f, "the node is not running"),
+        }
+    }
+}
+
+impl std::error::Error for NodeError {}
+
+/// A handle to a peer on the chat network.
+///
+/// It is cheap to clone, and all clones talk to the same node.
+#[derive(Debug, Clone)]
+pub struct ChatNode {
+    /// signs our edits
+    keypair: Keypair,
+    peer_id: PeerId,
+    tx: Sender<NetworkMessage>,
+    tx_events: broadcast::Sender<NetworkEvent>,
+}
+
+impl ChatNode {
+    pub fn builder() -> ChatNodeBuilder {
+        ChatNodeBuilder::default()
+    }
+
+    pub async fn join(&self, room: &str) -> Result<(), NodeError> {
+        self.send_message(NetworkMessage::Subscribed(IdentTopic::new(room)))
+            .await
+    }
+
+    pub async fn leave(&self, room: &str) -> Result<(), NodeError> {
+        self.send_message(NetworkMessage::Unsubscribed(IdentTopic::new(room)))
+            .await
+    }
+
+    /// Publish `message` to everyone in `room`.
+    pub async fn send(&self, room: &str, message: &str) -> Result<ChatMessage, NodeError> {
+        let message = ChatMessage::new(&self.peer_id, message.to_string()).signed(&self.keypair);
+        self.publish(room, message).await
+    }
+
+    /// Publish `message` to everyone in `room`, as a reply to `parent_id`.
+    pub async fn reply(
+        &self,
+        room: &str,
+        parent_id: &str,
+        message: &str,
+    ) -> Result<ChatMessage, NodeError> {
+        let message = ChatMessage::new(&self.peer_id, message.to_string())
+            .in_reply_to(parent_id.to_string())
+            .signed(&self.keypair);
+        self.publish(room, message).await
+    }
+
+    /// Publish our `message` again, after its delivery failed.
+    /// Peers that already got it ignore it.
+    pub async fn resend(&self, room: &str, message: ChatMessage) -> Result<ChatMessage, NodeError> {
+        self.send_message(NetworkMessage::Resend(
+            IdentTopic::new(room),
+            message.clone(),
+        ))
+        .await?;
+        Ok(message)
+    }
+
+    async fn publish(&self, room: &str, message: ChatMessage) -> Result<ChatMessage, NodeError> {
+        self.send_message(NetworkMessage::MessageReceived(
+            IdentTopic::new(room),
+            message.clone(),
+        ))
+        .await?;
+        Ok(message)
+    }
+
+    /// React to the message `message_id` of `room` with `emoji`,
+    /// or take the reaction back if `added` is `false`.
+    pub async fn react(
+        &self,
+        room: &str,
+        message_id: &str,
+        emoji: &str,
+        added: bool,
+    ) -> Result<Reaction, NodeError> {
+        let reaction = Reaction::new(
+            &self.keypair,
+            message_id.to_string(),
+            emoji.to_string(),
+            added,
+        );
+        self.send_message(NetworkMessage::Reaction(
+            IdentTopic::new(room),
+            reaction.clone(),
+        ))
+        .await?;
+        Ok(reaction)
+    }
+
+    /// Tell the peers in `room` that we are typing. Nothing is
+    /// stored, and it is up to the caller to not send it too often.
+    pub async fn typing(&self, room: &str) -> Result<(), NodeError> {
+        self.send_message(NetworkMessage::Typing(IdentTopic::new(room)))
+            .await
+    }
+
+    /// Tell the peers in `room` that we have seen `message_ids`,
+    /// unless read receipts are turned off.
+    pub async fn mark_seen(&self, room: &str, message_ids: Vec<String>) -> Result<(), NodeError> {
+        self.send_message(NetworkMessage::Seen(IdentTopic::new(room), message_ids))
+            .await
+    }
+
+    pub async fn dial(&self, addr: Multiaddr) -> Result<(), NodeError> {
+        self.send_message(NetworkMessage::Dial(addr)).await
+    }
+
+    /// Replace the body of our message `message_id` in `room`.
+    pub async fn edit(&self, room: &str, message_id: &str, body: &str) -> Result<Edit, NodeError> {
+        self.send_edit(room, message_id, Some(body.to_string()))
+            .await
+    }
+
+    /// Delete our message `message_id` in `room`, peers
+    /// will show a tombstone in its place.
+    pub async fn delete(&self, room: &str, message_id: &str) -> Result<Edit, NodeError> {
+        self.send_edit(room, message_id, None).await
+    }
+
+    /// The edits of the message `message_id` in `room`, oldest first.
+    pub async fn edit_history(&self, room: &str, message_id: &str) -> Result<Vec<Edit>, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::EditHistory(
+            room.to_string(),
+            message_id.to_string(),
+            tx,
+        ))
+        .await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// The stored messages of `room`, with their reactions and edits.
+    pub async fn history(&self, room: &str) -> Result<RoomHistory, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::RoomHistory(room.to_string(), tx))
+            .await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// Add the messages and edits of an export to the history of `room`,
+    /// the ones that are not signed by their author are rejected.
+    pub async fn import(
+        &self,
+        room: &str,
+        history: RoomHistory,
+    ) -> Result<ImportReport, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::Import(room.to_string(), history, tx))
+            .await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// The stored messages that match `query`, newest first.
+    pub async fn search(&self, query: SearchQuery) -> Result<Vec<SearchHit>, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::Search(query, tx)).await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    async fn send_edit(
+        &self,
+        room: &str,
+        message_id: &str,
+        body: Option<String>,
+    ) -> Result<Edit, NodeError> {
+        let edit = Edit::new(&self.keypair, message_id.to_string(), body);
+        self.send_message(NetworkMessage::Edit(IdentTopic::new(room), edit.clone()))
+            .await?;
+        Ok(edit)
+    }
+
+    /// Tell everyone that we are `state`, with the status text `status`.
+    pub async fn set_presence(&self, state: PresenceState, status: &str) -> Result<(), NodeError> {
+        self.send_message(NetworkMessage::SetPresence(state, status.to_string()))
+            .await
+    }
+
+    /// The presence of the peers we heard from lately.
+    pub async fn presence(&self) -> Result<Vec<Presence>, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::ListPresence(tx)).await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// The peers we have a connection with.
+    pub async fn peers(&self) -> Result<Vec<PeerId>, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::ListPeers(tx)).await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// The peers we know are in `room`, before they are in our mesh.
+    pub async fn room_peers(&self, room: &str) -> Result<Vec<PeerId>, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::RoomPeers(room.to_string(), tx))
+            .await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// The rooms we have joined.
+    pub async fn rooms(&self) -> Result<Vec<String>, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::ListRooms(tx)).await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// The rooms with messages in the history, joined or not.
+    pub async fn stored_rooms(&self) -> Result<Vec<String>, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::StoredRooms(tx)).await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    /// Our addresses, peers, meshes and NAT status.
+    pub async fn status(&self) -> Result<NodeStatus, NodeError> {
+        let (tx, rx) = oneshot::channel();
+        self.send_message(NetworkMessage::Status(tx)).await?;
+        rx.await.map_err(|_| NodeError::NotRunning)
+    }
+
+    pub fn peer_id(&self) -> PeerId {
+        self.peer_id
+    }
+
+    /// Stop the node. It leaves every room, flushes the
+    /// history and closes its connections first.
+    pub async fn quit(&self) -> Result<(), NodeError> {
+        self.send_message(NetworkMessage::Quit).await
+    }
+
+    /// Subscribe to the events of the node,
+    /// only events from now on are received.
+    pub fn events(&self) -> EventStream {
+        EventStream {
+            rx: self.tx_events.subscribe(),
+        }
+    }
+
+    async fn send_message(&self, msg: NetworkMessage) -> Result<(), NodeError> {
+        self.tx.send(msg).await.map_err(|_| NodeError::NotRunning)
+    }
+}
+
+/// The events of a `ChatNode`, see `ChatNode::events`.
+pub struct EventStream {
+    rx: broadcast::Receiver<NetworkEvent>,
+}
+
+impl EventStream {
+    /// Wait for the next event, `None` when the node has stopped.
+    pub async fn recv(&mut self) -> Option<NetworkEvent> {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => return Some(event),
+                Err(RecvError::Lagged(n)) => warn!("event stream lagged {n} events"),
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
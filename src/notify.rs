@@ -0,0 +1,69 @@
+//! Tell the user about messages they should not miss,
+//! with the terminal bell or a desktop notification.
+use std::{future::Future, process::Stdio};
+
+use tokio::process::Command;
+
+use crate::{config::Alert, supervisor::TaskResult};
+
+/// If `body` mentions one of `names`, as a whole word and ignoring case.
+/// A name can be written with a leading `@`.
+pub fn is_mention<S: AsRef<str>>(body: &str, names: &[S]) -> bool {
+    let body = body.to_lowercase();
+    names.iter().any(|name| {
+        let name = name.as_ref().trim_start_matches('@').to_lowercase();
+        !name.is_empty()
+            && body.match_indices(&name).any(|(i, _)| {
+                let before = body[..i].chars().next_back();
+                let after = body[i + name.len()..].chars().next();
+                !before.is_some_and(char::is_alphanumeric)
+                    && !after.is_some_and(char::is_alphanumeric)
+            })
+    })
+}
+
+/// The bytes to write to the terminal for `alert`. Control characters
+/// are left out of `title` and `body`, so a message can't end the
+/// escape sequence early and send its own.
+pub fn escape(alert: Alert, title: &str, body: &str) -> String {
+    let clean = |s: &str| s.chars().filter(|c| !c.is_control()).collect::<String>();
+    // the fields of OSC 777 are separated by `;`
+    let title = clean(title).replace(';', ",");
+    let body = clean(body);
+    match alert {
+        Alert::Bell => "\x07".to_string(),
+        Alert::Osc9 => format!("\x1b]9;{title}: {body}\x07"),
+        Alert::Osc777 => format!("\x1b]777;notify;{title};{body}\x07"),
+        Alert::None => String::new(),
+    }
+}
+
+/// Run the notify `command` with `sh -c`. The message is passed
+/// in the environment, it is never part of the command itself.
+///
+/// The command is started right away, the future waits for it to exit.
+pub fn run_command(
+    command: &str,
+    room: &str,
+    author: &str,
+    body: &str,
+) -> impl Future<Output = TaskResult> {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("P2P_CHAT_ROOM", room)
+        .env("P2P_CHAT_AUTHOR", author)
+        .env("P2P_CHAT_MESSAGE", body)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn();
+
+    async move {
+        let status = child?.wait().await?;
+        if !status.success() {
+            return Err(format!("exited with {status}").into());
+        }
+        Ok(())
+    }
+}
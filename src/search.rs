@@ -0,0 +1,281 @@
+use crate::node::ChatNode;
+use crossterm::event::{KeyCode, KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use tokio::sync::mpsc::Sender;
+use tui::{
+    backend::Backend,
+    layout::{Constraint, Direction, Layout, Rect},
+    style::Modifier,
+    text::{Span, Spans, Text},
+    widgets::{Block, Borders, List, ListItem, ListState, Paragraph},
+    Frame,
+};
+
+use crate::{
+    app::{AppMessage, AppStyle, Page},
+    keymap::{is_chord, Action, Keymap},
+    models::{
+        message::ChatMessage,
+        search::{SearchHit, SearchQuery},
+        time::{format_date, InvalidDate},
+    },
+    topic_list::TopicList,
+    ui::{contains, draw_input, input_height, short_name, wrap},
+};
+
+/// The search over the stored messages of every room.
+#[derive(Clone, Debug)]
+pub struct Search {
+    pub state: ListState,
+    /// the query, see `SearchQuery` for the filters
+    pub input: String,
+    /// the query is being typed
+    pub editing: bool,
+    /// the results of the last search, newest first
+    pub hits: Vec<SearchHit>,
+    /// a search ran, even if nothing matched
+    pub searched: bool,
+    /// our own `PeerId`
+    pub me: String,
+    /// where the results and the input were last drawn
+    pub hits_area: Rect,
+    pub input_area: Rect,
+}
+
+impl Search {
+    /// A search with `input` as the query, that is still being typed.
+    pub fn new(me: String, input: String) -> Self {
+        Self {
+            state: ListState::default(),
+            input,
+            editing: true,
+            hits: vec![],
+            searched: false,
+            me,
+            hits_area: Rect::default(),
+            input_area: Rect::default(),
+        }
+    }
+
+    pub fn query(&self) -> Result<SearchQuery, InvalidDate> {
+        self.input.parse()
+    }
+
+    pub async fn keybindings(
+        &mut self,
+        k: KeyEvent,
+        keymap: &Keymap,
+        tx: &Sender<AppMessage>,
+        _node: &ChatNode,
+    ) {
+        if self.editing {
+            match k.code {
+                _ if keymap.while_typing(k, Action::Open, KeyCode::Enter) => {
+                    let msg = match self.query() {
+                        Ok(query) => {
+                            self.editing = false;
+                            AppMessage::Search { query }
+                        }
+                        Err(e) => AppMessage::Error {
+                            error: e.to_string(),
+                        },
+                    };
+                    let _ = tx.send(msg).await;
+                }
+                KeyCode::Char(c) if !is_chord(k) => self.input.push(c),
+                KeyCode::Backspace => {
+                    self.input.pop();
+                }
+                _ if keymap.while_typing(k, Action::Back, KeyCode::Esc) => self.editing = false,
+                _ => {}
+            }
+            return;
+        }
+
+        match keymap.action(k) {
+            Some(Action::Insert | Action::Search) => self.editing = true,
+            Some(Action::Down) => self.next(),
+            Some(Action::Up) => self.previous(),
+            // jump to the message in its room
+            Some(Action::Open) => {
+                let Some(hit) = self.selected() else {
+                    return;
+                };
+                let msg = AppMessage::OpenMessage {
+                    room: hit.room.clone(),
+                    message_id: hit.message.id.clone(),
+                };
+                let _ = tx.send(msg).await;
+            }
+            Some(Action::Back) => {
+                let _ = tx
+                    .send(AppMessage::ChangePage {
+                        page: Page::TopicList(TopicList::new()),
+                    })
+                    .await;
+            }
+            _ => {}
+        }
+    }
+
+    /// The wheel moves through the results, a click on the
+    /// input edits the query, and one on the results stops.
+    pub async fn mouse(&mut self, m: MouseEvent, _tx: &Sender<AppMessage>, _node: &ChatNode) {
+        match m.kind {
+            MouseEventKind::ScrollDown => self.next(),
+            MouseEventKind::ScrollUp => self.previous(),
+            MouseEventKind::Down(MouseButton::Left) => {
+                if contains(self.input_area, m.column, m.row) {
+                    self.editing = true;
+                } else if contains(self.hits_area, m.column, m.row) {
+                    self.editing = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Show the results of the search.
+    pub fn push_hits(&mut self, hits: Vec<SearchHit>) {
+        self.state.select((!hits.is_empty()).then_some(0));
+        self.hits = hits;
+        self.searched = true;
+    }
+
+    pub fn selected(&self) -> Option<&SearchHit> {
+        self.hits.get(self.state.selected()?)
+    }
+
+    pub fn next(&mut self) {
+        let Some(last) = self.hits.len().checked_sub(1) else {
+            return;
+        };
+        let i = self.state.selected().map_or(0, |i| (i + 1).min(last));
+        self.state.select(Some(i));
+    }
+
+    pub fn previous(&mut self) {
+        if self.hits.is_empty() {
+            return;
+        }
+        let i = self.state.selected().map_or(0, |i| i.saturating_sub(1));
+        self.state.select(Some(i));
+    }
+
+    /// A message of a hit, on one line, after `prefix`.
+    fn line<'a>(&self, prefix: &'a str, message: &'a ChatMessage, ui: &AppStyle) -> Spans<'a> {
+        let author = if message.author == self.me {
+            "you"
+        } else {
+            short_name(&message.author)
+        };
+        Spans::from(vec![
+            Span::raw(prefix),
+            Span::styled(author.to_string(), ui.author_style(&message.author)),
+            Span::raw(": "),
+            Span::raw(message.body.as_str()),
+        ])
+    }
+
+    pub fn draw<B: Backend>(
+        &mut self,
+        f: &mut Frame<B>,
+        area: Rect,
+        ui: &AppStyle,
+        keymap: &Keymap,
+    ) {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Length(1),
+                    Constraint::Min(1),
+                    Constraint::Length(input_height(&self.input, area.width)),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+        self.hits_area = chunks[1];
+        self.input_area = chunks[2];
+
+        let msg = if self.editing {
+            vec![
+                Span::raw("Press "),
+                Span::styled(
+                    keymap.typing_label(Action::Open, KeyCode::Enter),
+                    ui.normal_style.add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(" to search, filters: room: from: after: before:"),
+            ]
+        } else {
+            vec![
+                Span::raw("Press "),
+                Span::styled(
+                    keymap.label(Action::Back),
+                    ui.normal_style.add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(" to exit, "),
+                Span::styled(
+                    keymap.label(Action::Search),
+                    ui.normal_style.add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(" to search again, "),
+                Span::styled(
+                    keymap.label(Action::Open),
+                    ui.normal_style.add_modifier(Modifier::BOLD),
+                ),
+                Span::raw(" to open it."),
+            ]
+        };
+        let mut text = Text::from(Spans::from(msg));
+        text.patch_style(ui.base_style);
+        f.render_widget(Paragraph::new(text), chunks[0]);
+
+        let cursor = draw_input(f, chunks[2], &self.input, "Search".to_string(), ui);
+        if self.editing {
+            f.set_cursor(cursor.0, cursor.1)
+        }
+
+        // each hit, between the messages around it
+        let width = chunks[1].width.saturating_sub(2) as usize;
+        let hits: Vec<ListItem> = self
+            .hits
+            .iter()
+            .map(|hit| {
+                let mut lines = vec![Spans::from(Span::styled(
+                    format!("#{} · {}", hit.room, format_date(hit.message.timestamp)),
+                    ui.normal_style,
+                ))];
+                if let Some(previous) = &hit.previous {
+                    lines.push(self.line("  ", previous, ui));
+                }
+                let mut line = self.line("> ", &hit.message, ui);
+                for span in &mut line.0 {
+                    span.style = span.style.add_modifier(Modifier::BOLD);
+                }
+                lines.push(line);
+                if let Some(next) = &hit.next {
+                    lines.push(self.line("  ", next, ui));
+                }
+                let lines: Vec<Spans> = lines.into_iter().flat_map(|l| wrap(l, width)).collect();
+                ListItem::new(lines)
+            })
+            .collect();
+
+        let title = match self.hits.len() {
+            _ if !self.searched => "Results".to_string(),
+            0 => "No results".to_string(),
+            1 => "1 result".to_string(),
+            n => format!("{n} results"),
+        };
+        let hits = List::new(hits)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(ui.border_style)
+                    .title(title),
+            )
+            .style(ui.base_style)
+            .highlight_style(ui.selected_style);
+        f.render_stateful_widget(hits, chunks[1], &mut self.state);
+    }
+}
@@ -0,0 +1,161 @@
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tui::{
+    backend::Backend,
+    layout::Rect,
+    text::{Span, Spans},
+    widgets::Paragraph,
+    Frame,
+};
+
+use crate::{
+    app::AppStyle,
+    models::{
+        network::NetworkEvent,
+        status::{NatStatus, NodeStatus},
+    },
+    ui::{is_compact, short_name},
+};
+
+/// How long we may have no peers after starting before we are isolated.
+pub const CONNECT_GRACE: Duration = Duration::from_secs(15);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Connectivity {
+    /// no peers yet, but we just started
+    Connecting,
+    Online,
+    /// no peers, and no more reason to expect them
+    Isolated,
+}
+
+/// Who we are and how the node is doing, on the last line.
+#[derive(Debug, Clone)]
+pub struct StatusBar {
+    pub nickname: Option<String>,
+    /// our own `PeerId`
+    pub me: String,
+    pub status: NodeStatus,
+    /// when the app started
+    pub started: Instant,
+    /// we had a peer at some point
+    pub was_online: bool,
+}
+
+impl StatusBar {
+    pub fn new(me: String, nickname: Option<String>) -> Self {
+        Self {
+            nickname,
+            me,
+            status: NodeStatus::default(),
+            started: Instant::now(),
+            was_online: false,
+        }
+    }
+
+    /// Show `status`, as the node reported it.
+    pub fn set_status(&mut self, status: NodeStatus) {
+        self.status = status;
+        self.was_online |= !self.status.peers.is_empty();
+    }
+
+    /// Follow an event of the node, returns `false` if it is not about the status.
+    pub fn apply(&mut self, event: &NetworkEvent) -> bool {
+        let applied = self.status.apply(event);
+        self.was_online |= !self.status.peers.is_empty();
+        applied
+    }
+
+    pub fn connectivity(&self, now: Instant) -> Connectivity {
+        if !self.status.peers.is_empty() {
+            Connectivity::Online
+        } else if self.was_online || now.duration_since(self.started) >= CONNECT_GRACE {
+            Connectivity::Isolated
+        } else {
+            Connectivity::Connecting
+        }
+    }
+
+    /// Draw the bar on the last line of `area`, and return
+    /// what is left. `room` is the room on the screen, if any.
+    pub fn draw<B: Backend>(
+        &self,
+        f: &mut Frame<B>,
+        area: Rect,
+        room: Option<&str>,
+        style: &AppStyle,
+    ) -> Rect {
+        if area.height < 2 {
+            return area;
+        }
+        let bar = Rect {
+            y: area.y + area.height - 1,
+            height: 1,
+            ..area
+        };
+
+        let separator = || Span::raw(" │ ");
+        let me = short_name(&self.me);
+        let mut spans = vec![match &self.nickname {
+            Some(nickname) => Span::raw(format!(" {nickname} ({me})")),
+            None => Span::raw(format!(" {me}")),
+        }];
+        spans.push(separator());
+        spans.push(match self.connectivity(Instant::now()) {
+            Connectivity::Connecting => Span::styled("connecting…", style.system_style),
+            Connectivity::Online => Span::styled("online", style.normal_style),
+            Connectivity::Isolated => Span::styled("isolated", style.error_style),
+        });
+        spans.push(separator());
+        spans.push(Span::raw(match self.status.peers.len() {
+            1 => "1 peer".to_string(),
+            n => format!("{n} peers"),
+        }));
+        if let Some(room) = room {
+            let mesh = self.status.mesh.get(room).copied().unwrap_or(0);
+            spans.push(separator());
+            spans.push(Span::raw(format!("mesh {mesh}")));
+        }
+        // the rest is the least useful, narrow screens go without it
+        if !is_compact(area) {
+            self.push_network(&mut spans, style);
+        }
+
+        let mut text = Spans::from(spans);
+        for span in &mut text.0 {
+            span.style = style.status_style.patch(span.style);
+        }
+        f.render_widget(Paragraph::new(text).style(style.status_style), bar);
+
+        Rect {
+            height: area.height - 1,
+            ..area
+        }
+    }
+
+    /// Whether we are behind a NAT, and where we listen.
+    fn push_network(&self, spans: &mut Vec<Span>, style: &AppStyle) {
+        let separator = || Span::raw(" │ ");
+        spans.push(separator());
+        spans.push(Span::raw(match self.status.nat {
+            NatStatus::Unknown => "NAT unknown",
+            NatStatus::Public => "public",
+            NatStatus::Private => "behind NAT",
+        }));
+        spans.push(separator());
+        // our `PeerId` is already on the left
+        let mut addresses = self
+            .status
+            .listen_addresses
+            .iter()
+            .map(|a| a.split("/p2p/").next().unwrap_or(a));
+        spans.push(match addresses.next() {
+            Some(address) => match addresses.count() {
+                0 => Span::raw(address.to_string()),
+                more => Span::raw(format!("{address} +{more}")),
+            },
+            None => Span::styled("not listening", style.system_style),
+        });
+    }
+}
@@ -0,0 +1,147 @@
+//! Runs the long-lived tasks of the app, like the UI, the network
+//! and the storage, on the current tokio runtime. Tasks that can be
+//! restarted are restarted when they fail, the others are reported.
+use std::{error::Error, fmt, future::Future, panic::AssertUnwindSafe};
+
+use futures::{future::BoxFuture, FutureExt};
+use log::{error, info, warn};
+use tokio::task::JoinSet;
+
+/// What a task returns when it stops.
+pub type TaskResult = Result<(), Box<dyn Error + Send + Sync>>;
+
+/// A task failed and could not be restarted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskError {
+    pub task: &'static str,
+    pub reason: String,
+}
+
+impl fmt::Display for TaskError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "the {} task failed: {}", self.task, self.reason)
+    }
+}
+
+impl Error for TaskError {}
+
+type Factory = Box<dyn FnMut() -> BoxFuture<'static, TaskResult> + Send>;
+type FailureHook = Box<dyn Fn(&TaskError) + Send>;
+
+struct Task {
+    name: &'static str,
+    restarts_left: usize,
+    /// how to start the task again, `None` if it can't be restarted
+    factory: Option<Factory>,
+}
+
+#[derive(Default)]
+pub struct Supervisor {
+    tasks: Vec<Task>,
+    running: JoinSet<(usize, TaskResult)>,
+    on_failure: Option<FailureHook>,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `future` as the task `name`, it is not restarted.
+    pub fn spawn<F>(&mut self, name: &'static str, future: F)
+    where
+        F: Future<Output = TaskResult> + Send + 'static,
+    {
+        let id = self.tasks.len();
+        self.tasks.push(Task {
+            name,
+            restarts_left: 0,
+            factory: None,
+        });
+        self.running.spawn(catch_panic(id, future.boxed()));
+    }
+
+    /// Run the future made by `factory` as the task `name`, and
+    /// make a new one when it fails, at most `max_restarts` times.
+    pub fn spawn_restartable<F, Fut>(
+        &mut self,
+        name: &'static str,
+        max_restarts: usize,
+        mut factory: F,
+    ) where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = TaskResult> + Send + 'static,
+    {
+        let mut factory: Factory = Box::new(move || factory().boxed());
+        let id = self.tasks.len();
+        self.running.spawn(catch_panic(id, factory()));
+        self.tasks.push(Task {
+            name,
+            restarts_left: max_restarts,
+            factory: Some(factory),
+        });
+    }
+
+    /// Called when a task fails for good, to stop the tasks
+    /// that depend on it.
+    pub fn on_failure(&mut self, f: impl Fn(&TaskError) + Send + 'static) {
+        self.on_failure = Some(Box::new(f));
+    }
+
+    /// Wait until every task has stopped.
+    ///
+    /// Returns the first failure of a task that
+    /// could not be restarted, if there was one.
+    pub async fn run(mut self) -> Result<(), TaskError> {
+        let mut failure = None;
+
+        while let Some(joined) = self.running.join_next().await {
+            // tasks are only cancelled when the supervisor is dropped
+            let Ok((id, result)) = joined else { continue };
+            let task = &mut self.tasks[id];
+
+            let Err(e) = result else {
+                info!("the {} task stopped", task.name);
+                task.factory = None;
+                continue;
+            };
+
+            if let (Some(factory), 1..) = (&mut task.factory, task.restarts_left) {
+                warn!("the {} task failed: {e}, restarting it", task.name);
+                task.restarts_left -= 1;
+                self.running.spawn(catch_panic(id, factory()));
+                continue;
+            }
+            // it won't run again, so let go of what the factory holds,
+            // e.g. a channel the other tasks should see closed
+            task.factory = None;
+
+            let e = TaskError {
+                task: task.name,
+                reason: e.to_string(),
+            };
+            error!("{e}");
+            if let Some(on_failure) = &self.on_failure {
+                on_failure(&e);
+            }
+            failure.get_or_insert(e);
+        }
+
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+/// A panic is just another way for a task to fail.
+async fn catch_panic(id: usize, future: BoxFuture<'static, TaskResult>) -> (usize, TaskResult) {
+    match AssertUnwindSafe(future).catch_unwind().await {
+        Ok(result) => (id, result),
+        Err(panic) => {
+            let reason = panic
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "unknown panic".to_string());
+            (id, Err(format!("panicked: {reason}").into()))
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! The colors of the TUI, the built-in themes and the ones from the config.
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer};
+use tui::style::{Color, Modifier, Style};
+
+/// The names of the built-in themes, `dark` is the default.
+pub const THEMES: [&str; 4] = ["dark", "light", "high-contrast", "solarized"];
+
+#[derive(Debug, Clone)]
+pub struct AppStyle {
+    /// text and background
+    pub base_style: Style,
+    pub selected_style: Style,
+    /// keys in the help line and headers
+    pub normal_style: Style,
+    pub error_style: Style,
+    pub border_style: Style,
+    /// what the app says, not the peers, e.g. who is typing
+    pub system_style: Style,
+    /// messages that mention us
+    pub mention_style: Style,
+    /// the status bar at the bottom
+    pub status_style: Style,
+    /// the colors of the authors, each peer always gets the same one
+    pub author_colors: Vec<Color>,
+}
+
+impl Default for AppStyle {
+    fn default() -> Self {
+        AppStyle {
+            base_style: Style::default().fg(Color::Gray),
+            selected_style: Style::default().bg(Color::LightBlue).fg(Color::DarkGray),
+            normal_style: Style::default().fg(Color::LightBlue),
+            error_style: Style::default().fg(Color::Red),
+            border_style: Style::default().fg(Color::Gray),
+            system_style: Style::default().fg(Color::DarkGray),
+            mention_style: Style::default()
+                .fg(Color::Yellow)
+                .add_modifier(Modifier::BOLD),
+            status_style: Style::default().fg(Color::Gray).bg(Color::Black),
+            author_colors: vec![
+                Color::LightRed,
+                Color::LightGreen,
+                Color::LightYellow,
+                Color::LightMagenta,
+                Color::LightCyan,
+                Color::LightBlue,
+            ],
+        }
+    }
+}
+
+impl AppStyle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The built-in theme `name`.
+    pub fn named(name: &str) -> Option<Self> {
+        let style = match name {
+            "dark" => Self::default(),
+            "light" => AppStyle {
+                base_style: Style::default().fg(Color::Black).bg(Color::White),
+                selected_style: Style::default().bg(Color::Blue).fg(Color::White),
+                normal_style: Style::default().fg(Color::Blue),
+                error_style: Style::default().fg(Color::Red),
+                border_style: Style::default().fg(Color::DarkGray),
+                system_style: Style::default().fg(Color::DarkGray),
+                mention_style: Style::default().bg(Color::Yellow).fg(Color::Black),
+                status_style: Style::default().fg(Color::White).bg(Color::Blue),
+                author_colors: vec![
+                    Color::Red,
+                    Color::Green,
+                    Color::Magenta,
+                    Color::Blue,
+                    Color::Cyan,
+                ],
+            },
+            "high-contrast" => AppStyle {
+                base_style: Style::default().fg(Color::White).bg(Color::Black),
+                selected_style: Style::default()
+                    .bg(Color::White)
+                    .fg(Color::Black)
+                    .add_modifier(Modifier::BOLD),
+                normal_style: Style::default()
+                    .fg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+                error_style: Style::default()
+                    .fg(Color::LightRed)
+                    .add_modifier(Modifier::BOLD),
+                border_style: Style::default().fg(Color::White),
+                system_style: Style::default().fg(Color::Gray),
+                mention_style: Style::default()
+                    .bg(Color::Yellow)
+                    .fg(Color::Black)
+                    .add_modifier(Modifier::BOLD),
+                status_style: Style::default().fg(Color::Black).bg(Color::White),
+                author_colors: vec![
+                    Color::Yellow,
+                    Color::Cyan,
+                    Color::LightGreen,
+                    Color::LightMagenta,
+                    Color::LightRed,
+                ],
+            },
+            "solarized" => {
+                let (base03, base02, base01, base0, base1) = (
+                    Color::Rgb(0x00, 0x2b, 0x36),
+                    Color::Rgb(0x07, 0x36, 0x42),
+                    Color::Rgb(0x58, 0x6e, 0x75),
+                    Color::Rgb(0x83, 0x94, 0x96),
+                    Color::Rgb(0x93, 0xa1, 0xa1),
+                );
+                let (yellow, orange, red, magenta, violet, blue, cyan, green) = (
+                    Color::Rgb(0xb5, 0x89, 0x00),
+                    Color::Rgb(0xcb, 0x4b, 0x16),
+                    Color::Rgb(0xdc, 0x32, 0x2f),
+                    Color::Rgb(0xd3, 0x36, 0x82),
+                    Color::Rgb(0x6c, 0x71, 0xc4),
+                    Color::Rgb(0x26, 0x8b, 0xd2),
+                    Color::Rgb(0x2a, 0xa1, 0x98),
+                    Color::Rgb(0x85, 0x99, 0x00),
+                );
+                AppStyle {
+                    base_style: Style::default().fg(base0).bg(base03),
+                    selected_style: Style::default().fg(base1).bg(base02),
+                    normal_style: Style::default().fg(blue),
+                    error_style: Style::default().fg(red),
+                    border_style: Style::default().fg(base01),
+                    system_style: Style::default().fg(base01),
+                    mention_style: Style::default().fg(yellow).add_modifier(Modifier::BOLD),
+                    status_style: Style::default().fg(base1).bg(base02),
+                    author_colors: vec![yellow, orange, red, magenta, violet, blue, cyan, green],
+                }
+            }
+            _ => return None,
+        };
+        Some(style)
+    }
+
+    /// The style of the name of `peer_id`, the same for every run.
+    pub fn author_style(&self, peer_id: &str) -> Style {
+        if self.author_colors.is_empty() {
+            return self.base_style;
+        }
+        // FNV-1a, the hasher of std may change between releases
+        let hash = peer_id.bytes().fold(0xcbf29ce484222325u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x100000001b3)
+        });
+        let color = self.author_colors[(hash % self.author_colors.len() as u64) as usize];
+        self.base_style.fg(color)
+    }
+}
+
+/// A theme from the config, on top of a built-in theme.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ThemeConfig {
+    /// the built-in theme this one changes, `dark` if not set
+    pub inherits: Option<String>,
+    pub text: Option<ThemeColor>,
+    pub background: Option<ThemeColor>,
+    pub accent: Option<ThemeColor>,
+    pub selected_text: Option<ThemeColor>,
+    pub selected_background: Option<ThemeColor>,
+    pub error: Option<ThemeColor>,
+    pub border: Option<ThemeColor>,
+    pub system: Option<ThemeColor>,
+    pub mention: Option<ThemeColor>,
+    pub status_text: Option<ThemeColor>,
+    pub status_background: Option<ThemeColor>,
+    pub authors: Vec<ThemeColor>,
+}
+
+impl ThemeConfig {
+    /// The style of this theme, `None` if it inherits an unknown theme.
+    pub fn style(&self) -> Option<AppStyle> {
+        let mut style = AppStyle::named(self.inherits.as_deref().unwrap_or("dark"))?;
+        let fg = |style: &mut Style, color: &Option<ThemeColor>| {
+            if let Some(ThemeColor(color)) = color {
+                *style = style.fg(*color);
+            }
+        };
+        let bg = |style: &mut Style, color: &Option<ThemeColor>| {
+            if let Some(ThemeColor(color)) = color {
+                *style = style.bg(*color);
+            }
+        };
+        fg(&mut style.base_style, &self.text);
+        bg(&mut style.base_style, &self.background);
+        fg(&mut style.normal_style, &self.accent);
+        fg(&mut style.selected_style, &self.selected_text);
+        bg(&mut style.selected_style, &self.selected_background);
+        fg(&mut style.error_style, &self.error);
+        fg(&mut style.border_style, &self.border);
+        fg(&mut style.system_style, &self.system);
+        fg(&mut style.mention_style, &self.mention);
+        fg(&mut style.status_style, &self.status_text);
+        bg(&mut style.status_style, &self.status_background);
+        if !self.authors.is_empty() {
+            style.author_colors = self.authors.iter().map(|c| c.0).collect();
+        }
+        Some(style)
+    }
+}
+
+/// The theme `name`, from the config first.
+pub fn theme(name: &str, custom: &HashMap<String, ThemeConfig>) -> Option<AppStyle> {
+    match custom.get(name) {
+        Some(theme) => theme.style(),
+        None => AppStyle::named(name),
+    }
+}
+
+/// A color of the config, a name like `light-blue`,
+/// `#rrggbb`, or the index of a terminal color.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThemeColor(pub Color);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidColor(pub String);
+
+impl fmt::Display for InvalidColor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid color {}, expected a name, #rrggbb or 0 to 255",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for InvalidColor {}
+
+impl FromStr for ThemeColor {
+    type Err = InvalidColor;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_lowercase().replace(['-', '_', ' '], "");
+        let color = match name.as_str() {
+            "reset" | "default" => Color::Reset,
+            "black" => Color::Black,
+            "red" => Color::Red,
+            "green" => Color::Green,
+            "yellow" => Color::Yellow,
+            "blue" => Color::Blue,
+            "magenta" => Color::Magenta,
+            "cyan" => Color::Cyan,
+            "gray" | "grey" => Color::Gray,
+            "darkgray" | "darkgrey" => Color::DarkGray,
+            "lightred" => Color::LightRed,
+            "lightgreen" => Color::LightGreen,
+            "lightyellow" => Color::LightYellow,
+            "lightblue" => Color::LightBlue,
+            "lightmagenta" => Color::LightMagenta,
+            "lightcyan" => Color::LightCyan,
+            "white" => Color::White,
+            _ => {
+                if let Some(hex) = name.strip_prefix('#') {
+                    let rgb = u32::from_str_radix(hex, 16)
+                        .ok()
+                        .filter(|_| hex.len() == 6)
+                        .ok_or_else(|| InvalidColor(s.to_string()))?;
+                    Color::Rgb((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8)
+                } else {
+                    let index = name.parse().map_err(|_| InvalidColor(s.to_string()))?;
+                    Color::Indexed(index)
+                }
+            }
+        };
+        Ok(ThemeColor(color))
+    }
+}
+
+impl<'de> Deserialize<'de> for ThemeColor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(de::Error::custom)
+    }
+}
@@ -1,26 +1,35 @@
-use crossterm::event::KeyCode;
-use libp2p::gossipsub::IdentTopic;
+use crate::node::ChatNode;
+use crossterm::event::{KeyEvent, MouseButton, MouseEvent, MouseEventKind};
+use std::collections::HashMap;
 use tokio::sync::mpsc::Sender;
 use tui::{
     backend::Backend,
-    layout::Constraint,
-    widgets::{Block, Borders, Cell, Row, Table, TableState},
+    layout::{Constraint, Direction, Layout, Rect},
+    text::{Span, Spans},
+    widgets::{Block, Borders, Cell, List, ListItem, Row, Table, TableState},
     Frame,
 };
 
 use crate::{
     app::{AppMessage, AppStyle, Page},
-    chat_room::ChatRoom,
-    NetworkMessage,
+    keymap::{Action, Keymap},
+    models::presence::Presence,
+    search::Search,
+    ui::{contains, is_compact, presence_indicator, short_name},
 };
 
 #[derive(Clone, Debug)]
-pub struct TopicList<'a> {
+pub struct TopicList {
     pub state: TableState,
-    pub items: Vec<Vec<&'a str>>,
+    pub items: Vec<Vec<String>>,
+    /// the presence of the peers, by peer id
+    pub presence: HashMap<String, Presence>,
+    /// where the rooms were last drawn, and the first one on the screen
+    pub rows: Rect,
+    pub offset: usize,
 }
 
-impl<'a> Default for TopicList<'a> {
+impl Default for TopicList {
     fn default() -> Self {
         let mut state = TableState::default();
         state.select(Some(0));
@@ -31,45 +40,97 @@ impl<'a> Default for TopicList<'a> {
             vec!["8", "Hiking organization"],
             vec!["2", "Secret meeting to rule to world"],
             vec!["1", "Talk about cats"],
-        ];
-        Self { state, items }
+        ]
+        .into_iter()
+        .map(|row| row.into_iter().map(String::from).collect())
+        .collect();
+        Self {
+            state,
+            items,
+            presence: HashMap::new(),
+            rows: Rect::default(),
+            offset: 0,
+        }
     }
 }
 
-impl<'a> TopicList<'a> {
+impl TopicList {
     pub fn new() -> Self {
         Self::default()
     }
 
     pub async fn keybindings(
         &mut self,
-        k: KeyCode,
-        tx: &Sender<AppMessage<'a>>,
-        tx_global: &Sender<NetworkMessage>,
+        k: KeyEvent,
+        keymap: &Keymap,
+        tx: &Sender<AppMessage>,
+        node: &ChatNode,
     ) {
-        match k {
-            KeyCode::Char('q') | KeyCode::Esc => tx.send(AppMessage::Quit).await.unwrap(),
-            KeyCode::Down | KeyCode::Char('j') => self.next(),
-            KeyCode::Up | KeyCode::Char('k') => self.previous(),
-            KeyCode::Enter => {
-                let topic_index = self.state.selected().unwrap().to_string();
-                let topic = IdentTopic::new(topic_index.clone());
-
-                let chat_room = Page::ChatRoom(ChatRoom::new(topic_index));
-
-                tx_global
-                    .send(NetworkMessage::Subscribed(topic))
-                    .await
-                    .unwrap();
-                tx.send(AppMessage::ChangePage { page: chat_room })
-                    .await
-                    .unwrap();
+        match keymap.action(k) {
+            Some(Action::Back) => {
+                let _ = tx.send(AppMessage::Quit).await;
+            }
+            Some(Action::Down) => self.next(),
+            Some(Action::Up) => self.previous(),
+            Some(Action::Search) => {
+                let search = Search::new(node.peer_id().to_string(), String::new());
+                let page = Page::Search(Box::new(search));
+                let _ = tx.send(AppMessage::ChangePage { page }).await;
+            }
+            Some(Action::Open) => self.open(tx).await,
+            _ => {}
+        }
+    }
+
+    /// A click selects a room, and opens it if it was selected.
+    pub async fn mouse(&mut self, m: MouseEvent, tx: &Sender<AppMessage>, _node: &ChatNode) {
+        match m.kind {
+            MouseEventKind::ScrollDown => self.next(),
+            MouseEventKind::ScrollUp => self.previous(),
+            MouseEventKind::Down(MouseButton::Left) if contains(self.rows, m.column, m.row) => {
+                let i = self.offset + (m.row - self.rows.y) as usize;
+                if i >= self.items.len() {
+                    return;
+                }
+                if self.state.selected() == Some(i) {
+                    self.open(tx).await;
+                } else {
+                    self.state.select(Some(i));
+                }
             }
             _ => {}
         }
     }
 
-    pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, style: &AppStyle) {
+    /// Join the selected room and show it, the app loads its history.
+    async fn open(&mut self, tx: &Sender<AppMessage>) {
+        let room = self.state.selected().unwrap().to_string();
+        let _ = tx.send(AppMessage::OpenRoom { room }).await;
+    }
+
+    /// Show the presence of a peer.
+    pub fn push_presence(&mut self, presence: Presence) {
+        self.presence.insert(presence.author.clone(), presence);
+    }
+
+    /// Forget the presence of a peer that went quiet.
+    pub fn remove_presence(&mut self, peer_id: &str) {
+        self.presence.remove(peer_id);
+    }
+
+    pub fn draw<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, style: &AppStyle) {
+        // the peers we know of, next to the rooms if there is room
+        let area = if self.presence.is_empty() || is_compact(area) {
+            area
+        } else {
+            let chunks = Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Percentage(65), Constraint::Percentage(35)].as_ref())
+                .split(area);
+            self.draw_peers(f, chunks[1], style);
+            chunks[0]
+        };
+
         let header_cells = ["Online", "Name"]
             .into_iter()
             .map(|h| Cell::from(h).style(style.normal_style));
@@ -86,18 +147,66 @@ impl<'a> TopicList<'a> {
                 .max()
                 .unwrap_or(0)
                 + 1;
-            let cells = item.iter().map(|c| Cell::from(*c));
+            let cells = item.iter().map(|c| Cell::from(c.as_str()));
             Row::new(cells).height(height as u16)
         });
 
         let t = Table::new(rows)
             .header(header)
-            .block(Block::default().borders(Borders::ALL).title("Chat Rooms"))
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(style.border_style)
+                    .title("Chat Rooms"),
+            )
             .highlight_style(style.selected_style)
             .style(style.base_style)
             .widths(&[Constraint::Percentage(10), Constraint::Length(90)]);
 
-        f.render_stateful_widget(t, f.size(), &mut self.state);
+        f.render_stateful_widget(t, area, &mut self.state);
+
+        // inside the borders, under the header and its margin,
+        // scrolled like the table does it, each room is a line
+        self.rows = Rect {
+            x: area.x + 1,
+            y: area.y + 3,
+            width: area.width.saturating_sub(2),
+            height: area.height.saturating_sub(4),
+        };
+        let selected = self.state.selected().unwrap_or(0);
+        let height = (self.rows.height as usize).max(1);
+        if selected >= self.offset + height {
+            self.offset = selected + 1 - height;
+        } else if selected < self.offset {
+            self.offset = selected;
+        }
+    }
+
+    fn draw_peers<B: Backend>(&self, f: &mut Frame<B>, area: Rect, style: &AppStyle) {
+        let mut peers: Vec<&Presence> = self.presence.values().collect();
+        peers.sort_by_key(|p| short_name(&p.author));
+        let peers: Vec<ListItem> = peers
+            .into_iter()
+            .map(|p| {
+                let mut line = vec![
+                    Span::raw(format!("{} ", presence_indicator(p.state))),
+                    Span::styled(short_name(&p.author), style.author_style(&p.author)),
+                ];
+                if !p.status.is_empty() {
+                    line.push(Span::styled(format!(" {}", p.status), style.system_style));
+                }
+                ListItem::new(Spans::from(line))
+            })
+            .collect();
+        let peers = List::new(peers)
+            .block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .border_style(style.border_style)
+                    .title("Peers"),
+            )
+            .style(style.base_style);
+        f.render_widget(peers, area);
     }
 
     pub fn next(&mut self) {
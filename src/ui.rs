@@ -1,51 +1,340 @@
-use std::{io, time::Duration};
-
-use crate::{
-    app::{AppMessage, AppStyle},
-    chat_room::ChatRoom,
-    models::network::NetworkMessage,
-    topic_list::TopicList,
-};
-use crossterm::event::{self, Event};
+use crate::app::{AppMessage, AppStyle, Page};
+use crate::chat_room::InputMode;
+use crate::console::Console;
+use crate::keymap::Keymap;
+use crate::models::presence::PresenceState;
+use crate::node::ChatNode;
+use crate::status_bar::StatusBar;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseEvent};
 use tokio::sync::mpsc::Sender;
-use tui::{backend::CrosstermBackend, Terminal};
+use tui::{
+    backend::Backend,
+    layout::{Alignment, Constraint, Direction, Layout, Rect},
+    style::Style,
+    text::{Span, Spans},
+    widgets::{Block, Borders, Clear, List, ListItem, Paragraph},
+    Frame,
+};
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
-pub async fn draw_topic_list<'a>(
-    page: &mut TopicList<'a>,
-    tx: &Sender<AppMessage<'a>>,
-    tx_network: &Sender<NetworkMessage>,
-    timeout: Duration,
-) {
-    let stdout = io::stdout();
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).unwrap();
-    let style = AppStyle::new();
+/// Below this, the screen only says that it is too small.
+pub const MIN_WIDTH: u16 = 30;
+pub const MIN_HEIGHT: u16 = 10;
 
-    terminal.draw(|f| page.draw(f, &style)).unwrap();
+/// Narrower than this, the side panes are hidden.
+pub const COMPACT_WIDTH: u16 = 60;
 
-    if event::poll(timeout).unwrap() {
-        if let Event::Key(k) = event::read().unwrap() {
-            page.keybindings(k.code, tx, tx_network).await;
-        }
+/// How many lines an input grows to, before it scrolls.
+const INPUT_LINES: usize = 5;
+
+/// What is drawn around the page, and over it.
+pub struct Panes<'a> {
+    pub status_bar: &'a StatusBar,
+    pub console: &'a mut Console,
+    pub notification: Option<&'a str>,
+    /// the keys of the keymap are shown over everything
+    pub help: bool,
+}
+
+/// Draw the current page, with the panes around it.
+pub fn draw<B: Backend>(
+    f: &mut Frame<B>,
+    page: &mut Page,
+    style: &AppStyle,
+    keymap: &Keymap,
+    panes: Panes,
+) {
+    let Panes {
+        status_bar,
+        console,
+        notification,
+        help,
+    } = panes;
+    let size = f.size();
+    if size.width < MIN_WIDTH || size.height < MIN_HEIGHT {
+        draw_too_small(f, style);
+        return;
+    }
+    let area = draw_notification(f, notification, style);
+    let room = match page {
+        Page::ChatRoom(page) => Some(page.name.as_str()),
+        _ => None,
+    };
+    let area = status_bar.draw(f, area, room, style);
+    let area = console.draw(f, area, style, keymap);
+    match page {
+        Page::TopicList(page) => page.draw(f, area, style),
+        Page::ChatRoom(page) => page.draw(f, area, style, keymap),
+        Page::Search(page) => page.draw(f, area, style, keymap),
+    }
+    if help {
+        draw_help(f, area, keymap, style);
+    }
+}
+
+/// Pass a key press to the console if it is open, or to the current page.
+pub async fn handle_key(
+    k: KeyEvent,
+    page: &mut Page,
+    console: &mut Console,
+    keymap: &Keymap,
+    tx: &Sender<AppMessage>,
+    node: &ChatNode,
+) {
+    if is_interrupt(&k) {
+        let _ = tx.send(AppMessage::Quit).await;
+        return;
+    }
+    if console.open {
+        console.keybindings(k, keymap);
+        return;
+    }
+    match page {
+        Page::TopicList(page) => page.keybindings(k, keymap, tx, node).await,
+        Page::ChatRoom(page) => page.keybindings(k, keymap, tx, node).await,
+        Page::Search(page) => page.keybindings(k, keymap, tx, node).await,
     }
 }
 
-pub async fn draw_chat_room<'a>(
-    page: &mut ChatRoom,
-    tx: &Sender<AppMessage<'a>>,
-    tx_network: &Sender<NetworkMessage>,
-    timeout: Duration,
+/// Pass a mouse event to the console if it is over
+/// it, or to the current page.
+pub async fn handle_mouse(
+    m: MouseEvent,
+    page: &mut Page,
+    console: &mut Console,
+    tx: &Sender<AppMessage>,
+    node: &ChatNode,
 ) {
-    let stdout = io::stdout();
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend).unwrap();
-    let style = AppStyle::new();
+    if contains(console.area, m.column, m.row) {
+        console.mouse(m);
+        return;
+    }
+    match page {
+        Page::TopicList(page) => page.mouse(m, tx, node).await,
+        Page::ChatRoom(page) => page.mouse(m, tx, node).await,
+        Page::Search(page) => page.mouse(m, tx, node).await,
+    }
+}
+
+/// Something is being typed in the page, so
+/// the keys are text and not actions.
+pub fn is_typing(page: &Page) -> bool {
+    match page {
+        Page::TopicList(_) => false,
+        Page::ChatRoom(page) => matches!(page.input_mode, InputMode::Insert),
+        Page::Search(page) => page.editing,
+    }
+}
+
+/// The keys of every action, in a box over `area`.
+pub fn draw_help<B: Backend>(f: &mut Frame<B>, area: Rect, keymap: &Keymap, style: &AppStyle) {
+    let help = keymap.help();
+    let keys_width = help.iter().map(|(keys, _)| keys.len()).max().unwrap_or(0);
+    let description_width = help.iter().map(|(_, d)| d.len()).max().unwrap_or(0);
+    // the borders and the space between the columns
+    let width = (keys_width + description_width + 4).min(area.width as usize) as u16;
+    let height = (help.len() + 2).min(area.height as usize) as u16;
+    let help_area = Rect {
+        x: area.x + (area.width - width) / 2,
+        y: area.y + (area.height - height) / 2,
+        width,
+        height,
+    };
+
+    let lines: Vec<ListItem> = help
+        .into_iter()
+        .map(|(keys, description)| ListItem::new(format!("{keys:keys_width$}  {description}")))
+        .collect();
+    let list = List::new(lines)
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .border_style(style.border_style)
+                .title("Keys, press any key to close"),
+        )
+        .style(style.base_style);
+    f.render_widget(Clear, help_area);
+    f.render_widget(list, help_area);
+}
+
+/// Ask for a bigger terminal, instead of a garbled page.
+fn draw_too_small<B: Backend>(f: &mut Frame<B>, style: &AppStyle) {
+    let size = f.size();
+    let text = vec![
+        Spans::from("The terminal is too small"),
+        Spans::from(format!(
+            "{}x{}, it needs {MIN_WIDTH}x{MIN_HEIGHT}",
+            size.width, size.height
+        )),
+    ];
+    let area = Rect {
+        y: size.y + size.height.saturating_sub(2) / 2,
+        height: size.height.min(2),
+        ..size
+    };
+    f.render_widget(Paragraph::new("").style(style.base_style), size);
+    f.render_widget(
+        Paragraph::new(text)
+            .style(style.base_style)
+            .alignment(Alignment::Center),
+        area,
+    );
+}
+
+/// Too narrow for the side panes.
+pub fn is_compact(area: Rect) -> bool {
+    area.width < COMPACT_WIDTH
+}
 
-    terminal.draw(|f| page.draw(f, &style)).unwrap();
+/// `line` cut into lines of at most `width` columns, between
+/// words when it can. The spaces at the cuts are dropped.
+pub fn wrap(line: Spans, width: usize) -> Vec<Spans<'static>> {
+    let chars: Vec<(char, Style)> = line
+        .0
+        .iter()
+        .flat_map(|span| span.content.chars().map(move |c| (c, span.style)))
+        .collect();
+    if width == 0 {
+        return vec![spans(&chars)];
+    }
+
+    let mut lines = vec![];
+    let mut current: Vec<(char, Style)> = vec![];
+    let mut current_width = 0;
+    for (c, style) in chars {
+        let w = c.width().unwrap_or(0);
+        let cut = current_width + w > width && !current.is_empty();
+        while current_width + w > width && !current.is_empty() {
+            // cut at the space, or after the last one that follows a word
+            let rest = match current.iter().rposition(|(c, _)| *c == ' ') {
+                _ if c == ' ' => vec![],
+                Some(i) if current[..i].iter().any(|(c, _)| *c != ' ') => current.split_off(i + 1),
+                _ => vec![],
+            };
+            while current.last().is_some_and(|(c, _)| *c == ' ') {
+                current.pop();
+            }
+            lines.push(spans(&current));
+            current = rest;
+            current_width = current.iter().map(|(c, _)| c.width().unwrap_or(0)).sum();
+        }
+        if cut && c == ' ' && current.is_empty() {
+            continue;
+        }
+        current.push((c, style));
+        current_width += w;
+    }
+    if !current.is_empty() || lines.is_empty() {
+        lines.push(spans(&current));
+    }
+    lines
+}
 
-    if event::poll(timeout).unwrap() {
-        if let Event::Key(k) = event::read().unwrap() {
-            page.keybindings(k.code, tx, tx_network).await;
+/// The characters back in spans, one per run of a style.
+fn spans(chars: &[(char, Style)]) -> Spans<'static> {
+    let mut spans: Vec<Span> = vec![];
+    for (c, style) in chars {
+        match spans.last_mut() {
+            Some(span) if span.style == *style => span.content.to_mut().push(*c),
+            _ => spans.push(Span::styled(c.to_string(), *style)),
         }
     }
+    Spans::from(spans)
+}
+
+/// The text of an input, cut into lines of `width` columns. There
+/// is always a line with room for the cursor after the text.
+pub fn input_lines(text: &str, width: u16) -> Vec<String> {
+    let width = width.max(1) as usize;
+    let mut lines = vec![String::new()];
+    let mut current_width = 0;
+    for c in text.chars() {
+        let w = c.width().unwrap_or(0);
+        if current_width + w > width {
+            lines.push(String::new());
+            current_width = 0;
+        }
+        lines.last_mut().unwrap().push(c);
+        current_width += w;
+    }
+    if current_width >= width {
+        lines.push(String::new());
+    }
+    lines
+}
+
+/// The height of an input box with `text`, in `width` columns with the borders.
+pub fn input_height(text: &str, width: u16) -> u16 {
+    let lines = input_lines(text, width.saturating_sub(2)).len();
+    lines.min(INPUT_LINES) as u16 + 2
+}
+
+/// Draw `text` in an input box on `area`, scrolled to its end,
+/// and return where the cursor goes after it.
+pub fn draw_input<B: Backend>(
+    f: &mut Frame<B>,
+    area: Rect,
+    text: &str,
+    title: String,
+    style: &AppStyle,
+) -> (u16, u16) {
+    let lines = input_lines(text, area.width.saturating_sub(2));
+    let shown = (area.height.saturating_sub(2) as usize).max(1);
+    let lines = &lines[lines.len().saturating_sub(shown)..];
+    let last = lines.last().map_or(0, |line| line.width()) as u16;
+    let text: Vec<Spans> = lines.iter().map(|l| Spans::from(l.as_str())).collect();
+    let input = Paragraph::new(text).style(style.base_style).block(
+        Block::default()
+            .borders(Borders::ALL)
+            .border_style(style.border_style)
+            .title(title),
+    );
+    f.render_widget(input, area);
+    (area.x + 1 + last, area.y + lines.len() as u16)
+}
+
+/// The cell at `column` and `row` is in `area`.
+pub fn contains(area: Rect, column: u16, row: u16) -> bool {
+    (area.x..area.x + area.width).contains(&column) && (area.y..area.y + area.height).contains(&row)
+}
+
+/// The end of a `PeerId`, it is enough to tell peers apart.
+pub fn short_name(peer_id: &str) -> &str {
+    &peer_id[peer_id.len().saturating_sub(7)..]
+}
+
+/// The symbol shown next to the name of a peer.
+pub fn presence_indicator(state: PresenceState) -> &'static str {
+    match state {
+        PresenceState::Online => "●",
+        PresenceState::Away => "◐",
+        PresenceState::DoNotDisturb => "⊘",
+    }
+}
+
+/// Ctrl-C, raw mode doesn't turn it into a SIGINT.
+fn is_interrupt(k: &KeyEvent) -> bool {
+    k.code == KeyCode::Char('c') && k.modifiers.contains(KeyModifiers::CONTROL)
+}
+
+/// Draw the notification on the last line, if there is one,
+/// and return the area that is left for the page.
+pub fn draw_notification<B: Backend>(
+    f: &mut Frame<B>,
+    notification: Option<&str>,
+    style: &AppStyle,
+) -> Rect {
+    let Some(notification) = notification else {
+        return f.size();
+    };
+
+    let chunks = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(1), Constraint::Length(1)].as_ref())
+        .split(f.size());
+
+    let notification = Paragraph::new(notification).style(style.error_style);
+    f.render_widget(notification, chunks[1]);
+
+    chunks[0]
 }
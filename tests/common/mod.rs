@@ -0,0 +1,244 @@
+//! A harness that runs many `ChatNode`s in one process, on
+//! loopback TCP and without mDNS, so tests don't need a real network.
+#![allow(dead_code)]
+
+use std::{path::PathBuf, time::Duration};
+
+use libp2p::Multiaddr;
+use p2p_chat::{
+    models::message::{Edit, Reaction},
+    ChatMessage, ChatNode, ChatNodeBuilder, EventStream, NetworkError, NetworkEvent,
+};
+use tokio::{task::JoinHandle, time::timeout};
+
+/// How long to wait for something that should happen.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait before deciding that something did not happen.
+pub const QUIET: Duration = Duration::from_secs(2);
+
+pub struct TestNode {
+    pub node: ChatNode,
+    pub events: EventStream,
+    pub address: Multiaddr,
+    pub peer_id: String,
+    /// finishes once the node has shut down
+    pub driver: JoinHandle<Result<(), NetworkError>>,
+}
+
+impl TestNode {
+    pub async fn spawn() -> Self {
+        Self::spawn_with(ChatNode::builder()).await
+    }
+
+    /// Spawn a node from `builder`, on loopback and without mDNS.
+    pub async fn spawn_with(builder: ChatNodeBuilder) -> Self {
+        let (node, driver) = builder
+            .listen_address("/ip4/127.0.0.1/tcp/0".parse().unwrap())
+            .mdns(false)
+            .build();
+        // subscribe before the driver runs, to not miss `Listening`
+        let mut events = node.events();
+        let driver = tokio::spawn(driver.run());
+
+        let address = wait_for(&mut events, |e| match e {
+            NetworkEvent::Listening { address } => Some(address.parse().unwrap()),
+            _ => None,
+        })
+        .await;
+        let peer_id = node.peer_id().to_string();
+
+        Self {
+            node,
+            events,
+            address,
+            peer_id,
+            driver,
+        }
+    }
+
+    /// Quit and wait until the node has shut down.
+    pub async fn quit(self) {
+        self.node.quit().await.unwrap();
+        timeout(TIMEOUT, self.driver)
+            .await
+            .expect("the node to shut down in time")
+            .unwrap()
+            .unwrap();
+    }
+
+    /// Dial `other` and wait for the connection.
+    pub async fn connect(&mut self, other: &TestNode) {
+        self.node.dial(other.address.clone()).await.unwrap();
+        let peer_id = other.peer_id.clone();
+        wait_for(&mut self.events, |e| match e {
+            NetworkEvent::PeerConnected { peer_id: p } if *p == peer_id => Some(()),
+            _ => None,
+        })
+        .await;
+    }
+
+    /// Wait until `n` peers we are connected to are in `room`.
+    pub async fn wait_room_peers(&mut self, room: &str, n: usize) {
+        let mut peers = 0;
+        while peers < n {
+            wait_for(&mut self.events, |e| match e {
+                NetworkEvent::PeerSubscribed { topic, .. } if topic == room => Some(()),
+                _ => None,
+            })
+            .await;
+            peers += 1;
+        }
+    }
+
+    /// Wait for an edit in `room`, ignoring every other event.
+    pub async fn expect_edit(&mut self, room: &str) -> Edit {
+        wait_for(&mut self.events, |e| match e {
+            NetworkEvent::EditReceived { topic, edit } if topic == room => Some(edit.clone()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait for a reaction in `room`, ignoring every other event.
+    pub async fn expect_reaction(&mut self, room: &str) -> Reaction {
+        wait_for(&mut self.events, |e| match e {
+            NetworkEvent::ReactionReceived { topic, reaction } if topic == room => {
+                Some(reaction.clone())
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait for a message in `room`, ignoring every other event.
+    pub async fn expect_message(&mut self, room: &str) -> ChatMessage {
+        wait_for(&mut self.events, |e| match e {
+            NetworkEvent::MessageReceived { topic, message, .. } if topic == room => {
+                Some(message.clone())
+            }
+            _ => None,
+        })
+        .await
+    }
+
+    /// Assert that no message arrives in `room` for a while.
+    pub async fn expect_no_message(&mut self, room: &str) {
+        let result = timeout(QUIET, async {
+            loop {
+                if let Some(NetworkEvent::MessageReceived { topic, message, .. }) =
+                    self.events.recv().await
+                {
+                    if topic == room {
+                        return message;
+                    }
+                }
+            }
+        })
+        .await;
+
+        if let Ok(message) = result {
+            panic!("unexpected message {message:?}");
+        }
+    }
+}
+
+/// How the nodes of a `Harness` are connected.
+pub enum Topology {
+    /// every node is connected to every other node
+    FullMesh,
+    /// node `i` is connected to node `i + 1`
+    Line,
+    /// every node is connected to node 0
+    Star,
+}
+
+pub struct Harness {
+    pub nodes: Vec<TestNode>,
+    pub topology: Topology,
+}
+
+impl Harness {
+    pub async fn new(n: usize, topology: Topology) -> Self {
+        let mut nodes = Vec::with_capacity(n);
+        for _ in 0..n {
+            nodes.push(TestNode::spawn().await);
+        }
+
+        let edges = topology.edges(n);
+        let mut harness = Self { nodes, topology };
+        for (a, b) in edges {
+            harness.connect(a, b).await;
+        }
+        harness
+    }
+
+    /// Make node `a` dial node `b`.
+    pub async fn connect(&mut self, a: usize, b: usize) {
+        assert_ne!(a, b);
+        let (a, b) = if a < b {
+            let (left, right) = self.nodes.split_at_mut(b);
+            (&mut left[a], &right[0])
+        } else {
+            let (left, right) = self.nodes.split_at_mut(a);
+            (&mut right[0], &left[b])
+        };
+        a.connect(b).await;
+    }
+
+    /// Make every node join `room`, and wait until each of
+    /// them knows about the neighbours that are in it too.
+    pub async fn join_all(&mut self, room: &str) {
+        for node in &self.nodes {
+            node.node.join(room).await.unwrap();
+        }
+        let n = self.nodes.len();
+        for (i, node) in self.nodes.iter_mut().enumerate() {
+            node.wait_room_peers(room, self.topology.degree(i, n)).await;
+        }
+    }
+}
+
+impl Topology {
+    fn edges(&self, n: usize) -> Vec<(usize, usize)> {
+        match self {
+            Topology::FullMesh => (0..n)
+                .flat_map(|a| (a + 1..n).map(move |b| (a, b)))
+                .collect(),
+            Topology::Line => (1..n).map(|b| (b - 1, b)).collect(),
+            Topology::Star => (1..n).map(|b| (b, 0)).collect(),
+        }
+    }
+
+    /// How many neighbours node `i` has.
+    fn degree(&self, i: usize, n: usize) -> usize {
+        self.edges(n)
+            .iter()
+            .filter(|(a, b)| *a == i || *b == i)
+            .count()
+    }
+}
+
+/// Wait for the first event for which `f` returns `Some`.
+pub async fn wait_for<T>(
+    events: &mut EventStream,
+    mut f: impl FnMut(&NetworkEvent) -> Option<T>,
+) -> T {
+    timeout(TIMEOUT, async {
+        loop {
+            let event = events.recv().await.expect("node to be running");
+            if let Some(t) = f(&event) {
+                return t;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for an event")
+}
+
+/// A new directory under the system temp dir.
+pub fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("p2p-chat-test-{:016x}", rand::random::<u64>()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,401 @@
+//! The config file of the TUI, and the notifications, the log and the times it sets up.
+use std::fs;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use log::{Level, LevelFilter, Log, Record};
+use p2p_chat::{
+    clock::{duration, TimeConfig, TimeFormat, Timezone},
+    config::{Alert, Config, Rule},
+    keymap::{Action, KeyChord, Keymap, Preset},
+    logger::{LogBuffer, LogConfig, LogRecord, Logger, RotatingFile, LOG_CAPACITY},
+    notify::{escape, is_mention},
+    theme::{AppStyle, THEMES},
+};
+use tui::style::Color;
+
+#[test]
+fn empty_config_has_defaults() {
+    let config: Config = "".parse().unwrap();
+    assert_eq!(config.nickname, None);
+    assert!(config.keywords.is_empty());
+    assert_eq!(config.notifications.alert, Alert::Bell);
+    assert_eq!(config.notifications.command, None);
+    assert_eq!(config.rule("anything"), Rule::Mentions);
+    assert!(config.mouse);
+    assert!(!"mouse = false".parse::<Config>().unwrap().mouse);
+}
+
+#[test]
+fn parses_notifications() {
+    let config: Config = r#"
+        nickname = "gabriel"
+        keywords = ["deploy", "release"]
+
+        [notifications]
+        alert = "osc777"
+        command = "notify-send \"$P2P_CHAT_ROOM\" \"$P2P_CHAT_MESSAGE\""
+        default = "none"
+
+        [notifications.rooms]
+        ops = "all"
+        random = "mentions"
+    "#
+    .parse()
+    .unwrap();
+
+    assert_eq!(config.nickname.as_deref(), Some("gabriel"));
+    assert_eq!(config.keywords, ["deploy", "release"]);
+    assert_eq!(config.notifications.alert, Alert::Osc777);
+    assert!(config.notifications.command.is_some());
+    assert_eq!(config.rule("ops"), Rule::All);
+    assert_eq!(config.rule("random"), Rule::Mentions);
+    assert_eq!(config.rule("general"), Rule::None);
+}
+
+#[test]
+fn rejects_unknown_settings() {
+    assert!("nick = \"gabriel\"".parse::<Config>().is_err());
+    assert!("[notifications]\nalert = \"loud\""
+        .parse::<Config>()
+        .is_err());
+}
+
+#[test]
+fn missing_file_is_default() {
+    let dir = std::env::temp_dir().join(format!("p2p-chat-config-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("config.toml");
+    let _ = fs::remove_file(&path);
+
+    let config = Config::load(&path).unwrap();
+    assert_eq!(config.rule("general"), Rule::Mentions);
+
+    fs::write(&path, "keywords = [").unwrap();
+    let err = Config::load(&path).unwrap_err().to_string();
+    assert!(err.contains("config.toml"), "{err}");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn mentions_are_whole_words() {
+    let names = ["gabriel", "@abc1234"];
+    assert!(is_mention("hey gabriel, look", &names));
+    assert!(is_mention("@Gabriel look", &names));
+    assert!(is_mention("ping abc1234", &names));
+    assert!(is_mention("gabriel", &names));
+    assert!(!is_mention("gabrielle is here", &names));
+    assert!(!is_mention("xabc1234", &names));
+    assert!(!is_mention("nothing to see", &names));
+    assert!(!is_mention("anything", &[""]));
+}
+
+#[test]
+fn escapes_leave_out_control_characters() {
+    assert_eq!(escape(Alert::Bell, "abc in general", "hi"), "\x07");
+    assert_eq!(escape(Alert::None, "abc in general", "hi"), "");
+    assert_eq!(
+        escape(Alert::Osc9, "abc in general", "hi\x07\x1b]9;pwned"),
+        "\x1b]9;abc in general: hi]9;pwned\x07"
+    );
+    assert_eq!(
+        escape(Alert::Osc777, "abc in a;b", "hi\nthere"),
+        "\x1b]777;notify;abc in a,b;hithere\x07"
+    );
+}
+
+#[test]
+fn parses_custom_themes() {
+    let config: Config = r##"
+        theme = "mine"
+
+        [themes.mine]
+        inherits = "solarized"
+        text = "light-blue"
+        background = "#101010"
+        mention = "208"
+        authors = ["red", "Dark Gray"]
+    "##
+    .parse()
+    .unwrap();
+    assert!(config.check_themes().is_ok());
+
+    let style = config.style("mine").unwrap();
+    let solarized = AppStyle::named("solarized").unwrap();
+    assert_eq!(style.base_style.fg, Some(Color::LightBlue));
+    assert_eq!(style.base_style.bg, Some(Color::Rgb(0x10, 0x10, 0x10)));
+    assert_eq!(style.mention_style.fg, Some(Color::Indexed(208)));
+    assert_eq!(style.author_colors, [Color::Red, Color::DarkGray]);
+    assert_eq!(style.border_style, solarized.border_style);
+
+    // the built-in themes are still there
+    for name in THEMES {
+        assert!(config.style(name).is_some(), "{name}");
+    }
+    assert!(config.style("neon").is_none());
+}
+
+#[test]
+fn rejects_unknown_themes_and_colors() {
+    let config: Config = "theme = \"neon\"".parse().unwrap();
+    assert_eq!(config.check_themes(), Err("neon".to_string()));
+
+    let config: Config = "[themes.mine]\ninherits = \"neon\"".parse().unwrap();
+    assert_eq!(config.check_themes(), Err("neon".to_string()));
+
+    for color in ["\"#12345\"", "\"#gggggg\"", "\"256\"", "\"purple\""] {
+        let toml = format!("[themes.mine]\ntext = {color}");
+        assert!(toml.parse::<Config>().is_err(), "{color}");
+    }
+}
+
+#[test]
+fn author_colors_are_stable() {
+    let peer = "12D3KooWRBy97UB99e3J6hiPesre1MZeuNQvfan4gBziswrralice01";
+    let style = AppStyle::new();
+    assert_eq!(style.author_style(peer), AppStyle::new().author_style(peer));
+    // the peers are spread over the palette
+    let mut colors: Vec<_> = (0..50)
+        .map(|i| style.author_style(&format!("{peer}{i}")).fg)
+        .collect();
+    colors.dedup();
+    assert!(colors.len() > 1);
+    assert!(colors
+        .iter()
+        .all(|c| c.is_some_and(|c| style.author_colors.contains(&c))));
+}
+
+#[test]
+fn parses_key_chords() {
+    let chord = |s: &str| s.parse::<KeyChord>().unwrap();
+    let ctrl_n = KeyChord::new(KeyCode::Char('n'), KeyModifiers::CONTROL);
+    assert_eq!(chord("ctrl-n"), ctrl_n);
+    assert_eq!(chord("C-n"), ctrl_n);
+    assert_eq!(chord("shift-r"), chord("R"));
+    assert_eq!(chord("alt-shift-r").to_string(), "alt-R");
+    assert_eq!(
+        chord("-"),
+        KeyChord::new(KeyCode::Char('-'), KeyModifiers::NONE)
+    );
+    assert_eq!(chord("ctrl--").to_string(), "ctrl--");
+    assert_eq!(chord("enter").to_string(), "Enter");
+    assert_eq!(chord("F5").to_string(), "F5");
+    assert_eq!(chord("space").to_string(), "Space");
+    for key in ["hyper-x", "f13", "enterr", "ctrl-"] {
+        assert!(key.parse::<KeyChord>().is_err(), "{key}");
+    }
+}
+
+#[test]
+fn parses_keys() {
+    let config: Config = r#"
+        [keys]
+        preset = "emacs"
+
+        [keys.bindings]
+        "ctrl-j" = "down"
+        "ctrl-n" = "none"
+        "alt-o" = "open"
+    "#
+    .parse()
+    .unwrap();
+    assert_eq!(config.keys.preset, Preset::Emacs);
+    let keymap = Keymap::new(&config.keys);
+    let key = |code, modifiers| keymap.action(KeyEvent::new(code, modifiers));
+    assert_eq!(
+        key(KeyCode::Char('j'), KeyModifiers::CONTROL),
+        Some(Action::Down)
+    );
+    assert_eq!(key(KeyCode::Char('n'), KeyModifiers::CONTROL), None);
+    assert_eq!(key(KeyCode::Down, KeyModifiers::NONE), Some(Action::Down));
+    assert_eq!(keymap.label(Action::Down), "Down");
+
+    // while typing, only the chords with ctrl or alt are not typed
+    assert_eq!(keymap.typing_label(Action::Back, KeyCode::Esc), "ctrl-g");
+    assert_eq!(keymap.typing_label(Action::Open, KeyCode::Enter), "Enter");
+    let vim = Keymap::vim();
+    assert_eq!(vim.typing_label(Action::Back, KeyCode::Esc), "Esc");
+    let typed = |code, modifiers| {
+        keymap.while_typing(KeyEvent::new(code, modifiers), Action::Open, KeyCode::Enter)
+    };
+    assert!(typed(KeyCode::Enter, KeyModifiers::NONE));
+    assert!(typed(KeyCode::Char('o'), KeyModifiers::ALT));
+    assert!(!typed(KeyCode::Char('o'), KeyModifiers::NONE));
+
+    // the keys of an action are in the order of the config
+    let config: Config = r#"
+        [keys.bindings]
+        "?" = "none"
+        "F1" = "none"
+        "alt-z" = "help"
+        "alt-a" = "help"
+        "alt-m" = "help"
+    "#
+    .parse()
+    .unwrap();
+    let keymap = Keymap::new(&config.keys);
+    let keys: Vec<String> = keymap.keys(Action::Help).map(|k| k.to_string()).collect();
+    assert_eq!(keys, ["alt-z", "alt-a", "alt-m"]);
+
+    assert!("[keys.bindings]\n\"ctrl-j\" = \"jump\""
+        .parse::<Config>()
+        .is_err());
+    assert!("[keys.bindings]\n\"hyper-j\" = \"down\""
+        .parse::<Config>()
+        .is_err());
+}
+
+#[test]
+fn parses_log() {
+    let config: Config = r#"
+        [log]
+        level = "debug"
+        file = "/tmp/p2p-chat.log"
+        max_size = 4096
+        files = 5
+    "#
+    .parse()
+    .unwrap();
+    assert_eq!(config.log.level, LevelFilter::Debug);
+    assert_eq!(
+        config.log.file.as_deref(),
+        Some("/tmp/p2p-chat.log".as_ref())
+    );
+    assert_eq!((config.log.max_size, config.log.files), (4096, 5));
+
+    let config: Config = "".parse().unwrap();
+    assert_eq!(config.log.level, LevelFilter::Info);
+    assert_eq!(config.log.file, None);
+    assert!("[log]\nlevel = \"loud\"".parse::<Config>().is_err());
+}
+
+#[test]
+fn log_keeps_the_last_records() {
+    let log = |logger: &Logger, level, message: &str| {
+        logger.log(
+            &Record::builder()
+                .level(level)
+                .target("p2p_chat::network")
+                .args(format_args!("{message}"))
+                .build(),
+        )
+    };
+    let logger = Logger::new(&LogConfig::default()).unwrap();
+    log(&logger, Level::Debug, "too verbose");
+    log(&logger, Level::Warn, "no peers");
+    let records = logger.buffer().records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].level, Level::Warn);
+    assert_eq!(records[0].message, "no peers");
+
+    let buffer = LogBuffer::new();
+    for i in 0..LOG_CAPACITY + 5 {
+        buffer.push(LogRecord {
+            timestamp: 0,
+            level: Level::Info,
+            target: "p2p_chat".to_string(),
+            message: i.to_string(),
+        });
+    }
+    let records = buffer.records();
+    assert_eq!(records.len(), LOG_CAPACITY);
+    assert_eq!(records[0].message, "5");
+
+    let record = &records[0];
+    assert!(record.matches(LevelFilter::Info, "P2P_CHAT 5"));
+    assert!(!record.matches(LevelFilter::Warn, ""));
+    assert!(!record.matches(LevelFilter::Info, "network"));
+}
+
+#[test]
+fn log_file_rotates() {
+    let dir = std::env::temp_dir().join(format!("p2p-chat-test-{:016x}", rand::random::<u64>()));
+    let path = dir.join("p2p-chat.log");
+    let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+    for line in ["first", "second", "third", "fourth"] {
+        file.write_line(line).unwrap();
+    }
+    let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+    assert_eq!(read("p2p-chat.log"), "fourth\n");
+    assert_eq!(read("p2p-chat.log.1"), "third\n");
+    assert_eq!(read("p2p-chat.log.2"), "second\n");
+    assert!(!dir.join("p2p-chat.log.3").exists());
+
+    // it appends to what is there
+    drop(file);
+    let mut file = RotatingFile::open(&path, 100, 2).unwrap();
+    file.write_line("fifth").unwrap();
+    assert_eq!(read("p2p-chat.log"), "fourth\nfifth\n");
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[test]
+fn parses_time() {
+    let config: Config = r#"
+        [time]
+        format = "relative"
+        twelve_hour = true
+        timezone = "utc"
+        max_skew = 60
+    "#
+    .parse()
+    .unwrap();
+    assert_eq!(config.time.format, TimeFormat::Relative);
+    assert!(config.time.twelve_hour);
+    assert_eq!(config.time.timezone, Timezone::Utc);
+    assert_eq!(config.time.max_skew, 60);
+
+    let config: Config = "".parse().unwrap();
+    assert_eq!(config.time.format, TimeFormat::Absolute);
+    assert_eq!(config.time.timezone, Timezone::Local);
+    assert!("[time]\ntimezone = \"mars\"".parse::<Config>().is_err());
+}
+
+#[test]
+fn formats_times() {
+    // 2024-05-01 14:05 UTC
+    let sent = 1_714_572_300_000;
+    let mut time = TimeConfig {
+        timezone: Timezone::Utc,
+        ..TimeConfig::default()
+    };
+    assert_eq!(time.time(sent, sent), "14:05");
+    assert_eq!(time.day(sent), "2024-05-01");
+    time.twelve_hour = true;
+    assert_eq!(time.time(sent, sent), "2:05 PM");
+    assert_eq!(time.time(sent - 14 * 60 * 60 * 1000, sent), "12:05 AM");
+
+    time.format = TimeFormat::Relative;
+    assert_eq!(time.time(sent, sent + 30 * 1000), "now");
+    assert_eq!(time.time(sent, sent + 5 * 60 * 1000), "5m ago");
+    assert_eq!(time.time(sent, sent + 3 * 24 * 60 * 60 * 1000), "3d ago");
+    assert_eq!(
+        time.time(sent, sent + 8 * 24 * 60 * 60 * 1000),
+        "2024-05-01"
+    );
+
+    assert_eq!(duration(45 * 1000), "45s");
+    assert_eq!(duration(2 * 60 * 60 * 1000), "2h");
+}
+
+#[test]
+fn flags_skewed_clocks() {
+    let time = TimeConfig::default();
+    let received = 1_714_572_300_000;
+    assert_eq!(time.skew(received - 60 * 1000, received), None);
+    assert_eq!(
+        time.skew(received + 10 * 60 * 1000, received).as_deref(),
+        Some("10m")
+    );
+    assert_eq!(
+        time.skew(received - 2 * 60 * 60 * 1000, received)
+            .as_deref(),
+        Some("2h")
+    );
+
+    let time = TimeConfig {
+        max_skew: 0,
+        ..TimeConfig::default()
+    };
+    assert_eq!(time.skew(0, received), None);
+}
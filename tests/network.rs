@@ -0,0 +1,1000 @@
+mod common;
+
+use common::{temp_dir, wait_for, Harness, TestNode, Topology, QUIET};
+use std::{collections::HashMap, os::unix::fs::PermissionsExt};
+
+use libp2p::{identity::Keypair, PeerId};
+use p2p_chat::{
+    models::{
+        archive::{self, ExportFormat, ImportReport},
+        control::{self, Client},
+        history::RoomHistory,
+        message::{ChatMessage, DeliveryStatus, Edit, Reaction},
+        presence::{Presence, PresenceState, Presences},
+        search::SearchQuery,
+        status::{is_public, NatStatus, NodeStatus},
+        storage::{Entry, Record},
+        time::format_date,
+    },
+    ChatNode, NetworkError, NetworkEvent,
+};
+use tokio::time::{timeout, Duration, Instant};
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_reaches_every_subscriber_in_a_full_mesh() {
+    let mut harness = Harness::new(4, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    let author = harness.nodes[0].peer_id.clone();
+    harness.nodes[0].node.send("rust", "hello").await.unwrap();
+
+    for node in &mut harness.nodes[1..] {
+        let message = node.expect_message("rust").await;
+        assert_eq!(message.body, "hello");
+        assert_eq!(message.author, author);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_reaches_every_subscriber_in_a_star() {
+    let mut harness = Harness::new(4, Topology::Star).await;
+    harness.join_all("rust").await;
+
+    // the leaves are not connected to each other,
+    // the message has to go through the center.
+    harness.nodes[1].node.send("rust", "hello").await.unwrap();
+
+    for i in [0, 2, 3] {
+        let message = harness.nodes[i].expect_message("rust").await;
+        assert_eq!(message.body, "hello");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_reaches_every_subscriber_in_a_line() {
+    let mut harness = Harness::new(3, Topology::Line).await;
+    harness.join_all("rust").await;
+
+    harness.nodes[0].node.send("rust", "hello").await.unwrap();
+
+    for node in &mut harness.nodes[1..] {
+        let message = node.expect_message("rust").await;
+        assert_eq!(message.body, "hello");
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn message_is_only_delivered_to_its_room() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+    harness.join_all("cats").await;
+
+    harness.nodes[0].node.send("cats", "meow").await.unwrap();
+
+    harness.nodes[1].expect_message("cats").await;
+    harness.nodes[1].expect_no_message("rust").await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn unsubscribe_stops_delivery() {
+    let mut harness = Harness::new(3, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    harness.nodes[2].node.leave("rust").await.unwrap();
+    harness.nodes[0].node.send("rust", "hello").await.unwrap();
+
+    let message = harness.nodes[1].expect_message("rust").await;
+    assert_eq!(message.body, "hello");
+    harness.nodes[2].expect_no_message("rust").await;
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn late_joiner_sees_history() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    let received = |e: &NetworkEvent| match e {
+        NetworkEvent::MessageReceived { message, live, .. } => Some((message.body.clone(), *live)),
+        _ => None,
+    };
+    for body in ["first", "second", "third"] {
+        harness.nodes[0].node.send("rust", body).await.unwrap();
+        harness.nodes[1].expect_message("rust").await;
+    }
+
+    // connected to node 1 only, which was not
+    // the author of any of the messages.
+    let mut late = TestNode::spawn().await;
+    late.connect(&harness.nodes[1]).await;
+    late.node.join("rust").await.unwrap();
+
+    let mut bodies = Vec::new();
+    for _ in 0..3 {
+        let (body, live) = wait_for(&mut late.events, received).await;
+        // shared with the history, not sent just now
+        assert!(!live);
+        bodies.push(body);
+    }
+    assert_eq!(bodies, ["first", "second", "third"]);
+
+    harness.nodes[1].node.send("rust", "welcome").await.unwrap();
+    let (body, live) = wait_for(&mut late.events, received).await;
+    assert_eq!(body, "welcome");
+    assert!(live);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn replies_keep_their_parent() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    let parent = harness.nodes[0].node.send("rust", "lunch?").await.unwrap();
+    harness.nodes[1].expect_message("rust").await;
+    harness.nodes[1]
+        .node
+        .reply("rust", &parent.id, "yes")
+        .await
+        .unwrap();
+
+    let reply = harness.nodes[0].expect_message("rust").await;
+    assert_eq!(reply.body, "yes");
+    assert_eq!(reply.reply_to, Some(parent.id));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn typing_is_delivered() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    harness.nodes[0].node.typing("rust").await.unwrap();
+
+    let typing = wait_for(&mut harness.nodes[1].events, |e| match e {
+        NetworkEvent::Typing { topic, peer_id } if topic == "rust" => Some(peer_id.clone()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(typing, harness.nodes[0].peer_id);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reactions_are_delivered_and_synced() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    let message = harness.nodes[0]
+        .node
+        .send("rust", "ship it?")
+        .await
+        .unwrap();
+    harness.nodes[1].expect_message("rust").await;
+
+    // one at a time, gossipsub doesn't keep the order
+    let author = harness.nodes[1].peer_id.clone();
+    for (emoji, added) in [("👍", true), ("🎉", true), ("🎉", false)] {
+        let node = &harness.nodes[1].node;
+        node.react("rust", &message.id, emoji, added).await.unwrap();
+
+        let reaction = harness.nodes[0].expect_reaction("rust").await;
+        assert_eq!(reaction.message_id, message.id);
+        assert_eq!(reaction.author, author);
+        assert_eq!((reaction.emoji.as_str(), reaction.added), (emoji, added));
+    }
+
+    // a late joiner gets the reactions with the history
+    let mut late = TestNode::spawn().await;
+    late.connect(&harness.nodes[0]).await;
+    late.node.join("rust").await.unwrap();
+    late.expect_message("rust").await;
+
+    let mut synced = Vec::new();
+    for _ in 0..2 {
+        let reaction = late.expect_reaction("rust").await;
+        synced.push((reaction.emoji, reaction.added));
+    }
+    synced.sort();
+    assert_eq!(
+        synced,
+        [("🎉".to_string(), false), ("👍".to_string(), true)]
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn only_the_author_can_edit_a_message() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    let message = harness.nodes[0].node.send("rust", "helo").await.unwrap();
+    harness.nodes[1].expect_message("rust").await;
+
+    let node = harness.nodes[0].node.clone();
+    node.edit("rust", &message.id, "hello").await.unwrap();
+    let edit = harness.nodes[1].expect_edit("rust").await;
+    assert!(edit.verify());
+    assert_eq!(edit.body.as_deref(), Some("hello"));
+
+    node.delete("rust", &message.id).await.unwrap();
+    let edit = harness.nodes[1].expect_edit("rust").await;
+    assert_eq!(edit.body, None);
+
+    let history = harness.nodes[1]
+        .node
+        .edit_history("rust", &message.id)
+        .await
+        .unwrap();
+    let bodies: Vec<_> = history.iter().map(|e| e.body.as_deref()).collect();
+    assert_eq!(bodies, [Some("hello"), None]);
+
+    // someone else's message is refused before it is published
+    let other = &mut harness.nodes[1];
+    other.node.edit("rust", &message.id, "pwned").await.unwrap();
+    let error = wait_for(&mut other.events, |e| match e {
+        NetworkEvent::Error { error } => Some(error.clone()),
+        _ => None,
+    })
+    .await;
+    assert!(matches!(error, NetworkError::Edit { .. }), "{error:?}");
+
+    tokio::time::sleep(common::QUIET).await;
+    assert_eq!(
+        node.edit_history("rust", &message.id).await.unwrap().len(),
+        2
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn reactions_and_edits_of_unknown_messages_are_dropped() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    // the reaction gets there before the message, as they are sent in order
+    let node = harness.nodes[0].node.clone();
+    node.react("rust", "made-up", "👍", true).await.unwrap();
+    node.send("rust", "after").await.unwrap();
+    let reacted = wait_for(&mut harness.nodes[1].events, |e| match e {
+        NetworkEvent::ReactionReceived { .. } => Some(true),
+        NetworkEvent::MessageReceived { .. } => Some(false),
+        _ => None,
+    })
+    .await;
+    assert!(!reacted);
+
+    let keypair = Keypair::generate_ed25519();
+    let history = RoomHistory {
+        edits: vec![Edit::new(&keypair, "made-up".to_string(), None)],
+        reactions: vec![Reaction::new(
+            &keypair,
+            "made-up".to_string(),
+            "👎".to_string(),
+            true,
+        )],
+        ..Default::default()
+    };
+    let report = harness.nodes[1].node.import("rust", history).await.unwrap();
+    assert_eq!(report.imported, 0);
+    assert_eq!(report.skipped, 2);
+    let history = harness.nodes[1]
+        .node
+        .edit_history("rust", "made-up")
+        .await
+        .unwrap();
+    assert!(history.is_empty());
+}
+
+#[test]
+fn tampered_edit_does_not_verify() {
+    let keypair = Keypair::generate_ed25519();
+    let edit = Edit::new(&keypair, "1".to_string(), Some("hello".to_string()));
+    assert!(edit.verify());
+
+    let mut tampered = edit.clone();
+    tampered.body = Some("goodbye".to_string());
+    assert!(!tampered.verify());
+
+    // signed by us, but claiming to be someone else
+    let mut impostor = edit;
+    impostor.author = PeerId::random().to_string();
+    assert!(!impostor.verify());
+}
+
+#[test]
+fn tampered_reaction_does_not_verify() {
+    let keypair = Keypair::generate_ed25519();
+    let reaction = Reaction::new(&keypair, "1".to_string(), "👍".to_string(), true);
+    assert!(reaction.verify());
+
+    // taking back someone else's reaction
+    let mut tampered = reaction.clone();
+    tampered.added = false;
+    assert!(!tampered.verify());
+
+    let mut impostor = reaction;
+    impostor.author = PeerId::random().to_string();
+    assert!(!impostor.verify());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn forged_history_is_not_merged() {
+    // a peer that has, or makes up, a message and a reaction their author never signed
+    let keypair = Keypair::generate_ed25519();
+    let author = keypair.public().to_peer_id();
+    let forged = ChatMessage::new(&author, "forged".to_string());
+    let genuine = ChatMessage::new(&author, "genuine".to_string()).signed(&keypair);
+    let liked = Reaction::new(&keypair, genuine.id.clone(), "👍".to_string(), true);
+    let disliked = Reaction {
+        emoji: "👎".to_string(),
+        public_key: String::new(),
+        signature: String::new(),
+        ..liked.clone()
+    };
+    let entries = [
+        Entry::Message(forged),
+        Entry::Message(genuine),
+        Entry::Reaction(liked),
+        Entry::Reaction(disliked),
+    ];
+    let dir = temp_dir();
+    let lines: Vec<String> = entries
+        .into_iter()
+        .map(|entry| {
+            serde_json::to_string(&Record {
+                room: "rust".to_string(),
+                entry,
+            })
+            .unwrap()
+        })
+        .collect();
+    std::fs::write(dir.join("history.jsonl"), lines.join("\n")).unwrap();
+
+    let sharer = TestNode::spawn_with(ChatNode::builder().data_dir(dir)).await;
+    sharer.node.join("rust").await.unwrap();
+    let mut late = TestNode::spawn().await;
+    late.connect(&sharer).await;
+    late.node.join("rust").await.unwrap();
+
+    let peer_id = wait_for(&mut late.events, |e| match e {
+        NetworkEvent::Error {
+            error: NetworkError::InvalidMessage { peer_id, .. },
+        } => Some(peer_id.clone()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer_id, sharer.peer_id);
+    assert_eq!(late.expect_message("rust").await.body, "genuine");
+    assert_eq!(late.expect_reaction("rust").await.emoji, "👍");
+    late.expect_no_message("rust").await;
+    let reactions = late.node.history("rust").await.unwrap().reactions;
+    assert_eq!(reactions.len(), 1);
+}
+
+#[test]
+fn tampered_message_does_not_verify() {
+    let keypair = Keypair::generate_ed25519();
+    let author = keypair.public().to_peer_id();
+    let message = ChatMessage::new(&author, "hello".to_string())
+        .in_reply_to("1".to_string())
+        .signed(&keypair);
+    assert!(message.verify());
+    assert!(!ChatMessage::new(&author, "hello".to_string()).verify());
+
+    let mut tampered = message.clone();
+    tampered.reply_to = None;
+    assert!(!tampered.verify());
+
+    let mut impostor = message;
+    impostor.author = PeerId::random().to_string();
+    assert!(!impostor.verify());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn history_survives_a_restart() {
+    let dir = temp_dir();
+    let mut node = TestNode::spawn_with(ChatNode::builder().data_dir(dir.clone())).await;
+    let mut other = TestNode::spawn().await;
+    other.connect(&node).await;
+    node.node.join("rust").await.unwrap();
+    other.node.join("rust").await.unwrap();
+    other.wait_room_peers("rust", 1).await;
+
+    node.node.send("rust", "mine").await.unwrap();
+    other.expect_message("rust").await;
+    other.node.send("rust", "theirs").await.unwrap();
+    node.expect_message("rust").await;
+
+    // quitting leaves the room and closes the connection
+    node.quit().await;
+    wait_for(&mut other.events, |e| match e {
+        NetworkEvent::PeerDisconnected { .. } => Some(()),
+        _ => None,
+    })
+    .await;
+
+    // the new node only knows the messages from disk
+    let restarted = TestNode::spawn_with(ChatNode::builder().data_dir(dir.clone())).await;
+    assert!(restarted.node.rooms().await.unwrap().is_empty());
+    assert_eq!(restarted.node.stored_rooms().await.unwrap(), ["rust"]);
+    restarted.node.join("rust").await.unwrap();
+    let mut late = TestNode::spawn().await;
+    late.connect(&restarted).await;
+    late.node.join("rust").await.unwrap();
+
+    let mut bodies = Vec::new();
+    for _ in 0..2 {
+        bodies.push(late.expect_message("rust").await.body);
+    }
+    assert_eq!(bodies, ["mine", "theirs"]);
+
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn late_joiner_gets_history_when_joining_before_connecting() {
+    let mut harness = Harness::new(1, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+    harness.nodes[0].node.send("rust", "hello").await.unwrap();
+
+    let mut late = TestNode::spawn().await;
+    late.node.join("rust").await.unwrap();
+    late.connect(&harness.nodes[0]).await;
+
+    let message = late.expect_message("rust").await;
+    assert_eq!(message.body, "hello");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn failed_dial_is_reported_and_the_node_keeps_running() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    // nothing listens there, and there is no `/p2p/` suffix
+    let addr = "/ip4/127.0.0.1/tcp/1".parse().unwrap();
+    harness.nodes[0].node.dial(addr).await.unwrap();
+
+    let error = wait_for(&mut harness.nodes[0].events, |e| match e {
+        NetworkEvent::Error { error } => Some(error.clone()),
+        _ => None,
+    })
+    .await;
+    assert!(matches!(error, NetworkError::Dial { .. }), "{error:?}");
+
+    harness.nodes[0]
+        .node
+        .send("rust", "still here")
+        .await
+        .unwrap();
+    let message = harness.nodes[1].expect_message("rust").await;
+    assert_eq!(message.body, "still here");
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn publish_errors_are_reported() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    // larger than a gossipsub message may be
+    let body = "a".repeat(70 * 1024);
+    let message = harness.nodes[0].node.send("rust", &body).await.unwrap();
+
+    let error = wait_for(&mut harness.nodes[0].events, |e| match e {
+        NetworkEvent::Error { error } => Some(error.clone()),
+        _ => None,
+    })
+    .await;
+    assert!(
+        matches!(&error, NetworkError::Publish { topic, .. } if topic == "rust"),
+        "{error:?}"
+    );
+    let status = expect_delivery(&mut harness.nodes[0], &message.id).await;
+    assert!(
+        matches!(status, DeliveryStatus::Failed { .. }),
+        "{status:?}"
+    );
+
+    // a failed message may be sent again
+    harness.nodes[0]
+        .node
+        .resend("rust", message.clone())
+        .await
+        .unwrap();
+    let status = expect_delivery(&mut harness.nodes[0], &message.id).await;
+    assert!(
+        matches!(status, DeliveryStatus::Failed { .. }),
+        "{status:?}"
+    );
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn messages_without_peers_are_sent_in_order_once_someone_joins() {
+    let mut alone = TestNode::spawn().await;
+    alone.node.join("rust").await.unwrap();
+    let mut sent = vec![];
+    for body in ["first", "second", "third"] {
+        let message = alone.node.send("rust", body).await.unwrap();
+        let status = expect_delivery(&mut alone, &message.id).await;
+        assert_eq!(status, DeliveryStatus::Queued);
+        sent.push(message);
+    }
+    // sending a queued message again doesn't queue it twice
+    alone.node.resend("rust", sent[0].clone()).await.unwrap();
+    let status = expect_delivery(&mut alone, &sent[0].id).await;
+    assert_eq!(status, DeliveryStatus::Queued);
+
+    let mut late = TestNode::spawn().await;
+    late.connect(&alone).await;
+    late.node.join("rust").await.unwrap();
+
+    let mut bodies = vec![];
+    for _ in 0..3 {
+        bodies.push(late.expect_message("rust").await.body);
+    }
+    assert_eq!(bodies, ["first", "second", "third"]);
+    late.expect_no_message("rust").await;
+    for message in &sent {
+        let status = expect_delivery(&mut alone, &message.id).await;
+        assert_eq!(status, DeliveryStatus::Published);
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn outbox_survives_a_restart() {
+    let dir = temp_dir();
+    let node = TestNode::spawn_with(ChatNode::builder().data_dir(dir.clone())).await;
+    let peer_id = node.peer_id.clone();
+    node.node.join("rust").await.unwrap();
+    node.node.send("rust", "anyone?").await.unwrap();
+    node.quit().await;
+
+    // the same identity, so the message is still ours
+    let node = TestNode::spawn_with(ChatNode::builder().data_dir(dir.clone())).await;
+    assert_eq!(node.peer_id, peer_id);
+    node.node.join("rust").await.unwrap();
+
+    let mut peer = TestNode::spawn().await;
+    peer.connect(&node).await;
+    peer.node.join("rust").await.unwrap();
+    let message = peer.expect_message("rust").await;
+    assert_eq!(message.body, "anyone?");
+    assert_eq!(message.author, peer_id);
+
+    node.quit().await;
+    std::fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn delivery_status_is_reported() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+    let message = harness.nodes[0].node.send("rust", "hi").await.unwrap();
+    let status = expect_delivery(&mut harness.nodes[0], &message.id).await;
+    assert_eq!(status, DeliveryStatus::Published);
+
+    // it was published, so there is nothing to send again
+    harness.nodes[1].expect_message("rust").await;
+    harness.nodes[0]
+        .node
+        .resend("rust", message.clone())
+        .await
+        .unwrap();
+    expect_resend_error(&mut harness.nodes[0], &message.id).await;
+    harness.nodes[1].expect_no_message("rust").await;
+
+    // nor can anyone send a message in someone else's name
+    let keypair = Keypair::generate_ed25519();
+    let author = keypair.public().to_peer_id();
+    let forged = ChatMessage::new(&author, "not mine".to_string()).signed(&keypair);
+    harness.nodes[0]
+        .node
+        .resend("rust", forged.clone())
+        .await
+        .unwrap();
+    expect_resend_error(&mut harness.nodes[0], &forged.id).await;
+    harness.nodes[1].expect_no_message("rust").await;
+    assert!(harness.nodes[0]
+        .node
+        .history("rust")
+        .await
+        .unwrap()
+        .messages
+        .iter()
+        .all(|m| m.id != forged.id));
+}
+
+async fn expect_resend_error(node: &mut TestNode, id: &str) {
+    let message_id = wait_for(&mut node.events, |e| match e {
+        NetworkEvent::Error {
+            error: NetworkError::Resend { message_id, .. },
+        } => Some(message_id.clone()),
+        _ => None,
+    })
+    .await;
+    assert_eq!(message_id, id);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn read_receipts_can_be_turned_off() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    let mut quiet = TestNode::spawn_with(ChatNode::builder().read_receipts(false)).await;
+    quiet.connect(&harness.nodes[0]).await;
+    harness.join_all("rust").await;
+    quiet.node.join("rust").await.unwrap();
+    harness.nodes[0].wait_room_peers("rust", 1).await;
+
+    let message = harness.nodes[0].node.send("rust", "hi").await.unwrap();
+    for node in [&harness.nodes[1].node, &quiet.node] {
+        node.mark_seen("rust", vec![message.id.clone()])
+            .await
+            .unwrap();
+    }
+
+    let (peer_id, message_ids) = wait_for(&mut harness.nodes[0].events, |e| match e {
+        NetworkEvent::ReceiptReceived {
+            peer_id,
+            message_ids,
+            ..
+        } => Some((peer_id.clone(), message_ids.clone())),
+        _ => None,
+    })
+    .await;
+    assert_eq!(peer_id, harness.nodes[1].peer_id);
+    assert_eq!(message_ids, [message.id]);
+
+    let receipt = timeout(QUIET, async {
+        wait_for(&mut harness.nodes[0].events, |e| match e {
+            NetworkEvent::ReceiptReceived { peer_id, .. } => Some(peer_id.clone()),
+            _ => None,
+        })
+        .await
+    })
+    .await;
+    assert!(receipt.is_err(), "unexpected receipt from {receipt:?}");
+}
+
+async fn expect_delivery(node: &mut TestNode, id: &str) -> DeliveryStatus {
+    wait_for(&mut node.events, |e| match e {
+        NetworkEvent::Delivery {
+            message_id, status, ..
+        } if message_id == id => Some(status.clone()),
+        _ => None,
+    })
+    .await
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn presence_is_broadcast() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.nodes[0]
+        .node
+        .set_presence(PresenceState::Away, "lunch")
+        .await
+        .unwrap();
+
+    let author = harness.nodes[0].peer_id.clone();
+    let presence = wait_for(&mut harness.nodes[1].events, |e| match e {
+        NetworkEvent::PresenceReceived { presence }
+            if presence.author == author && presence.state == PresenceState::Away =>
+        {
+            Some(presence.clone())
+        }
+        _ => None,
+    })
+    .await;
+    assert_eq!(presence.status, "lunch");
+
+    let known = harness.nodes[1].node.presence().await.unwrap();
+    assert_eq!(known, [presence]);
+    // the presence topic is not a room
+    assert!(harness.nodes[1].node.rooms().await.unwrap().is_empty());
+}
+
+#[test]
+fn presence_expires_when_a_peer_goes_quiet() {
+    let peer_id = PeerId::random();
+    let mut presences = Presences::default();
+    let start = Instant::now();
+
+    let online = Presence::new(&peer_id, PresenceState::Online, String::new());
+    let mut away = Presence::new(&peer_id, PresenceState::Away, "brb".to_string());
+    away.timestamp = online.timestamp + 1;
+    assert!(presences.apply(away.clone(), start));
+    // a heartbeat that arrives late changes nothing
+    assert!(!presences.apply(online, start));
+    assert_eq!(presences.list(), [away]);
+
+    let timeout = Duration::from_secs(30);
+    assert!(presences
+        .expire(start + Duration::from_secs(10), timeout)
+        .is_empty());
+    assert_eq!(
+        presences.expire(start + timeout, timeout),
+        [peer_id.to_string()]
+    );
+    assert!(presences.list().is_empty());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn search_finds_stored_messages() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+    harness.join_all("cats").await;
+    let [a, b] = &mut harness.nodes[..] else {
+        unreachable!()
+    };
+
+    let tokio = a.node.send("rust", "Tokio is great").await.unwrap();
+    b.expect_message("rust").await;
+    let reply = b.node.send("rust", "tokio or async-std?").await.unwrap();
+    a.expect_message("rust").await;
+    a.node.send("cats", "my cat likes tokio").await.unwrap();
+    b.expect_message("cats").await;
+    let deleted = a.node.send("rust", "tokio typo").await.unwrap();
+    b.expect_message("rust").await;
+    a.node.delete("rust", &deleted.id).await.unwrap();
+    b.expect_edit("rust").await;
+
+    let query = |s: &str| s.parse::<SearchQuery>().unwrap();
+    let hits = b.node.search(query("TOKIO")).await.unwrap();
+    let bodies: Vec<&str> = hits.iter().map(|h| h.message.body.as_str()).collect();
+    assert_eq!(
+        bodies,
+        [
+            "my cat likes tokio",
+            "tokio or async-std?",
+            "Tokio is great"
+        ]
+    );
+
+    let hits = b.node.search(query("room:rust is tokio")).await.unwrap();
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].message.id, tokio.id);
+    assert_eq!(hits[0].next.as_ref().map(|m| &m.id), Some(&reply.id));
+
+    let author = &b.node.peer_id().to_string();
+    let hits = b
+        .node
+        .search(query(&format!("from:{}", &author[author.len() - 7..])));
+    assert_eq!(hits.await.unwrap().len(), 1);
+
+    let today = format_date(tokio.timestamp);
+    let hits = b
+        .node
+        .search(query(&format!("before:{today}")))
+        .await
+        .unwrap();
+    assert!(hits.is_empty());
+    let hits = b
+        .node
+        .search(query(&format!("after:{today}")))
+        .await
+        .unwrap();
+    assert_eq!(hits.len(), 3);
+
+    let history = b.node.history("rust").await.unwrap();
+    assert_eq!(history.messages.len(), 3);
+    assert_eq!(history.edits.len(), 1);
+}
+
+#[test]
+fn search_query_parses_filters() {
+    let query: SearchQuery =
+        "room:rust hello from:alice01 after:2024-02-29 world before:2024-03-01"
+            .parse()
+            .unwrap();
+    assert_eq!(query.text, "hello world");
+    assert_eq!(query.room.as_deref(), Some("rust"));
+    assert_eq!(query.author.as_deref(), Some("alice01"));
+    assert_eq!(query.after, Some(1_709_164_800_000));
+    assert_eq!(query.before, Some(1_709_251_200_000));
+    assert_eq!(format_date(1_709_251_199_999), "2024-02-29");
+
+    assert!("after:yesterday".parse::<SearchQuery>().is_err());
+    assert!("before:2024-02".parse::<SearchQuery>().is_err());
+    // days past the end of the month don't roll into the next one
+    assert!("after:2024-02-30".parse::<SearchQuery>().is_err());
+    assert!("after:2023-02-29".parse::<SearchQuery>().is_err());
+    assert!("before:2023-04-31".parse::<SearchQuery>().is_err());
+    assert!("after:2024-02-29".parse::<SearchQuery>().is_ok());
+    assert!("after:2000-02-29".parse::<SearchQuery>().is_ok());
+    assert!("after:2100-02-29".parse::<SearchQuery>().is_err());
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn export_can_be_imported_but_not_forged() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+    let [a, b] = &mut harness.nodes[..] else {
+        unreachable!()
+    };
+    let first = a.node.send("rust", "hello").await.unwrap();
+    b.expect_message("rust").await;
+    b.node.reply("rust", &first.id, "hi").await.unwrap();
+    a.expect_message("rust").await;
+    a.node.edit("rust", &first.id, "hello!").await.unwrap();
+    b.expect_edit("rust").await;
+    a.node.react("rust", &first.id, "👍", true).await.unwrap();
+    b.expect_reaction("rust").await;
+
+    let history = b.node.history("rust").await.unwrap();
+    let mut nicknames = HashMap::new();
+    nicknames.insert(b.node.peer_id().to_string(), "bob".to_string());
+    let jsonl = archive::export("rust", &history, &nicknames, ExportFormat::Jsonl);
+    assert_eq!(jsonl.lines().count(), 4);
+    assert!(jsonl.contains(r#""nickname":"bob""#));
+
+    let markdown = archive::export("rust", &history, &nicknames, ExportFormat::Markdown);
+    assert!(markdown.starts_with("# rust\n"));
+    assert!(markdown.contains("> hello! (edited)"));
+    assert!(markdown.contains(&format!("bob (`{}`)", b.node.peer_id())));
+    assert!(markdown.contains(&history.messages[0].signature));
+    let text = archive::export("rust", &history, &nicknames, ExportFormat::Text);
+    assert!(text.contains(&format!("reply to: {}", first.id)));
+
+    // someone changed what a tells in the archive
+    let forged = jsonl.replace(r#""body":"hello""#, r#""body":"goodbye""#);
+    let mut rooms = archive::import(&forged).unwrap();
+    let imported = rooms.remove("rust").unwrap();
+    assert_eq!(imported.messages.len(), 2);
+
+    // the reaction and the edit of the forged message have nothing to apply to
+    let fresh = TestNode::spawn().await;
+    let report = fresh.node.import("rust", imported).await.unwrap();
+    assert_eq!(
+        report,
+        ImportReport {
+            imported: 1,
+            skipped: 2,
+            rejected: 1,
+        }
+    );
+    let stored = fresh.node.history("rust").await.unwrap();
+    let bodies: Vec<&str> = stored.messages.iter().map(|m| m.body.as_str()).collect();
+    assert_eq!(bodies, ["hi"]);
+
+    // the untouched archive fills the gap, the reply is already there
+    let mut rooms = archive::import(&jsonl).unwrap();
+    let report = fresh.node.import("rust", rooms.remove("rust").unwrap());
+    assert_eq!(
+        report.await.unwrap(),
+        ImportReport {
+            imported: 3,
+            skipped: 1,
+            rejected: 0,
+        }
+    );
+    assert_eq!(fresh.node.history("rust").await.unwrap().messages.len(), 2);
+
+    let error = archive::import("{}\nnot json").unwrap_err();
+    assert_eq!(error.line, 1);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn status_reports_addresses_peers_and_meshes() {
+    let mut harness = Harness::new(2, Topology::FullMesh).await;
+    harness.join_all("rust").await;
+
+    let status = harness.nodes[0].node.status().await.unwrap();
+    let address = harness.nodes[0].address.to_string();
+    assert!(status.listen_addresses.contains(&address), "{status:?}");
+    assert!(status.peers.contains(&harness.nodes[1].peer_id));
+    // the nodes only listen on the loopback
+    assert_eq!(status.nat, NatStatus::Private);
+    // the room peers are known before the mesh is built
+    let peers = harness.nodes[0].node.room_peers("rust").await.unwrap();
+    assert_eq!(peers, [harness.nodes[1].node.peer_id()]);
+
+    // gossipsub builds the meshes on its heartbeat
+    let deadline = Instant::now() + Duration::from_secs(15);
+    loop {
+        let mut meshes = vec![];
+        for node in &harness.nodes {
+            let status = node.node.status().await.unwrap();
+            meshes.push(status.mesh.get("rust").copied().unwrap_or(0));
+        }
+        if meshes.contains(&1) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "no mesh in time: {meshes:?}");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+    }
+}
+
+#[test]
+fn status_follows_the_events() {
+    let mut status = NodeStatus::default();
+    assert!(status.apply(&NetworkEvent::PeerConnected {
+        peer_id: "a".to_string()
+    }));
+    // a second connection to the same peer
+    status.apply(&NetworkEvent::PeerConnected {
+        peer_id: "a".to_string(),
+    });
+    status.apply(&NetworkEvent::MeshChanged {
+        topic: "rust".to_string(),
+        peers: 1,
+    });
+    assert_eq!(status.peers.len(), 1);
+    assert_eq!(status.mesh["rust"], 1);
+    status.apply(&NetworkEvent::PeerDisconnected {
+        peer_id: "a".to_string(),
+    });
+    assert!(status.peers.is_empty());
+    assert!(!status.apply(&NetworkEvent::Typing {
+        topic: "rust".to_string(),
+        peer_id: "a".to_string()
+    }));
+
+    let public = |a: &str| is_public(&a.parse().unwrap());
+    assert!(public("/ip4/8.8.8.8/tcp/4001"));
+    assert!(public("/ip6/2606:4700::1111/tcp/4001"));
+    assert!(!public("/ip4/192.168.1.5/tcp/4001"));
+    assert!(!public("/ip4/100.64.0.1/tcp/4001"));
+    assert!(!public("/ip4/127.0.0.1/tcp/4001"));
+    assert!(!public("/ip6/fe80::1/tcp/4001"));
+    assert!(!public("/dns4/example.com/tcp/4001"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn control_socket_is_private_and_kept_by_its_daemon() {
+    let node = TestNode::spawn().await;
+    let path = temp_dir().join("run").join("p2p-chat.sock");
+    let (socket, daemon) = (path.clone(), node.node.clone());
+    tokio::spawn(async move { control::serve(&socket, daemon).await });
+
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut client = loop {
+        match Client::connect(&path).await {
+            Ok(client) => break client,
+            Err(e) => assert!(Instant::now() < deadline, "no socket: {e}"),
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+
+    // a second daemon doesn't take the socket away
+    let second = TestNode::spawn().await;
+    let error = control::serve(&path, second.node.clone())
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), std::io::ErrorKind::AddrInUse);
+    let id = client.call("id", serde_json::Value::Null).await.unwrap();
+    assert_eq!(id, node.peer_id);
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn control_api_follows_json_rpc() {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    let node = TestNode::spawn().await;
+    let (client, server) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(control::handle_client(server, node.node.clone()));
+    let (reader, mut writer) = tokio::io::split(client);
+    let mut lines = BufReader::new(reader).lines();
+
+    let requests = [
+        // a notification is run, but gets no response
+        r#"{"jsonrpc": "2.0", "method": "join", "params": {"room": "rust"}}"#,
+        r#"{"jsonrpc": "2.0", "id": null, "method": "rooms"}"#,
+        r#"{"jsonrpc": "1.0", "id": 2, "method": "id"}"#,
+        r#"{"id": 3, "method": "id"}"#,
+        "not json",
+    ];
+    for request in requests {
+        writer
+            .write_all(format!("{request}\n").as_bytes())
+            .await
+            .unwrap();
+    }
+    let mut responses = vec![];
+    for _ in 0..4 {
+        let line = lines.next_line().await.unwrap().unwrap();
+        responses.push(serde_json::from_str::<serde_json::Value>(&line).unwrap());
+    }
+    let [rooms, old, missing, garbage] = &responses[..] else {
+        unreachable!()
+    };
+    assert_eq!(rooms.get("id"), Some(&serde_json::Value::Null));
+    assert_eq!(rooms["result"], serde_json::json!(["rust"]));
+    assert_eq!(old["id"], 2);
+    assert_eq!(old["error"]["code"], -32600);
+    assert_eq!(missing["error"]["code"], -32600);
+    assert_eq!(garbage["error"]["code"], -32700);
+}
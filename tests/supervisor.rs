@@ -0,0 +1,110 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+use p2p_chat::{Supervisor, TaskError};
+use tokio::{sync::mpsc, time::timeout};
+
+#[tokio::test]
+async fn finished_tasks_are_not_restarted() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let mut supervisor = Supervisor::new();
+
+    let counter = runs.clone();
+    supervisor.spawn_restartable("ok", 3, move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        async { Ok(()) }
+    });
+
+    assert_eq!(supervisor.run().await, Ok(()));
+    assert_eq!(runs.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn failed_task_is_restarted_until_it_works() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let mut supervisor = Supervisor::new();
+
+    let counter = runs.clone();
+    supervisor.spawn_restartable("flaky", 3, move || {
+        let run = counter.fetch_add(1, Ordering::SeqCst);
+        async move {
+            if run < 2 {
+                return Err("not yet".into());
+            }
+            Ok(())
+        }
+    });
+
+    assert_eq!(supervisor.run().await, Ok(()));
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+}
+
+#[tokio::test]
+async fn task_that_keeps_failing_is_reported() {
+    let runs = Arc::new(AtomicUsize::new(0));
+    let failures = Arc::new(AtomicUsize::new(0));
+    let mut supervisor = Supervisor::new();
+
+    let counter = runs.clone();
+    supervisor.spawn_restartable("broken", 2, move || {
+        counter.fetch_add(1, Ordering::SeqCst);
+        async { Err("disk full".into()) }
+    });
+    let reported = failures.clone();
+    supervisor.on_failure(move |_| {
+        reported.fetch_add(1, Ordering::SeqCst);
+    });
+
+    assert_eq!(
+        supervisor.run().await,
+        Err(TaskError {
+            task: "broken",
+            reason: "disk full".to_string(),
+        })
+    );
+    // the first run and two restarts
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+    assert_eq!(failures.load(Ordering::SeqCst), 1);
+}
+
+#[tokio::test]
+async fn panic_is_a_failure() {
+    let mut supervisor = Supervisor::new();
+    supervisor.spawn("ok", async { Ok(()) });
+    supervisor.spawn("ui", async { panic!("out of bounds") });
+
+    assert_eq!(
+        supervisor.run().await,
+        Err(TaskError {
+            task: "ui",
+            reason: "panicked: out of bounds".to_string(),
+        })
+    );
+}
+
+#[tokio::test]
+async fn failed_task_lets_go_of_its_factory() {
+    let (tx, rx) = mpsc::unbounded_channel::<()>();
+    let rx = Arc::new(Mutex::new(rx));
+    let mut supervisor = Supervisor::new();
+
+    supervisor.spawn_restartable("storage", 1, move || {
+        let _rx = rx.clone();
+        async { Err("disk full".into()) }
+    });
+    // only stops once no one can receive what it would send
+    let sender = tx.clone();
+    supervisor.spawn("network", async move {
+        sender.closed().await;
+        Ok(())
+    });
+
+    let result = timeout(Duration::from_secs(5), supervisor.run()).await;
+    assert!(result.expect("the receiver was kept").is_err());
+    assert!(tx.send(()).is_err());
+}
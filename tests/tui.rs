@@ -0,0 +1,1244 @@
+//! Renders the TUI pages on a `TestBackend` and compares
+//! them with the snapshots in `tests/snapshots`.
+//!
+//! Run with `UPDATE_SNAPSHOTS=1` to accept the new output
+//! after changing the layout on purpose.
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::{temp_dir, TestNode};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use log::{Level, LevelFilter};
+use p2p_chat::{
+    app::{AppMessage, AppStyle, Page},
+    chat_room::{ChatRoom, InputMode, REACTIONS, TYPING_TIMEOUT},
+    clock::Timezone,
+    console::Console,
+    keymap::{Keymap, KeysConfig, Preset},
+    logger::{LogBuffer, LogRecord},
+    models::{
+        history::RoomHistory,
+        message::{DeliveryStatus, Reaction},
+        presence::{Presence, PresenceState},
+        search::{SearchHit, SearchQuery},
+        status::NatStatus,
+    },
+    search::Search,
+    status_bar::{Connectivity, StatusBar, CONNECT_GRACE},
+    topic_list::TopicList,
+    ui::{self, draw_help, draw_notification, wrap, Panes},
+    ChatMessage, ChatNode, NetworkEvent, NodeDriver,
+};
+use tokio::{
+    sync::mpsc::{self, Receiver, Sender},
+    time::Instant,
+};
+use tui::{
+    backend::TestBackend,
+    buffer::Buffer,
+    text::{Span, Spans},
+    Terminal,
+};
+use unicode_width::UnicodeWidthStr;
+
+/// The channels that the pages talk to. The node is never
+/// run, its messages just wait in the channel.
+struct Harness {
+    tx: Sender<AppMessage>,
+    rx: Receiver<AppMessage>,
+    node: ChatNode,
+    keymap: Keymap,
+    _driver: NodeDriver,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let (tx, rx) = mpsc::channel(200);
+        let (node, _driver) = ChatNode::builder().build();
+        Self {
+            tx,
+            rx,
+            node,
+            keymap: Keymap::default(),
+            _driver,
+        }
+    }
+
+    async fn press_topic_list<K: Into<KeyEvent> + Copy>(&self, page: &mut TopicList, keys: &[K]) {
+        for k in keys {
+            page.keybindings((*k).into(), &self.keymap, &self.tx, &self.node)
+                .await;
+        }
+    }
+
+    async fn press_chat_room<K: Into<KeyEvent> + Copy>(&self, page: &mut ChatRoom, keys: &[K]) {
+        for k in keys {
+            page.keybindings((*k).into(), &self.keymap, &self.tx, &self.node)
+                .await;
+        }
+    }
+
+    async fn press_search<K: Into<KeyEvent> + Copy>(&self, page: &mut Search, keys: &[K]) {
+        for k in keys {
+            page.keybindings((*k).into(), &self.keymap, &self.tx, &self.node)
+                .await;
+        }
+    }
+}
+
+/// The `PeerId`s are only shown by their last 7 characters.
+const ME: &str = "12D3KooWDpJ7As7BWAwRMfu1VU2WCqNjvq387JEYKDBj4kx6me00001";
+const ALICE: &str = "12D3KooWRBy97UB99e3J6hiPesre1MZeuNQvfan4gBziswrralice01";
+const BOB: &str = "12D3KooWQYhTNQdmr3ArTeUHRYeFMHmRxbhN6xBjNSwsqcnrbob0002";
+
+fn message(id: &str, author: &str, body: &str) -> ChatMessage {
+    ChatMessage {
+        id: id.to_string(),
+        author: author.to_string(),
+        body: body.to_string(),
+        timestamp: 0,
+        reply_to: None,
+        public_key: String::new(),
+        signature: String::new(),
+    }
+}
+
+/// The messages sent from the page have the time they were
+/// sent, which would change the snapshots every minute.
+fn clear_times(page: &mut ChatRoom) {
+    for message in &mut page.messages {
+        message.timestamp = 0;
+    }
+}
+
+fn reply(id: &str, author: &str, body: &str, parent: &str) -> ChatMessage {
+    message(id, author, body).in_reply_to(parent.to_string())
+}
+
+fn reaction(message_id: &str, author: &str, emoji: &str) -> Reaction {
+    Reaction {
+        message_id: message_id.to_string(),
+        author: author.to_string(),
+        emoji: emoji.to_string(),
+        added: true,
+        timestamp: 0,
+        public_key: String::new(),
+        signature: String::new(),
+    }
+}
+
+fn presence(author: &str, state: PresenceState, status: &str) -> Presence {
+    Presence {
+        author: author.to_string(),
+        state,
+        status: status.to_string(),
+        timestamp: 0,
+    }
+}
+
+fn chars(s: &str) -> Vec<KeyCode> {
+    s.chars().map(KeyCode::Char).collect()
+}
+
+fn render_topic_list(page: &mut TopicList, width: u16, height: u16) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal
+        .draw(|f| page.draw(f, f.size(), &AppStyle::new()))
+        .unwrap();
+    buffer_view(terminal.backend().buffer(), None)
+}
+
+fn render_chat_room(page: &mut ChatRoom, width: u16, height: u16) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal
+        .draw(|f| page.draw(f, f.size(), &AppStyle::new(), &Keymap::default()))
+        .unwrap();
+    // the cursor is only shown in insert mode
+    let cursor = match page.input_mode {
+        InputMode::Insert => Some(terminal.get_cursor().unwrap()),
+        _ => None,
+    };
+    buffer_view(terminal.backend().buffer(), cursor)
+}
+
+fn render_search(page: &mut Search, width: u16, height: u16) -> String {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal
+        .draw(|f| page.draw(f, f.size(), &AppStyle::new(), &Keymap::default()))
+        .unwrap();
+    let cursor = page.editing.then(|| terminal.get_cursor().unwrap());
+    buffer_view(terminal.backend().buffer(), cursor)
+}
+
+/// Every row between `|`, so trailing spaces are visible.
+fn buffer_view(buffer: &Buffer, cursor: Option<(u16, u16)>) -> String {
+    let mut view = String::new();
+    for row in buffer.content.chunks(buffer.area.width as usize) {
+        view.push('|');
+        let mut skip = 0;
+        for cell in row {
+            // the cells covered by a wide symbol
+            if skip == 0 {
+                view.push_str(&cell.symbol);
+            }
+            skip = std::cmp::max(skip, cell.symbol.width()).saturating_sub(1);
+        }
+        view.push_str("|\n");
+    }
+    if let Some((x, y)) = cursor {
+        view.push_str(&format!("cursor: ({x}, {y})\n"));
+    }
+    view
+}
+
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/snapshots")
+        .join(format!("{name}.snap"));
+
+    if std::env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let Ok(expected) = fs::read_to_string(&path) else {
+        panic!(
+            "snapshot {} is missing, run with UPDATE_SNAPSHOTS=1 to write it\n\nactual:\n{actual}",
+            path.display()
+        );
+    };
+    assert!(
+        expected == actual,
+        "snapshot {name} does not match\n\nexpected:\n{expected}\nactual:\n{actual}"
+    );
+}
+
+#[tokio::test]
+async fn topic_list() {
+    let mut page = TopicList::new();
+    assert_snapshot("topic_list", &render_topic_list(&mut page, 60, 10));
+}
+
+#[tokio::test]
+async fn topic_list_navigation() {
+    let harness = Harness::new();
+    let mut page = TopicList::new();
+
+    harness
+        .press_topic_list(&mut page, &[KeyCode::Char('j'), KeyCode::Down])
+        .await;
+    assert_eq!(page.state.selected(), Some(2));
+    assert_snapshot(
+        "topic_list_navigation",
+        &render_topic_list(&mut page, 60, 10),
+    );
+
+    // wraps around at the top
+    harness
+        .press_topic_list(&mut page, &[KeyCode::Char('k'), KeyCode::Up, KeyCode::Up])
+        .await;
+    assert_eq!(page.state.selected(), Some(4));
+}
+
+#[tokio::test]
+async fn topic_list_narrow() {
+    let mut page = TopicList::new();
+    assert_snapshot("topic_list_narrow", &render_topic_list(&mut page, 24, 6));
+}
+
+#[tokio::test]
+async fn topic_list_enter_opens_chat_room() {
+    let mut harness = Harness::new();
+    let mut page = TopicList::new();
+
+    harness
+        .press_topic_list(&mut page, &[KeyCode::Down, KeyCode::Enter])
+        .await;
+
+    match harness.rx.try_recv() {
+        Ok(AppMessage::OpenRoom { room }) => assert_eq!(room, "1"),
+        other => panic!("expected to open a chat room, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn topic_list_quit() {
+    let mut harness = Harness::new();
+    let mut page = TopicList::new();
+
+    harness
+        .press_topic_list(&mut page, &[KeyCode::Char('q')])
+        .await;
+
+    assert!(matches!(harness.rx.try_recv(), Ok(AppMessage::Quit)));
+}
+
+#[tokio::test]
+async fn chat_room_normal_mode() {
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    assert_snapshot(
+        "chat_room_normal_mode",
+        &render_chat_room(&mut page, 60, 10),
+    );
+}
+
+#[tokio::test]
+async fn chat_room_insert_mode() {
+    let harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+
+    harness.press_chat_room(&mut page, &chars("ihello")).await;
+    assert_snapshot(
+        "chat_room_insert_mode",
+        &render_chat_room(&mut page, 60, 10),
+    );
+
+    // backspace and back to normal mode
+    harness
+        .press_chat_room(&mut page, &[KeyCode::Backspace, KeyCode::Esc])
+        .await;
+    assert_eq!(page.input, "hell");
+    assert_snapshot(
+        "chat_room_back_to_normal_mode",
+        &render_chat_room(&mut page, 60, 10),
+    );
+}
+
+#[tokio::test]
+async fn chat_room_send_messages() {
+    let harness = Harness::new();
+    let me = harness.node.peer_id().to_string();
+    let mut page = ChatRoom::new("0".to_string(), me);
+
+    harness.press_chat_room(&mut page, &chars("ifirst")).await;
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    harness.press_chat_room(&mut page, &chars("second")).await;
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+
+    assert!(page.input.is_empty());
+    clear_times(&mut page);
+    assert_snapshot(
+        "chat_room_send_messages",
+        &render_chat_room(&mut page, 60, 10),
+    );
+}
+
+#[tokio::test]
+async fn chat_room_long_message() {
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message(
+        "1",
+        ALICE,
+        &"lorem ipsum dolor sit amet ".repeat(5),
+    ));
+    page.push_message(message("2", BOB, "short"));
+
+    assert_snapshot(
+        "chat_room_long_message",
+        &render_chat_room(&mut page, 40, 10),
+    );
+}
+
+#[tokio::test]
+async fn chat_room_narrow() {
+    let harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "a message in a tiny terminal"));
+
+    harness.press_chat_room(&mut page, &chars("itiny")).await;
+    assert_snapshot("chat_room_narrow", &render_chat_room(&mut page, 16, 8));
+}
+
+#[tokio::test]
+async fn chat_room_unicode() {
+    let harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "olá, 世界 🦀"));
+
+    harness.press_chat_room(&mut page, &chars("i日本")).await;
+    assert_snapshot("chat_room_unicode", &render_chat_room(&mut page, 40, 10));
+}
+
+#[tokio::test]
+async fn timestamps_and_day_separators() {
+    // 2024-05-01 23:58 UTC
+    let sent = 1_714_607_880_000;
+    let at = |id, author, body, timestamp| ChatMessage {
+        timestamp,
+        ..message(id, author, body)
+    };
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.time.timezone = Timezone::Utc;
+    page.push_message(at("1", ALICE, "still up?", sent));
+    page.push_message(at("2", BOB, "yes", sent + 60 * 1000));
+    page.push_message(at("3", ALICE, "past midnight", sent + 3 * 60 * 1000));
+    // its clock is an hour ahead of ours
+    page.push_live_message(
+        at("4", BOB, "time to sleep", sent + 65 * 60 * 1000),
+        sent + 5 * 60 * 1000,
+    );
+
+    assert_snapshot("timestamps", &render_chat_room(&mut page, 60, 12));
+}
+
+#[tokio::test]
+async fn chat_room_reactions() {
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "ship it?"));
+    page.push_message(message("2", BOB, "shipped"));
+    page.push_reaction(reaction("1", BOB, "👍"));
+    page.push_reaction(reaction("1", ME, "👍"));
+    page.push_reaction(reaction("1", BOB, "🎉"));
+    page.push_reaction(reaction("2", ALICE, "🚀"));
+
+    // only the counts, until a message is selected
+    assert_snapshot("chat_room_reactions", &render_chat_room(&mut page, 60, 12));
+
+    page.state.select(Some(0));
+    assert_snapshot(
+        "chat_room_reactions_selected",
+        &render_chat_room(&mut page, 60, 12),
+    );
+}
+
+#[tokio::test]
+async fn chat_room_reaction_picker() {
+    let harness = Harness::new();
+    let me = harness.node.peer_id().to_string();
+    let mut page = ChatRoom::new("0".to_string(), me.clone());
+    page.push_message(message("1", ALICE, "ship it?"));
+
+    // nothing to react to until a message is selected
+    harness.press_chat_room(&mut page, &chars("r")).await;
+    assert!(matches!(page.input_mode, InputMode::Normal));
+
+    harness
+        .press_chat_room(
+            &mut page,
+            &[KeyCode::Char('k'), KeyCode::Char('r'), KeyCode::Right],
+        )
+        .await;
+    assert!(matches!(page.input_mode, InputMode::React(1)));
+    assert_snapshot(
+        "chat_room_reaction_picker",
+        &render_chat_room(&mut page, 60, 10),
+    );
+
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    assert!(matches!(page.input_mode, InputMode::Normal));
+    assert_eq!(
+        page.reactions.summary("1"),
+        [(REACTIONS[1], vec![me.as_str()])]
+    );
+
+    // picking it again takes it back
+    harness.press_chat_room(&mut page, &chars("r2")).await;
+    assert!(page.reactions.summary("1").is_empty());
+}
+
+#[tokio::test]
+async fn chat_room_edit_and_delete() {
+    let harness = Harness::new();
+    let me = harness.node.peer_id().to_string();
+    let mut page = ChatRoom::new("0".to_string(), me.clone());
+    page.push_message(message("1", &me, "helo"));
+    page.push_message(message("2", ALICE, "hi"));
+    page.push_message(message("3", &me, "oops"));
+
+    // delete the last message
+    harness.press_chat_room(&mut page, &chars("kd")).await;
+
+    // only our own messages can be edited
+    harness.press_chat_room(&mut page, &chars("ke")).await;
+    assert!(matches!(page.input_mode, InputMode::Normal));
+
+    harness.press_chat_room(&mut page, &chars("ke")).await;
+    assert!(matches!(page.input_mode, InputMode::Insert));
+    assert_eq!(page.input, "helo");
+    assert_snapshot("chat_room_editing", &render_chat_room(&mut page, 60, 10));
+
+    harness
+        .press_chat_room(&mut page, &[KeyCode::Backspace, KeyCode::Backspace])
+        .await;
+    harness.press_chat_room(&mut page, &chars("llo")).await;
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    assert!(matches!(page.input_mode, InputMode::Normal));
+    assert!(page.input.is_empty());
+
+    // the versions of the selected message
+    harness.press_chat_room(&mut page, &chars("v")).await;
+    assert_snapshot("chat_room_edited", &render_chat_room(&mut page, 60, 10));
+}
+
+#[tokio::test]
+async fn chat_room_reply() {
+    let harness = Harness::new();
+    let me = harness.node.peer_id().to_string();
+    let mut page = ChatRoom::new("0".to_string(), me.clone());
+    page.push_message(message("1", ALICE, "lunch?"));
+    page.push_message(message("2", BOB, "later"));
+
+    // the selected message is quoted in the composer
+    harness.press_chat_room(&mut page, &chars("kkR")).await;
+    assert!(matches!(page.input_mode, InputMode::Insert));
+    harness.press_chat_room(&mut page, &chars("yes")).await;
+    assert_snapshot("chat_room_replying", &render_chat_room(&mut page, 60, 10));
+
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    assert_eq!(page.messages[2].reply_to.as_deref(), Some("1"));
+    assert!(page.replying.is_none());
+
+    // and inline in the message list
+    harness.press_chat_room(&mut page, &[KeyCode::Esc]).await;
+    page.state.select(None);
+    clear_times(&mut page);
+    assert_snapshot("chat_room_reply", &render_chat_room(&mut page, 60, 10));
+}
+
+#[tokio::test]
+async fn chat_room_thread() {
+    let harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "lunch?"));
+    page.push_message(message("2", BOB, "unrelated"));
+    page.push_message(reply("3", BOB, "yes", "1"));
+    page.push_message(reply("4", ME, "me too", "3"));
+
+    // the thread of the first message has all of its replies
+    page.state.select(Some(0));
+    harness.press_chat_room(&mut page, &chars("t")).await;
+    assert_snapshot("chat_room_thread", &render_chat_room(&mut page, 60, 12));
+
+    // from the last reply, the thread follows the parents up
+    page.state.select(Some(2));
+    harness.press_chat_room(&mut page, &chars("t")).await;
+    harness.press_chat_room(&mut page, &chars("p")).await;
+    assert_eq!(page.thread.as_deref(), Some("3"));
+    assert_eq!(page.selected().unwrap().id, "3");
+    harness.press_chat_room(&mut page, &chars("p")).await;
+    assert_eq!(page.thread.as_deref(), Some("1"));
+    assert_eq!(page.selected().unwrap().id, "1");
+
+    // leaving the thread shows the whole room again
+    harness.press_chat_room(&mut page, &chars("q")).await;
+    assert!(page.thread.is_none());
+    assert!(page.selected().is_none());
+
+    // outside of a thread it only moves the selection
+    page.state.select(Some(3));
+    harness.press_chat_room(&mut page, &chars("p")).await;
+    assert_eq!(page.selected().unwrap().id, "3");
+}
+
+#[tokio::test]
+async fn chat_room_typing() {
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "lunch?"));
+    page.push_typing(BOB.to_string(), Instant::now());
+    assert_snapshot("chat_room_typing", &render_chat_room(&mut page, 60, 10));
+
+    page.push_typing(ALICE.to_string(), Instant::now());
+    assert!(render_chat_room(&mut page, 60, 10).contains("alice01 and bob0002 are typing…"));
+
+    // sending the message means they stopped typing
+    page.push_message(message("2", ALICE, "anyone?"));
+    assert!(render_chat_room(&mut page, 60, 10).contains("bob0002 is typing…"));
+
+    // and it expires after a while
+    let long_ago = Instant::now() - TYPING_TIMEOUT;
+    page.push_typing(BOB.to_string(), long_ago);
+    assert!(!render_chat_room(&mut page, 60, 10).contains("typing"));
+}
+
+#[tokio::test]
+async fn chat_room_typing_is_rate_limited() {
+    let harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+
+    // nothing is typed yet
+    harness.press_chat_room(&mut page, &chars("i")).await;
+    assert!(page.typing_sent.is_none());
+
+    harness.press_chat_room(&mut page, &chars("h")).await;
+    let sent = page.typing_sent.expect("typing to be sent");
+    harness.press_chat_room(&mut page, &chars("ey")).await;
+    assert_eq!(page.typing_sent, Some(sent));
+}
+
+#[tokio::test]
+async fn chat_room_delivery_status() {
+    let harness = Harness::new();
+    let me = harness.node.peer_id().to_string();
+    let mut page = ChatRoom::new("0".to_string(), me.clone());
+
+    harness.press_chat_room(&mut page, &chars("i")).await;
+    for body in ["first", "second", "third", "fourth"] {
+        harness.press_chat_room(&mut page, &chars(body)).await;
+        harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    }
+    harness.press_chat_room(&mut page, &[KeyCode::Esc]).await;
+    let ids: Vec<String> = page.messages.iter().map(|m| m.id.clone()).collect();
+
+    page.push_delivery(ids[1].clone(), DeliveryStatus::Published);
+    page.push_delivery(ids[2].clone(), DeliveryStatus::Published);
+    page.push_receipt(ALICE.to_string(), vec![ids[2].clone()]);
+    page.push_receipt(BOB.to_string(), ids[1..3].to_vec());
+    page.push_delivery(
+        ids[3].clone(),
+        DeliveryStatus::Failed {
+            reason: "InsufficientPeers".to_string(),
+        },
+    );
+    clear_times(&mut page);
+    assert_snapshot(
+        "chat_room_delivery_status",
+        &render_chat_room(&mut page, 60, 10),
+    );
+
+    // only failed messages are sent again
+    harness.press_chat_room(&mut page, &chars("kks")).await;
+    assert_eq!(page.delivery[&ids[2]], DeliveryStatus::Published);
+    harness.press_chat_room(&mut page, &chars("js")).await;
+    assert_eq!(page.delivery[&ids[3]], DeliveryStatus::Pending);
+    assert_eq!(page.messages.len(), 4);
+
+    page.push_delivery(ids[3].clone(), DeliveryStatus::Queued);
+    assert!(render_chat_room(&mut page, 60, 10).contains("you: fourth (queued)"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn queued_messages_are_shown_after_a_restart() {
+    let dir = temp_dir();
+    let node = TestNode::spawn_with(ChatNode::builder().data_dir(dir.clone())).await;
+    node.node.join("0").await.unwrap();
+    node.node.send("0", "anyone?").await.unwrap();
+    node.quit().await;
+
+    // the room is opened again from the history on disk
+    let node = TestNode::spawn_with(ChatNode::builder().data_dir(dir.clone())).await;
+    let mut page = ChatRoom::open(&node.node, "0".to_string()).await.unwrap();
+    let screen = render_chat_room(&mut page, 60, 10);
+    assert!(screen.contains("you: anyone? (queued)"), "{screen}");
+
+    node.quit().await;
+    fs::remove_dir_all(dir).unwrap();
+}
+
+#[tokio::test]
+async fn chat_room_presence() {
+    let mut harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "lunch?"));
+    page.push_message(message("2", BOB, "in a meeting"));
+    page.push_message(message("3", ME, "later"));
+    page.push_presence(presence(ALICE, PresenceState::Online, ""));
+    page.push_presence(presence(BOB, PresenceState::DoNotDisturb, "focus"));
+    page.push_presence(presence(ME, PresenceState::Away, ""));
+    assert_snapshot("chat_room_presence", &render_chat_room(&mut page, 60, 10));
+
+    page.remove_presence(BOB);
+    assert!(render_chat_room(&mut page, 60, 10).contains("│bob0002: in a meeting"));
+
+    // commands change our presence instead of being sent
+    harness
+        .press_chat_room(&mut page, &chars("i/dnd deep work"))
+        .await;
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    assert!(page.input.is_empty());
+    assert_eq!(page.messages.len(), 3);
+    assert!(matches!(
+        harness.rx.try_recv(),
+        Ok(AppMessage::SetPresence {
+            state: PresenceState::DoNotDisturb,
+            status,
+        }) if status == "deep work"
+    ));
+}
+
+#[tokio::test]
+async fn topic_list_peers() {
+    let mut page = TopicList::new();
+    page.push_presence(presence(BOB, PresenceState::Away, "brb"));
+    page.push_presence(presence(ALICE, PresenceState::Online, ""));
+    assert_snapshot("topic_list_peers", &render_topic_list(&mut page, 60, 10));
+}
+
+#[tokio::test]
+async fn chat_room_quit_goes_back_to_topic_list() {
+    let mut harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+
+    harness
+        .press_chat_room(&mut page, &[KeyCode::Char('q')])
+        .await;
+
+    assert!(matches!(
+        harness.rx.try_recv(),
+        Ok(AppMessage::ChangePage {
+            page: Page::TopicList(_)
+        })
+    ));
+}
+
+#[tokio::test]
+async fn notification_takes_the_last_line() {
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    let mut terminal = Terminal::new(TestBackend::new(60, 10)).unwrap();
+    let style = AppStyle::new();
+    terminal
+        .draw(|f| {
+            let area = draw_notification(f, Some("could not dial /ip4/127.0.0.1"), &style);
+            page.draw(f, area, &style, &Keymap::default())
+        })
+        .unwrap();
+
+    assert_snapshot(
+        "notification",
+        &buffer_view(terminal.backend().buffer(), None),
+    );
+}
+
+#[tokio::test]
+async fn search_command_opens_search() {
+    let mut harness = Harness::new();
+    let mut page = ChatRoom::new("rust".to_string(), ME.to_string());
+
+    harness
+        .press_chat_room(&mut page, &[KeyCode::Char('i')])
+        .await;
+    harness
+        .press_chat_room(&mut page, &chars("/search room:rust tokio"))
+        .await;
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    assert!(page.input.is_empty());
+    assert!(page.messages.is_empty());
+
+    match harness.rx.try_recv() {
+        Ok(AppMessage::ChangePage {
+            page: Page::Search(search),
+        }) => assert_eq!(search.input, "room:rust tokio"),
+        other => panic!("expected the search page, got {other:?}"),
+    }
+    match harness.rx.try_recv() {
+        Ok(AppMessage::Search { query }) => {
+            assert_eq!(query.room.as_deref(), Some("rust"));
+            assert_eq!(query.text, "tokio");
+        }
+        other => panic!("expected a search, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn search_results_open_their_message() {
+    let mut harness = Harness::new();
+    let mut page = Search::new(ME.to_string(), String::new());
+
+    harness
+        .press_search(&mut page, &chars("from:alice01 before:2024-13-01"))
+        .await;
+    harness.press_search(&mut page, &[KeyCode::Enter]).await;
+    assert!(page.editing);
+    assert!(matches!(
+        harness.rx.try_recv(),
+        Ok(AppMessage::Error { .. })
+    ));
+
+    for _ in 0.."before:2024-13-01".len() {
+        harness.press_search(&mut page, &[KeyCode::Backspace]).await;
+    }
+    harness.press_search(&mut page, &chars("tokio")).await;
+    assert_snapshot("search_editing", &render_search(&mut page, 60, 10));
+    harness.press_search(&mut page, &[KeyCode::Enter]).await;
+    assert!(!page.editing);
+    match harness.rx.try_recv() {
+        Ok(AppMessage::Search { query }) => assert_eq!(
+            query,
+            SearchQuery {
+                text: "tokio".to_string(),
+                author: Some("alice01".to_string()),
+                ..SearchQuery::default()
+            }
+        ),
+        other => panic!("expected a search, got {other:?}"),
+    }
+
+    page.push_hits(vec![
+        SearchHit {
+            room: "rust".to_string(),
+            message: message("2", ALICE, "tokio is great"),
+            previous: Some(message("1", ME, "which runtime?")),
+            next: None,
+        },
+        SearchHit {
+            room: "async".to_string(),
+            message: message("7", ALICE, "tokio::select!"),
+            previous: None,
+            next: Some(message("8", BOB, "thanks")),
+        },
+    ]);
+    harness.press_search(&mut page, &[KeyCode::Char('j')]).await;
+    assert_snapshot("search_results", &render_search(&mut page, 60, 14));
+
+    harness.press_search(&mut page, &[KeyCode::Enter]).await;
+    match harness.rx.try_recv() {
+        Ok(AppMessage::OpenMessage { room, message_id }) => {
+            assert_eq!((room.as_str(), message_id.as_str()), ("async", "7"));
+        }
+        other => panic!("expected to open the message, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn loaded_history_comes_before_new_messages() {
+    let mut page = ChatRoom::new("rust".to_string(), ME.to_string());
+    page.push_message(message("3", BOB, "new"));
+    page.load(RoomHistory {
+        messages: vec![message("1", ALICE, "old"), message("3", BOB, "new")],
+        reactions: vec![reaction("1", BOB, "👍")],
+        ..RoomHistory::default()
+    });
+
+    let ids: Vec<&str> = page.messages.iter().map(|m| m.id.as_str()).collect();
+    assert_eq!(ids, ["1", "3"]);
+    assert_eq!(page.reactions.summary("1").len(), 1);
+
+    assert!(page.select_message("1"));
+    assert_eq!(page.selected().map(|m| m.body.as_str()), Some("old"));
+    assert!(!page.select_message("9"));
+}
+
+#[tokio::test]
+async fn theme_command_sets_the_theme() {
+    let mut harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+
+    harness
+        .press_chat_room(&mut page, &[KeyCode::Char('i')])
+        .await;
+    harness
+        .press_chat_room(&mut page, &chars("/theme solarized"))
+        .await;
+    harness.press_chat_room(&mut page, &[KeyCode::Enter]).await;
+    assert!(page.input.is_empty());
+    assert!(page.messages.is_empty());
+    assert!(matches!(
+        harness.rx.try_recv(),
+        Ok(AppMessage::SetTheme { name }) if name == "solarized"
+    ));
+}
+
+#[tokio::test]
+async fn themes_color_authors_and_mentions() {
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.mentions.push("gabriel".to_string());
+    page.push_message(message("1", ALICE, "hi gabriel"));
+    page.push_message(message("2", BOB, "hi all"));
+
+    for name in ["dark", "light", "high-contrast", "solarized"] {
+        let style = AppStyle::named(name).unwrap();
+        let mut terminal = Terminal::new(TestBackend::new(60, 10)).unwrap();
+        terminal
+            .draw(|f| page.draw(f, f.size(), &style, &Keymap::default()))
+            .unwrap();
+        let buffer = terminal.backend().buffer();
+
+        // the first line of the messages is under the help line and the border
+        let alice = buffer.get(1, 2);
+        assert_eq!(alice.symbol, "a");
+        assert_eq!(Some(alice.fg), style.author_style(ALICE).fg, "{name}");
+        let mention = buffer.get(10, 2);
+        assert_eq!(mention.symbol, "h");
+        assert_eq!(Some(mention.fg), style.mention_style.fg, "{name}");
+        let plain = buffer.get(10, 3);
+        assert_eq!(plain.symbol, "h");
+        assert_eq!(Some(plain.fg), style.base_style.fg, "{name}");
+        assert_eq!(Some(buffer.get(0, 1).fg), style.border_style.fg, "{name}");
+    }
+}
+
+/// A key with modifiers, as the terminal sends it.
+fn chord(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+    KeyEvent::new(code, modifiers)
+}
+
+#[tokio::test]
+async fn emacs_keymap_drives_the_chat_room() {
+    let mut harness = Harness::new();
+    harness.keymap = Keymap::emacs();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "hello"));
+    page.push_message(message("2", BOB, "hi"));
+
+    // the vim keys do nothing, and plain keys are not actions
+    harness.press_chat_room(&mut page, &chars("kir")).await;
+    assert_eq!(page.state.selected(), None);
+    assert!(matches!(page.input_mode, InputMode::Normal));
+
+    let ctrl = KeyModifiers::CONTROL;
+    harness
+        .press_chat_room(&mut page, &[chord(KeyCode::Char('p'), ctrl)])
+        .await;
+    assert_eq!(page.selected().unwrap().id, "2");
+    harness
+        .press_chat_room(&mut page, &[chord(KeyCode::Char('p'), ctrl)])
+        .await;
+    assert_eq!(page.selected().unwrap().id, "1");
+    // alt and shift make `alt-R`, the reply
+    harness
+        .press_chat_room(
+            &mut page,
+            &[chord(
+                KeyCode::Char('r'),
+                KeyModifiers::ALT | KeyModifiers::SHIFT,
+            )],
+        )
+        .await;
+    assert_eq!(page.replying.as_deref(), Some("1"));
+    assert!(matches!(page.input_mode, InputMode::Insert));
+
+    // in insert mode the chords are not typed, and ctrl-g leaves it
+    harness.press_chat_room(&mut page, &chars("ok")).await;
+    harness
+        .press_chat_room(&mut page, &[chord(KeyCode::Char('g'), ctrl)])
+        .await;
+    assert_eq!(page.input, "ok");
+    assert!(matches!(page.input_mode, InputMode::Normal));
+
+    let render = |page: &mut ChatRoom, keymap: &Keymap| {
+        let mut terminal = Terminal::new(TestBackend::new(60, 10)).unwrap();
+        terminal
+            .draw(|f| page.draw(f, f.size(), &AppStyle::new(), keymap))
+            .unwrap();
+        buffer_view(terminal.backend().buffer(), None)
+    };
+    page.push_delivery(
+        "2".to_string(),
+        DeliveryStatus::Failed {
+            reason: "InsufficientPeers".to_string(),
+        },
+    );
+    let screen = render(&mut page, &harness.keymap);
+    assert!(screen.contains("Press ctrl-g to exit, alt-i to enter insert mode"));
+    assert!(screen.contains("failed, press alt-s to retry"));
+
+    // the help of insert mode has the keys that work while typing
+    harness
+        .press_chat_room(&mut page, &[chord(KeyCode::Char('i'), KeyModifiers::ALT)])
+        .await;
+    let screen = render(&mut page, &harness.keymap);
+    assert!(screen.contains("Press ctrl-g to enter normal mode, Enter to send"));
+}
+
+#[tokio::test]
+async fn config_bindings_change_the_preset() {
+    let keys: KeysConfig = toml::from_str(
+        r#"
+        preset = "vim"
+        [bindings]
+        "ctrl-d" = "down"
+        "j" = "none"
+        "x" = "delete"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(keys.preset, Preset::Vim);
+    let mut harness = Harness::new();
+    harness.keymap = Keymap::new(&keys);
+    let mut page = TopicList::new();
+
+    harness.press_topic_list(&mut page, &chars("j")).await;
+    assert_eq!(page.state.selected(), Some(0));
+    harness
+        .press_topic_list(
+            &mut page,
+            &[chord(KeyCode::Char('d'), KeyModifiers::CONTROL)],
+        )
+        .await;
+    assert_eq!(page.state.selected(), Some(1));
+    harness.press_topic_list(&mut page, &[KeyCode::Down]).await;
+    assert_eq!(page.state.selected(), Some(2));
+}
+
+#[tokio::test]
+async fn help_lists_the_keys_of_the_keymap() {
+    let mut terminal = Terminal::new(TestBackend::new(70, 24)).unwrap();
+    let (style, keymap) = (AppStyle::new(), Keymap::default());
+    let mut page = TopicList::new();
+    terminal
+        .draw(|f| {
+            page.draw(f, f.size(), &style);
+            draw_help(f, f.size(), &keymap, &style);
+        })
+        .unwrap();
+    assert_snapshot("help", &buffer_view(terminal.backend().buffer(), None));
+}
+
+#[tokio::test]
+async fn status_bar_follows_the_network() {
+    let mut bar = StatusBar::new(ME.to_string(), Some("gabriel".to_string()));
+    let now = Instant::now();
+    assert_eq!(bar.connectivity(now), Connectivity::Connecting);
+    assert_eq!(
+        bar.connectivity(now + CONNECT_GRACE),
+        Connectivity::Isolated
+    );
+
+    for event in [
+        NetworkEvent::Listening {
+            address: format!("/ip4/192.168.1.5/tcp/4001/p2p/{ME}"),
+        },
+        NetworkEvent::Listening {
+            address: format!("/ip4/127.0.0.1/tcp/4001/p2p/{ME}"),
+        },
+        NetworkEvent::PeerConnected {
+            peer_id: ALICE.to_string(),
+        },
+        NetworkEvent::PeerConnected {
+            peer_id: BOB.to_string(),
+        },
+        NetworkEvent::MeshChanged {
+            topic: "0".to_string(),
+            peers: 1,
+        },
+        NetworkEvent::NatChanged {
+            nat: NatStatus::Private,
+        },
+    ] {
+        assert!(bar.apply(&event));
+    }
+    assert_eq!(bar.connectivity(now), Connectivity::Online);
+
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "hello"));
+    let mut terminal = Terminal::new(TestBackend::new(80, 8)).unwrap();
+    let (style, keymap) = (AppStyle::new(), Keymap::default());
+    terminal
+        .draw(|f| {
+            let area = bar.draw(f, f.size(), Some(&page.name), &style);
+            page.draw(f, area, &style, &keymap)
+        })
+        .unwrap();
+    assert_snapshot(
+        "status_bar",
+        &buffer_view(terminal.backend().buffer(), None),
+    );
+
+    // every peer left
+    for peer_id in [ALICE, BOB] {
+        bar.apply(&NetworkEvent::PeerDisconnected {
+            peer_id: peer_id.to_string(),
+        });
+    }
+    assert_eq!(bar.connectivity(now), Connectivity::Isolated);
+}
+
+#[tokio::test]
+async fn console_filters_the_log() {
+    let log = LogBuffer::new();
+    for (second, level, target, message) in [
+        (
+            1,
+            Level::Info,
+            "p2p_chat::network",
+            "listening on /ip4/127.0.0.1/tcp/4001",
+        ),
+        (2, Level::Debug, "libp2p_gossipsub", "joined the mesh of 0"),
+        (3, Level::Warn, "p2p_chat::network", "no peers in room 0"),
+        (
+            4,
+            Level::Error,
+            "p2p_chat::storage",
+            "could not save the history",
+        ),
+    ] {
+        log.push(LogRecord {
+            timestamp: second * 1000,
+            level,
+            target: target.to_string(),
+            message: message.to_string(),
+        });
+    }
+    let keymap = Keymap::default();
+    let mut console = Console::new(log);
+    let press = |console: &mut Console, keys: &[KeyCode]| {
+        for k in keys {
+            console.keybindings((*k).into(), &keymap);
+        }
+    };
+
+    console.open = true;
+    let mut page = TopicList::new();
+    let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+    let style = AppStyle::new();
+    terminal
+        .draw(|f| {
+            let area = console.draw(f, f.size(), &style, &keymap);
+            page.draw(f, area, &style)
+        })
+        .unwrap();
+    assert_snapshot("console", &buffer_view(terminal.backend().buffer(), None));
+
+    // down to warnings, then only the network
+    press(
+        &mut console,
+        &[KeyCode::Char('h'), KeyCode::Char('h'), KeyCode::Char('h')],
+    );
+    assert_eq!(console.level, LevelFilter::Warn);
+    assert_eq!(console.records().len(), 2);
+    press(&mut console, &[KeyCode::Char('/')]);
+    press(&mut console, &chars("network"));
+    press(&mut console, &[KeyCode::Enter]);
+    let records = console.records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].message, "no peers in room 0");
+
+    // typing doesn't close it, the key of the console does
+    press(
+        &mut console,
+        &[KeyCode::Char('/'), KeyCode::Char('`'), KeyCode::Esc],
+    );
+    assert!(console.open);
+    assert_eq!(console.search, "network`");
+    press(&mut console, &[KeyCode::Char('`')]);
+    assert!(!console.open);
+}
+
+fn mouse(kind: MouseEventKind, column: u16, row: u16) -> MouseEvent {
+    MouseEvent {
+        kind,
+        column,
+        row,
+        modifiers: KeyModifiers::NONE,
+    }
+}
+
+fn click(column: u16, row: u16) -> MouseEvent {
+    mouse(MouseEventKind::Down(MouseButton::Left), column, row)
+}
+
+#[tokio::test]
+async fn mouse_selects_rooms_and_scrolls_messages() {
+    let mut harness = Harness::new();
+    let (tx, node) = (&harness.tx, &harness.node);
+
+    // the rooms start on the 4th line, under the header
+    let mut rooms = TopicList::new();
+    render_topic_list(&mut rooms, 60, 10);
+    rooms.mouse(click(10, 5), tx, node).await;
+    assert_eq!(rooms.state.selected(), Some(2));
+    rooms
+        .mouse(mouse(MouseEventKind::ScrollDown, 10, 5), tx, node)
+        .await;
+    assert_eq!(rooms.state.selected(), Some(3));
+    // the border is not a room
+    rooms.mouse(click(10, 9), tx, node).await;
+    assert_eq!(rooms.state.selected(), Some(3));
+    rooms.mouse(click(10, 4), tx, node).await;
+    assert!(harness.rx.try_recv().is_err());
+    // a click on the selected room opens it
+    rooms.mouse(click(10, 4), tx, node).await;
+    match harness.rx.try_recv() {
+        Ok(AppMessage::OpenRoom { room }) => assert_eq!(room, "1"),
+        other => panic!("expected to open a chat room, got {other:?}"),
+    }
+
+    let (tx, node) = (&harness.tx, &harness.node);
+    let mut page = ChatRoom::new("0".to_string(), node.peer_id().to_string());
+    for (id, body) in [("1", "ship it?"), ("2", "yes"), ("3", "done")] {
+        page.push_message(message(id, ALICE, body));
+    }
+    render_chat_room(&mut page, 60, 10);
+    let scroll_up = mouse(MouseEventKind::ScrollUp, 10, 3);
+    let scroll_down = mouse(MouseEventKind::ScrollDown, 10, 3);
+    page.mouse(scroll_up, tx, node).await;
+    page.mouse(scroll_up, tx, node).await;
+    assert_eq!(page.state.selected(), Some(1));
+    page.mouse(scroll_down, tx, node).await;
+    page.mouse(scroll_down, tx, node).await;
+    assert_eq!(page.state.selected(), None);
+
+    // the input is on the last 3 lines
+    page.mouse(click(5, 8), tx, node).await;
+    assert!(matches!(page.input_mode, InputMode::Insert));
+    page.mouse(click(5, 3), tx, node).await;
+    assert!(matches!(page.input_mode, InputMode::Normal));
+
+    // "React: 1 👍 2 😂", the second emoji
+    page.mouse(scroll_up, tx, node).await;
+    harness.press_chat_room(&mut page, &chars("r")).await;
+    render_chat_room(&mut page, 60, 10);
+    page.mouse(click(14, 0), &harness.tx, &harness.node).await;
+    assert!(matches!(page.input_mode, InputMode::Normal));
+    assert_eq!(
+        page.reactions.summary("3"),
+        [(
+            REACTIONS[1],
+            vec![harness.node.peer_id().to_string().as_str()]
+        )]
+    );
+}
+
+#[tokio::test]
+async fn long_input_wraps_with_the_cursor() {
+    let harness = Harness::new();
+    let mut page = ChatRoom::new("0".to_string(), ME.to_string());
+    page.push_message(message("1", ALICE, "how was the trip to the mountains?"));
+    harness.press_chat_room(&mut page, &chars("i")).await;
+    harness
+        .press_chat_room(&mut page, &chars("great, we walked for hours 🏔 and saw"))
+        .await;
+    assert_snapshot("long_input", &render_chat_room(&mut page, 30, 12));
+
+    let line = Spans::from(vec![
+        Span::raw("alice01: "),
+        Span::raw("the quick brown fox"),
+    ]);
+    let lines: Vec<String> = wrap(line, 12)
+        .iter()
+        .map(|l| l.0.iter().map(|s| s.content.as_ref()).collect())
+        .collect();
+    assert_eq!(lines, ["alice01: the", "quick brown", "fox"]);
+    // a word longer than the line is cut
+    let lines = wrap(Spans::from("abcdefgh"), 3);
+    assert_eq!(lines.len(), 3);
+}
+
+#[tokio::test]
+async fn small_terminals() {
+    let mut page = Page::TopicList(TopicList::new());
+    if let Page::TopicList(rooms) = &mut page {
+        rooms.push_presence(presence(ALICE, PresenceState::Online, ""));
+    }
+    let mut bar = StatusBar::new(ME.to_string(), None);
+    bar.apply(&NetworkEvent::Listening {
+        address: "/ip4/192.168.1.5/tcp/4001".to_string(),
+    });
+    let mut console = Console::new(LogBuffer::new());
+    let (style, keymap) = (AppStyle::new(), Keymap::default());
+    let mut render = |width, height| {
+        let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+        terminal
+            .draw(|f| {
+                let panes = Panes {
+                    status_bar: &bar,
+                    console: &mut console,
+                    notification: None,
+                    help: false,
+                };
+                ui::draw(f, &mut page, &style, &keymap, panes)
+            })
+            .unwrap();
+        buffer_view(terminal.backend().buffer(), None)
+    };
+
+    // no peers next to the rooms, and no addresses in the status bar
+    let compact = render(40, 10);
+    assert!(!compact.contains("Peers"));
+    assert!(!compact.contains("192.168.1.5"));
+    assert!(render(80, 10).contains("Peers"));
+    assert_snapshot("compact", &compact);
+    assert_snapshot("too_small", &render(28, 6));
+}